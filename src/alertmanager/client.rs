@@ -0,0 +1,235 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::str::FromStr;
+
+use http::header::CONTENT_TYPE;
+use http::Uri;
+use hyper::{Body, Client, Request};
+use serde::Serialize;
+use serde_json;
+use url::Url;
+
+use crate::alertmanager::messages::{
+    AlertGroup, AlertmanagerStatus, GettableAlert, GettableSilence, PostableAlert,
+    PostableSilence, SilenceCreated,
+};
+use crate::messages::AlertManagers;
+use crate::transport::{self, HyperHttpsConnector};
+use crate::Result;
+
+/// Paths Prometheus appends to an Alertmanager URL when it reports
+/// it via `/api/v1/alertmanagers`.
+const ALERTS_API_SUFFIXES: &[&str] = &["api/v1/alerts", "api/v2/alerts"];
+
+pub struct AlertmanagerClient<T: hyper::client::connect::Connect + 'static> {
+    client: Client<T, Body>,
+    host: Url,
+}
+
+impl AlertmanagerClient<HyperHttpsConnector> {
+    pub fn new_https(host: &str) -> Result<AlertmanagerClient<HyperHttpsConnector>> {
+        let host = base_url(transport::parse_host(host)?);
+        Ok(AlertmanagerClient {
+            client: transport::https_client(),
+            host,
+        })
+    }
+
+    /// Build one client for each _active_ Alertmanager in an `alert_managers()` response.
+    /// All returned clients share a single connection pool.
+    pub fn from_alert_managers(
+        alert_managers: &AlertManagers,
+    ) -> Vec<AlertmanagerClient<HyperHttpsConnector>> {
+        let client = transport::https_client();
+        alert_managers
+            .active
+            .iter()
+            .map(|am| AlertmanagerClient {
+                client: client.clone(),
+                host: base_url(am.url.clone()),
+            })
+            .collect()
+    }
+}
+
+impl<T: hyper::client::connect::Connect + 'static> AlertmanagerClient<T> {
+    pub fn host(&self) -> &Url {
+        &self.host
+    }
+
+    pub async fn silences(&mut self, filters: Vec<String>) -> Result<Vec<GettableSilence>> {
+        let mut u = self.api_call_base_url("api/v2/silences");
+        for f in filters {
+            u.query_pairs_mut().append_pair("filter", &f);
+        }
+        let u = Uri::from_str(u.as_str())?;
+
+        await!(transport::send_json(&self.client, get(u)))
+    }
+
+    /// Create a silence, or update one if `silence.id` is set.
+    /// Returns the id of the created silence.
+    pub async fn create_silence(&mut self, silence: PostableSilence) -> Result<String> {
+        let u = self.api_call_base_url("api/v2/silences");
+        let u = Uri::from_str(u.as_str())?;
+        let post = post_json(u, &silence)?;
+
+        let created: SilenceCreated = await!(transport::send_json(&self.client, post))?;
+        Ok(created.silence_id)
+    }
+
+    pub async fn expire_silence(&mut self, id: String) -> Result<()> {
+        let u = self.silence_url(&id);
+        let u = Uri::from_str(u.as_str())?;
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let delete = Request::delete(u)
+            .body(Body::empty())
+            .expect("Failed to construct 'expire_silence' DELETE with empty body");
+
+        await!(transport::send(&self.client, delete))?;
+        Ok(())
+    }
+
+    pub async fn alerts(&mut self, filters: Vec<String>) -> Result<Vec<GettableAlert>> {
+        let mut u = self.api_call_base_url("api/v2/alerts");
+        for f in filters {
+            u.query_pairs_mut().append_pair("filter", &f);
+        }
+        let u = Uri::from_str(u.as_str())?;
+
+        await!(transport::send_json(&self.client, get(u)))
+    }
+
+    pub async fn alert_groups(&mut self, filters: Vec<String>) -> Result<Vec<AlertGroup>> {
+        let mut u = self.api_call_base_url("api/v2/alerts/groups");
+        for f in filters {
+            u.query_pairs_mut().append_pair("filter", &f);
+        }
+        let u = Uri::from_str(u.as_str())?;
+
+        await!(transport::send_json(&self.client, get(u)))
+    }
+
+    pub async fn status(&mut self) -> Result<AlertmanagerStatus> {
+        let u = self.api_call_base_url("api/v2/status");
+        let u = Uri::from_str(u.as_str())?;
+
+        await!(transport::send_json(&self.client, get(u)))
+    }
+
+    /// Post alerts directly to Alertmanager, bypassing Prometheus.
+    /// Useful for testing routing and receiver configuration.
+    pub async fn post_alerts(&mut self, alerts: Vec<PostableAlert>) -> Result<()> {
+        let u = self.api_call_base_url("api/v2/alerts");
+        let u = Uri::from_str(u.as_str())?;
+        let post = post_json(u, &alerts)?;
+
+        await!(transport::send(&self.client, post))?;
+        Ok(())
+    }
+
+    fn api_call_base_url(&self, api_path: &str) -> Url {
+        // Explicitly unwrapping here because we should be able
+        // to join an already-verified Alertmanager URL with
+        // precanned valid path fragments
+        self.host
+            .join(api_path)
+            .expect(&format!("Cannot create API url with path '{}'", api_path))
+    }
+
+    fn silence_url(&self, id: &str) -> Url {
+        let mut u = self.api_call_base_url("api/v2/silence");
+        // Explicitly unwrapping here because the URL was just joined
+        // onto the Alertmanager URL, so it can be a base
+        u.path_segments_mut()
+            .expect("Alertmanager URL cannot be a base")
+            .push(id);
+        u
+    }
+}
+
+/// Reduce an Alertmanager URL to the directory under which its API lives.
+///
+/// Alertmanagers are often served under a route prefix, so unlike
+/// `PromClient` API paths are joined relative to this URL, not its root.
+fn base_url(mut url: Url) -> Url {
+    let path = url.path().to_owned();
+    let path = ALERTS_API_SUFFIXES
+        .iter()
+        .find(|suffix| path.ends_with(*suffix))
+        .map_or(path.as_str(), |suffix| &path[..path.len() - suffix.len()]);
+    let path = if path.ends_with('/') {
+        path.to_owned()
+    } else {
+        format!("{}/", path)
+    };
+    url.set_path(&path);
+    url.set_query(None);
+    url
+}
+
+fn get(u: Uri) -> Request<Body> {
+    // Explicitly unwrapping here because this shouldn't fail,
+    // and there's nothing a user can do if it does. this failure
+    // is because of a library bug, not because of their input
+    Request::get(u)
+        .body(Body::empty())
+        .expect("Failed to construct GET with empty body")
+}
+
+fn post_json<B: Serialize>(u: Uri, body: &B) -> Result<Request<Body>> {
+    let body = serde_json::to_vec(body)?;
+    // Explicitly unwrapping here because this shouldn't fail,
+    // and there's nothing a user can do if it does. this failure
+    // is because of a library bug, not because of their input
+    Ok(Request::post(u)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("Failed to construct POST with JSON body"))
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{base_url, AlertmanagerClient};
+
+    #[test]
+    fn should_strip_alerts_api_path_from_discovered_url() {
+        let u = Url::parse("http://127.0.0.1:9093/api/v1/alerts").unwrap();
+        assert_eq!("http://127.0.0.1:9093/", base_url(u).as_str());
+
+        let u = Url::parse("http://am.example.com/alertmanager/api/v2/alerts").unwrap();
+        assert_eq!("http://am.example.com/alertmanager/", base_url(u).as_str());
+    }
+
+    #[test]
+    fn should_add_trailing_slash_to_route_prefix() {
+        let u = Url::parse("http://am.example.com/alertmanager").unwrap();
+        assert_eq!("http://am.example.com/alertmanager/", base_url(u).as_str());
+    }
+
+    #[test]
+    fn should_encode_silence_id_as_one_path_segment() {
+        let am = AlertmanagerClient::new_https("http://am.example.com/alertmanager").unwrap();
+        assert_eq!(
+            "http://am.example.com/alertmanager/api/v2/silence/a%2Fb%3Fc",
+            am.silence_url("a/b?c").as_str()
+        );
+    }
+}
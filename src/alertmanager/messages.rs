@@ -0,0 +1,325 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request and response types for the [Alertmanager V2 API](https://github.com/prometheus/alertmanager/blob/master/api/v2/openapi.yaml).
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Matcher {
+    pub name: String,
+    pub value: String,
+    pub is_regex: bool,
+    // absent on older Alertmanagers, which only support positive matchers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_equal: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostableSilence {
+    /// Set to update an existing silence instead of creating a new one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub matchers: Vec<Matcher>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_by: String,
+    pub comment: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GettableSilence {
+    pub id: String,
+    pub status: SilenceStatus,
+    pub updated_at: DateTime<Utc>,
+    pub matchers: Vec<Matcher>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_by: String,
+    pub comment: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SilenceStatus {
+    pub state: SilenceState,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SilenceState {
+    Expired,
+    Active,
+    Pending,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SilenceCreated {
+    #[serde(rename = "silenceID")]
+    pub silence_id: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostableAlert {
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        rename = "generatorURL",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub generator_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GettableAlert {
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default, rename = "generatorURL")]
+    pub generator_url: Option<String>,
+    pub fingerprint: String,
+    #[serde(default)]
+    pub receivers: Vec<Receiver>,
+    pub status: AlertStatus,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Receiver {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertStatus {
+    pub state: AlertState,
+    #[serde(default)]
+    pub silenced_by: Vec<String>,
+    #[serde(default)]
+    pub inhibited_by: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Unprocessed,
+    Active,
+    Suppressed,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AlertGroup {
+    pub labels: HashMap<String, String>,
+    pub receiver: Receiver,
+    pub alerts: Vec<GettableAlert>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertmanagerStatus {
+    pub cluster: ClusterStatus,
+    pub version_info: VersionInfo,
+    pub config: AlertmanagerConfig,
+    pub uptime: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClusterStatus {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub status: String,
+    #[serde(default)]
+    pub peers: Vec<PeerStatus>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PeerStatus {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub branch: String,
+    pub build_date: String,
+    pub build_user: String,
+    pub go_version: String,
+    pub revision: String,
+    pub version: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AlertmanagerConfig {
+    pub original: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::result::Result as StdResult;
+
+    use chrono::{DateTime, Utc};
+
+    use crate::alertmanager::messages::{
+        AlertGroup, AlertState, AlertStatus, GettableAlert, GettableSilence, Matcher,
+        PostableAlert, Receiver, SilenceState, SilenceStatus,
+    };
+
+    #[test]
+    fn should_deserialize_json_silences() -> StdResult<(), std::io::Error> {
+        let j = r#"
+        [
+            {
+                "id": "d6b0e8a4-4d4f-4c3c-9cb5-6c1f2a4e6a1b",
+                "status": { "state": "active" },
+                "updatedAt": "2019-04-01T10:00:00.000Z",
+                "matchers": [
+                    { "name": "job", "value": "node", "isRegex": false, "isEqual": true }
+                ],
+                "startsAt": "2019-04-01T10:00:00.000Z",
+                "endsAt": "2019-04-01T12:00:00.000Z",
+                "createdBy": "allen",
+                "comment": "maintenance"
+            }
+        ]
+        "#;
+
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+
+        let res = serde_json::from_str::<Vec<GettableSilence>>(j)?;
+        assert_eq!(
+            vec![GettableSilence {
+                id: "d6b0e8a4-4d4f-4c3c-9cb5-6c1f2a4e6a1b".to_owned(),
+                status: SilenceStatus {
+                    state: SilenceState::Active,
+                },
+                updated_at: at("2019-04-01T10:00:00Z"),
+                matchers: vec![Matcher {
+                    name: "job".to_owned(),
+                    value: "node".to_owned(),
+                    is_regex: false,
+                    is_equal: Some(true),
+                }],
+                starts_at: at("2019-04-01T10:00:00Z"),
+                ends_at: at("2019-04-01T12:00:00Z"),
+                created_by: "allen".to_owned(),
+                comment: "maintenance".to_owned(),
+            }],
+            res
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_deserialize_json_alert_groups() -> StdResult<(), std::io::Error> {
+        let j = r#"
+        [
+            {
+                "labels": { "alertname": "InstanceDown" },
+                "receiver": { "name": "pager" },
+                "alerts": [
+                    {
+                        "labels": { "alertname": "InstanceDown", "instance": "localhost:9100" },
+                        "annotations": {},
+                        "startsAt": "2019-04-01T10:00:00Z",
+                        "endsAt": "2019-04-01T10:05:00Z",
+                        "updatedAt": "2019-04-01T10:01:00Z",
+                        "generatorURL": "http://localhost:9090/graph",
+                        "fingerprint": "1a2b3c",
+                        "receivers": [ { "name": "pager" } ],
+                        "status": { "state": "active", "silencedBy": [], "inhibitedBy": [] }
+                    }
+                ]
+            }
+        ]
+        "#;
+
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+
+        let mut group_labels: HashMap<String, String> = HashMap::new();
+        group_labels.insert("alertname".to_owned(), "InstanceDown".to_owned());
+
+        let mut alert_labels = group_labels.clone();
+        alert_labels.insert("instance".to_owned(), "localhost:9100".to_owned());
+
+        let res = serde_json::from_str::<Vec<AlertGroup>>(j)?;
+        assert_eq!(
+            vec![AlertGroup {
+                labels: group_labels,
+                receiver: Receiver {
+                    name: "pager".to_owned()
+                },
+                alerts: vec![GettableAlert {
+                    labels: alert_labels,
+                    annotations: HashMap::new(),
+                    starts_at: at("2019-04-01T10:00:00Z"),
+                    ends_at: at("2019-04-01T10:05:00Z"),
+                    updated_at: at("2019-04-01T10:01:00Z"),
+                    generator_url: Some("http://localhost:9090/graph".to_owned()),
+                    fingerprint: "1a2b3c".to_owned(),
+                    receivers: vec![Receiver {
+                        name: "pager".to_owned()
+                    }],
+                    status: AlertStatus {
+                        state: AlertState::Active,
+                        silenced_by: Vec::new(),
+                        inhibited_by: Vec::new(),
+                    },
+                }],
+            }],
+            res
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_serialize_rust_postable_alert_without_optional_fields(
+    ) -> StdResult<(), std::io::Error> {
+        let mut labels: HashMap<String, String> = HashMap::new();
+        labels.insert("alertname".to_owned(), "TestAlert".to_owned());
+
+        let s = serde_json::to_value(&PostableAlert {
+            labels,
+            ..Default::default()
+        })?;
+
+        assert_eq!(
+            serde_json::json!({
+                "labels": { "alertname": "TestAlert" },
+                "annotations": {}
+            }),
+            s
+        );
+
+        Ok(())
+    }
+}
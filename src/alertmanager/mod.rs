@@ -0,0 +1,22 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Async interface to the [Alertmanager V2 API](https://github.com/prometheus/alertmanager/blob/master/api/v2/openapi.yaml).
+//! Use [AlertmanagerClient::from_alert_managers] to talk to the
+//! Alertmanagers a Prometheus server reports via `alert_managers()`.
+
+pub use self::client::AlertmanagerClient;
+
+mod client;
+pub mod messages;
@@ -17,14 +17,17 @@
 use std::borrow::ToOwned;
 use std::error::Error as StdError;
 use std::result::Result as StdResult;
-use std::time::Duration;
 
 use chrono::{DateTime, TimeZone, Utc};
 use clap::{App, AppSettings, Arg, SubCommand};
 use futures::{FutureExt, TryFutureExt};
 use tokio;
 
-use prometheus_query::{messages::ApiResult, PromClient};
+use prometheus_query::{
+    health::{TargetHealthReport, DEFAULT_STALE_FACTOR},
+    messages::ApiResult,
+    PromClient, PromDuration,
+};
 
 // XXX: remember: if you accidentally return the wrong value from an async function
 // the compiler compares everything to that _wrong_ return value as opposed to the type
@@ -77,6 +80,24 @@ fn main() -> Result<(), std::io::Error> {
             .boxed()
             .compat()
         });
+    } else if let Some(matches) = matches.subcommand_matches("health") {
+        let stale_factor = matches.value_of("stale-factor");
+        tokio::run({
+            target_health(
+                hostname,
+                stale_factor.map(ToOwned::to_owned),
+                query_timeout.map(ToOwned::to_owned),
+            )
+            .map(|r| {
+                match r {
+                    Ok(report) => print!("{}", report),
+                    Err(e) => println!("{:#?}", e),
+                }
+                Ok(())
+            })
+            .boxed()
+            .compat()
+        });
     }
 
     Ok(())
@@ -96,7 +117,7 @@ fn cli<'a, 'b>() -> App<'a, 'b> {
         )
         .arg(
             Arg::with_name("timeout")
-                .help("Query timeout")
+                .help("Query timeout, e.g. 30s or 1m30s")
                 .short("o")
                 .long("timeout")
                 .takes_value(true),
@@ -145,6 +166,17 @@ fn cli<'a, 'b>() -> App<'a, 'b> {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("health")
+                .about("Target health report")
+                .arg(
+                    Arg::with_name("stale-factor")
+                        .help("Scrape intervals after which a target is considered stale")
+                        .short("f")
+                        .long("stale-factor")
+                        .takes_value(true),
+                ),
+        )
 }
 
 async fn instant_query(
@@ -154,12 +186,9 @@ async fn instant_query(
     query_timeout: Option<String>,
 ) -> StdResult<ApiResult, Box<StdError + 'static>> {
     let at = date_time(at)?;
-    let query_timeout = if let Some(v) = query_timeout {
-        let v = v.parse::<u64>()?;
-        Some(Duration::new(v, 0))
-    } else {
-        None
-    };
+    let query_timeout = query_timeout
+        .map(|v| v.parse::<PromDuration>())
+        .transpose()?;
 
     let mut p = PromClient::new_https(&hostname, query_timeout)?;
     let v = await!(p.instant_query(query, at));
@@ -175,15 +204,31 @@ async fn delete_series(
 ) -> StdResult<ApiResult, Box<StdError + 'static>> {
     let start = date_time(start)?;
     let end = date_time(end)?;
-    let query_timeout = if let Some(v) = query_timeout {
-        let v = v.parse::<u64>()?;
-        Some(Duration::new(v, 0))
+    let query_timeout = query_timeout
+        .map(|v| v.parse::<PromDuration>())
+        .transpose()?;
+
+    let mut p = PromClient::new_https(&hostname, query_timeout)?;
+    let v = await!(p.delete_series(series, start, end));
+    v.map_err(From::from)
+}
+
+async fn target_health(
+    hostname: String,
+    stale_factor: Option<String>,
+    query_timeout: Option<String>,
+) -> StdResult<TargetHealthReport, Box<StdError + 'static>> {
+    let stale_factor = if let Some(v) = stale_factor {
+        v.parse::<u32>()?
     } else {
-        None
+        DEFAULT_STALE_FACTOR
     };
+    let query_timeout = query_timeout
+        .map(|v| v.parse::<PromDuration>())
+        .transpose()?;
 
     let mut p = PromClient::new_https(&hostname, query_timeout)?;
-    let v = await!(p.delete_series(series, start, end));
+    let v = await!(p.target_health(stale_factor));
     v.map_err(From::from)
 }
 
@@ -19,58 +19,142 @@ use chrono::offset::Utc;
 use chrono::DateTime;
 use futures::compat::Future01CompatExt;
 use futures_stable::Stream;
+use http::header::{HeaderName, HeaderValue};
 use http::Uri;
-use hyper::client::HttpConnector;
-use hyper::{Body, Client, Request};
-use hyper_tls::HttpsConnector;
+use hyper::{Body, Chunk, Client, Request};
 use serde_json;
 use url::Url;
 
 use crate::messages::ApiResult;
+use crate::options::{NoOptions, QueryOptions};
+use crate::promql::Query;
+use crate::streaming::{self, MatrixDecoder, RangeStream};
+use crate::transport::{self, HyperHttpsConnector};
+use crate::units::PromDuration;
 use crate::{Error, Result};
 
 // TODO: query_timeout function
 // TODO: use ToStr where possible
 
-// TODO: replace Step with plain duration once it supports f64 output
-// ref: https://github.com/rust-lang/rust/issues/54361
+/// Resolution of a range query. Durations are sent to millisecond precision.
 pub enum Step {
     Seconds(f64),
     Duration(Duration),
 }
 
-pub type HyperHttpsConnector = HttpsConnector<HttpConnector>;
+impl From<f64> for Step {
+    fn from(secs: f64) -> Step {
+        Step::Seconds(secs)
+    }
+}
+
+impl From<Duration> for Step {
+    fn from(d: Duration) -> Step {
+        Step::Duration(d)
+    }
+}
+
+impl From<PromDuration> for Step {
+    fn from(d: PromDuration) -> Step {
+        Step::Duration(d.into())
+    }
+}
+
+/// Which targets [targets](PromClient::targets) returns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetState {
+    Active,
+    Dropped,
+    Any,
+}
+
+impl TargetState {
+    fn as_str(self) -> &'static str {
+        match self {
+            TargetState::Active => "active",
+            TargetState::Dropped => "dropped",
+            TargetState::Any => "any",
+        }
+    }
+}
 
 // FIXME: why am I exposing the underlying connection type?
+#[derive(Clone)]
 pub struct PromClient<T: hyper::client::connect::Connect + 'static> {
     client: Client<T, Body>,
     host: Url,
-    query_timeout: Option<Duration>,
+    query_timeout: Option<PromDuration>,
+    strict: bool,
 }
 
 impl PromClient<HyperHttpsConnector> {
-    pub fn new_https(
+    /// `query_timeout` may be a `std::time::Duration` or a [PromDuration].
+    pub fn new_https<D: Into<PromDuration>>(
         host: &str,
-        query_timeout: Option<Duration>,
+        query_timeout: Option<D>,
     ) -> Result<PromClient<HyperHttpsConnector>> {
-        let host = Url::from_str(host).map_err(|e| Error::new_invalid_host_error(host, e))?;
-        // Explicitly unwrapping here because this library is unusable if you can't build an HTTPS connection pool
-        let https = HttpsConnector::new(4).expect("Cannot build HTTPS connection pool");
+        let host = transport::parse_host(host)?;
         Ok(PromClient {
-            client: Client::builder().keep_alive(true).build(https),
+            client: transport::https_client(),
             host,
-            query_timeout,
+            query_timeout: query_timeout.map(Into::into),
+            strict: false,
         })
     }
 }
 
 impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
-    pub async fn instant_query(
+    /// Fail requests whose responses contain fields this library doesn't
+    /// know about, instead of keeping them in each struct's `extra` map.
+    ///
+    /// Off by default. Useful in tests that should catch API drift.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub async fn instant_query<Q: Into<Query>>(
         &mut self,
-        query: String,
+        query: Q,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<ApiResult> {
+        await!(self.instant_query_with_options(query, at, NoOptions))
+    }
+
+    /// Instant query with additional backend-specific parameters and headers.
+    pub async fn instant_query_with_options<Q: Into<Query>, O: QueryOptions>(
+        &mut self,
+        query: Q,
         at: Option<DateTime<Utc>>,
+        options: O,
     ) -> Result<ApiResult> {
         // interesting: when there were problems with the await macro it flagged the wrong line
+        let query = query.into().into_string()?;
+        let mut u = self.instant_query_url(query, at);
+        for (k, v) in options.query_params() {
+            u.query_pairs_mut().append_pair(&k, &v);
+        }
+        let u = Uri::from_str(u.as_str())?;
+
+        await!(self.make_http_get_api_call_with_headers(u, options.headers()))
+    }
+
+    /// Instant query that returns the undecoded response body.
+    /// Decode it with [messages::borrowed::from_slice](crate::messages::borrowed::from_slice)
+    /// to avoid allocating label names and values.
+    pub async fn instant_query_raw<Q: Into<Query>>(
+        &mut self,
+        query: Q,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<Chunk> {
+        let query = query.into().into_string()?;
+        let u = self.instant_query_url(query, at);
+        let u = Uri::from_str(u.as_str())?;
+
+        let resp = await!(self.client.get(u).compat())?;
+        await!(resp.into_body().concat2().compat()).map_err(From::from)
+    }
+
+    fn instant_query_url(&self, query: String, at: Option<DateTime<Utc>>) -> Url {
         let mut u = self.api_call_base_url("/api/v1/query");
         u.query_pairs_mut().append_pair("query", &query);
         if let Some(t) = at {
@@ -79,20 +163,89 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
         }
         if let Some(t) = self.query_timeout {
             u.query_pairs_mut()
-                .append_pair("timeout", &t.as_secs().to_string());
+                .append_pair("timeout", &t.to_string());
+        }
+        u
+    }
+
+    pub async fn range_query<Q: Into<Query>, S: Into<Step>>(
+        &mut self,
+        query: Q,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: S,
+    ) -> Result<ApiResult> {
+        await!(self.range_query_with_options(query, start, end, step, NoOptions))
+    }
+
+    /// Range query with additional backend-specific parameters and headers.
+    pub async fn range_query_with_options<Q: Into<Query>, S: Into<Step>, O: QueryOptions>(
+        &mut self,
+        query: Q,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: S,
+        options: O,
+    ) -> Result<ApiResult> {
+        let query = query.into().into_string()?;
+        let mut u = self.range_query_url(query, start, end, step.into());
+        for (k, v) in options.query_params() {
+            u.query_pairs_mut().append_pair(&k, &v);
         }
         let u = Uri::from_str(u.as_str())?;
 
-        await!(self.make_http_get_api_call(u))
+        await!(self.make_http_get_api_call_with_headers(u, options.headers()))
+    }
+
+    /// Range query whose series are decoded and yielded one at a time as the
+    /// response arrives, instead of after the whole body has been read.
+    /// Memory use is bounded by the largest single series.
+    ///
+    /// Error responses from Prometheus are reported as an
+    /// [ErrorKind::Api](crate::error::ErrorKind::Api) once the body ends.
+    pub async fn range_query_stream<Q: Into<Query>, S: Into<Step>>(
+        &mut self,
+        query: Q,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: S,
+    ) -> Result<RangeStream> {
+        let query = query.into().into_string()?;
+        let u = self.range_query_url(query, start, end, step.into());
+        let u = Uri::from_str(u.as_str())?;
+
+        let resp = await!(self.client.get(u).compat())?;
+        Ok(streaming::decode_body(
+            resp.into_body(),
+            MatrixDecoder::default(),
+        ))
     }
 
-    pub async fn range_query(
+    /// Range query that returns the undecoded response body.
+    /// Decode it with [messages::borrowed::from_slice](crate::messages::borrowed::from_slice)
+    /// to avoid allocating label names and values.
+    pub async fn range_query_raw<Q: Into<Query>, S: Into<Step>>(
         &mut self,
+        query: Q,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: S,
+    ) -> Result<Chunk> {
+        let query = query.into().into_string()?;
+        let u = self.range_query_url(query, start, end, step.into());
+        let u = Uri::from_str(u.as_str())?;
+
+        let resp = await!(self.client.get(u).compat())?;
+        await!(resp.into_body().concat2().compat()).map_err(From::from)
+    }
+
+    fn range_query_url(
+        &self,
         query: String,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
         step: Step,
-    ) -> Result<ApiResult> {
+    ) -> Url {
         let mut u = self.api_call_base_url("/api/v1/query_range");
         u.query_pairs_mut().append_pair("query", &query);
         u.query_pairs_mut()
@@ -101,16 +254,14 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
             .append_pair("end", &end.to_rfc3339().to_string());
         let step: String = match step {
             Step::Seconds(f) => f.to_string(),
-            Step::Duration(d) => format!("{}s", d.as_secs().to_string()),
+            Step::Duration(d) => PromDuration::from(d).to_string(),
         };
         u.query_pairs_mut().append_pair("step", &step);
         if let Some(t) = self.query_timeout {
             u.query_pairs_mut()
-                .append_pair("timeout", &t.as_secs().to_string());
+                .append_pair("timeout", &t.to_string());
         }
-        let u = Uri::from_str(u.as_str())?;
-
-        await!(self.make_http_get_api_call(u))
+        u
     }
 
     pub async fn series(
@@ -129,7 +280,7 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
             .append_pair("end", &end.to_rfc3339().to_string());
         if let Some(t) = self.query_timeout {
             u.query_pairs_mut()
-                .append_pair("timeout", &t.as_secs().to_string());
+                .append_pair("timeout", &t.to_string());
         }
         let u = Uri::from_str(u.as_str())?;
 
@@ -148,8 +299,20 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
         await!(self.make_http_get_api_call(u))
     }
 
-    pub async fn targets(&mut self) -> Result<ApiResult> {
-        let u = self.api_call_base_url("/api/v1/targets");
+    /// Scrape targets, optionally filtered by `state` and `scrape_pool`.
+    /// Without filters, Prometheus returns both active and dropped targets.
+    pub async fn targets(
+        &mut self,
+        state: Option<TargetState>,
+        scrape_pool: Option<String>,
+    ) -> Result<ApiResult> {
+        let mut u = self.api_call_base_url("/api/v1/targets");
+        if let Some(state) = state {
+            u.query_pairs_mut().append_pair("state", state.as_str());
+        }
+        if let Some(scrape_pool) = scrape_pool {
+            u.query_pairs_mut().append_pair("scrapePool", &scrape_pool);
+        }
         let u = Uri::from_str(u.as_str())?;
         await!(self.make_http_get_api_call(u))
     }
@@ -173,9 +336,31 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
     }
 
     async fn make_http_get_api_call(&mut self, u: Uri) -> Result<ApiResult> {
-        let resp = await!(self.client.get(u).compat())?;
+        await!(self.make_http_get_api_call_with_headers(u, Vec::new()))
+    }
+
+    async fn make_http_get_api_call_with_headers(
+        &mut self,
+        u: Uri,
+        headers: Vec<(String, String)>,
+    ) -> Result<ApiResult> {
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let mut get = Request::get(u)
+            .body(Body::empty())
+            .expect("Failed to construct GET with empty body");
+        for (name, value) in headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::new_invalid_header_error(name.as_str(), value.as_str()))?;
+            let header_value = HeaderValue::from_str(&value)
+                .map_err(|_| Error::new_invalid_header_error(name.as_str(), value.as_str()))?;
+            get.headers_mut().append(header_name, header_value);
+        }
+
+        let resp = await!(self.client.request(get).compat())?;
         let body = await!(resp.into_body().concat2().compat())?;
-        serde_json::from_slice::<ApiResult>(&body).map_err(From::from)
+        self.parse_api_result(&body)
     }
 
     //
@@ -202,7 +387,7 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
         }
         if let Some(t) = self.query_timeout {
             u.query_pairs_mut()
-                .append_pair("timeout", &t.as_secs().to_string());
+                .append_pair("timeout", &t.to_string());
         }
         let u = Uri::from_str(u.as_str())?;
 
@@ -215,7 +400,7 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
 
         let resp = await!(self.client.request(post).compat())?;
         let body = await!(resp.into_body().concat2().compat())?;
-        serde_json::from_slice::<ApiResult>(&body).map_err(From::from)
+        self.parse_api_result(&body)
     }
 
     pub async fn snapshot(&mut self, skip_head: bool) -> Result<ApiResult> {
@@ -232,7 +417,7 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
 
         let resp = await!(self.client.request(post).compat())?;
         let body = await!(resp.into_body().concat2().compat())?;
-        serde_json::from_slice::<ApiResult>(&body).map_err(From::from)
+        self.parse_api_result(&body)
     }
 
     pub async fn clean_tombstones(&mut self) -> Result<ApiResult> {
@@ -248,10 +433,24 @@ impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
 
         let resp = await!(self.client.request(post).compat())?;
         let body = await!(resp.into_body().concat2().compat())?;
-        serde_json::from_slice::<ApiResult>(&body).map_err(From::from)
+        self.parse_api_result(&body)
+    }
+
+    fn parse_api_result(&self, body: &[u8]) -> Result<ApiResult> {
+        let res = serde_json::from_slice::<ApiResult>(body)?;
+        if self.strict {
+            res.deny_unknown_fields()
+        } else {
+            Ok(res)
+        }
+    }
+
+    #[cfg(feature = "victoriametrics")]
+    pub(crate) fn http_client(&self) -> &Client<T, Body> {
+        &self.client
     }
 
-    fn api_call_base_url(&self, api_path: &str) -> Url {
+    pub(crate) fn api_call_base_url(&self, api_path: &str) -> Url {
         // Explicitly unwrapping here because we should be able
         // to join an already-verified Prometheus hostname with
         // precanned valid path fragments
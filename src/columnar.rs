@@ -0,0 +1,205 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Columnar representation of range query results.
+//!
+//! [RangeColumns] stores timestamps and values in separate contiguous
+//! vectors, which suits vectorized analytics better than a `Vec<Sample>`.
+//! Timestamps are integer milliseconds, the precision Prometheus stores.
+
+use crate::messages::{epoch_to_millis, millis_to_epoch, Metric, Range, Sample};
+use crate::{Error, Result};
+
+/// A single series with timestamps and values stored as parallel columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeColumns {
+    pub metric: Metric,
+    /// Milliseconds since the Unix epoch, in ascending order.
+    pub timestamps: Vec<i64>,
+    pub values: Vec<f64>,
+}
+
+impl From<Range> for RangeColumns {
+    fn from(range: Range) -> Self {
+        let mut timestamps = Vec::with_capacity(range.samples.len());
+        let mut values = Vec::with_capacity(range.samples.len());
+        for s in range.samples {
+            timestamps.push(epoch_to_millis(s.epoch));
+            values.push(s.value);
+        }
+        RangeColumns {
+            metric: range.metric,
+            timestamps,
+            values,
+        }
+    }
+}
+
+impl<'a> From<&'a Range> for RangeColumns {
+    fn from(range: &'a Range) -> Self {
+        RangeColumns {
+            metric: range.metric.clone(),
+            timestamps: range
+                .samples
+                .iter()
+                .map(|s| epoch_to_millis(s.epoch))
+                .collect(),
+            values: range.samples.iter().map(|s| s.value).collect(),
+        }
+    }
+}
+
+impl From<RangeColumns> for Range {
+    fn from(columns: RangeColumns) -> Self {
+        let samples = columns
+            .timestamps
+            .into_iter()
+            .zip(columns.values)
+            .map(|(ts, value)| Sample {
+                epoch: millis_to_epoch(ts),
+                value,
+            })
+            .collect();
+        Range {
+            metric: columns.metric,
+            samples,
+        }
+    }
+}
+
+impl RangeColumns {
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+}
+
+/// Many series sharing a single timestamp column.
+///
+/// `columns[i]` holds the values of `metrics[i]`, with `NaN`
+/// wherever that series has no sample at a timestamp.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlignedSeries {
+    pub timestamps: Vec<i64>,
+    pub metrics: Vec<Metric>,
+    pub columns: Vec<Vec<f64>>,
+}
+
+/// Align `series` onto the sorted union of all their timestamps.
+pub fn align(series: &[RangeColumns]) -> AlignedSeries {
+    let mut timestamps: Vec<i64> = series
+        .iter()
+        .flat_map(|s| s.timestamps.iter().cloned())
+        .collect();
+    timestamps.sort_unstable();
+    timestamps.dedup();
+    align_to(series, timestamps)
+}
+
+/// Align `series` onto the given ascending `timestamps`.
+/// Samples at timestamps not in `timestamps` are dropped.
+pub fn align_to(series: &[RangeColumns], timestamps: Vec<i64>) -> AlignedSeries {
+    let columns = series
+        .iter()
+        .map(|s| {
+            let mut column = vec![std::f64::NAN; timestamps.len()];
+            let mut i = 0;
+            for (ts, value) in s.timestamps.iter().zip(&s.values) {
+                while i < timestamps.len() && timestamps[i] < *ts {
+                    i += 1;
+                }
+                if i == timestamps.len() {
+                    break;
+                }
+                if timestamps[i] == *ts {
+                    column[i] = *value;
+                }
+            }
+            column
+        })
+        .collect();
+
+    AlignedSeries {
+        timestamps,
+        metrics: series.iter().map(|s| s.metric.clone()).collect(),
+        columns,
+    }
+}
+
+/// Evenly spaced timestamps from `start` to `end` inclusive, as used by range queries.
+pub fn step_timestamps(start: i64, end: i64, step: i64) -> Result<Vec<i64>> {
+    if step <= 0 {
+        return Err(Error::new_invalid_query_error(
+            "zero or negative query resolution step",
+        ));
+    }
+    Ok((0..)
+        .map(|i| start + i * step)
+        .take_while(|ts| *ts <= end)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::columnar::{align, align_to, step_timestamps, RangeColumns};
+    use crate::fixtures::range;
+    use crate::messages::Range;
+
+    #[test]
+    fn should_convert_losslessly_between_range_and_columns() {
+        let range = range(
+            &[],
+            &[(1435781430.781, 1.0), (1435781445.001, 0.5), (0.999, -2.0)],
+        );
+
+        let c = RangeColumns::from(&range);
+        assert_eq!(vec![1435781430781, 1435781445001, 999], c.timestamps);
+        assert_eq!(vec![1.0, 0.5, -2.0], c.values);
+        assert_eq!(range, Range::from(c));
+    }
+
+    #[test]
+    fn should_align_series_on_union_of_timestamps() {
+        let a = range(&[("job", "a")], &[(1.0, 1.0), (2.0, 2.0), (3.0, 3.0)]);
+        let b = range(&[("job", "b")], &[(2.0, 20.0), (4.0, 40.0)]);
+
+        let aligned = align(&[RangeColumns::from(a), RangeColumns::from(b)]);
+        assert_eq!(vec![1000, 2000, 3000, 4000], aligned.timestamps);
+        assert_eq!(&[1.0, 2.0, 3.0], &aligned.columns[0][..3]);
+        assert!(aligned.columns[0][3].is_nan());
+        assert!(aligned.columns[1][0].is_nan());
+        assert_eq!(20.0, aligned.columns[1][1]);
+        assert!(aligned.columns[1][2].is_nan());
+        assert_eq!(40.0, aligned.columns[1][3]);
+    }
+
+    #[test]
+    fn should_align_series_to_step_grid() {
+        let a = range(&[("job", "a")], &[(0.5, 0.5), (1.0, 1.0), (3.0, 3.0)]);
+
+        let aligned = align_to(
+            &[RangeColumns::from(a)],
+            step_timestamps(1000, 3000, 1000).unwrap(),
+        );
+        assert_eq!(vec![1000, 2000, 3000], aligned.timestamps);
+        assert_eq!(1.0, aligned.columns[0][0]);
+        assert!(aligned.columns[0][1].is_nan());
+        assert_eq!(3.0, aligned.columns[0][2]);
+
+        assert!(step_timestamps(1000, 3000, 0).is_err());
+    }
+}
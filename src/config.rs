@@ -0,0 +1,249 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed model of [`prometheus.yml`](https://prometheus.io/docs/prometheus/latest/configuration/configuration/).
+//! Enabled with the `config` feature.
+//!
+//! Only commonly-inspected sections are modelled. Unknown keys are
+//! ignored, and the many service discovery configs of a scrape config
+//! are kept as raw YAML in [ScrapeConfig::other]. Durations are parsed
+//! into [PromDuration]s, so equal durations written differently, e.g.
+//! `1m` and `60s`, compare equal.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml;
+
+use crate::messages::Config;
+use crate::units::PromDuration;
+use crate::{Error, Result};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PrometheusConfig {
+    #[serde(default)]
+    pub global: GlobalConfig,
+    #[serde(default)]
+    pub rule_files: Vec<String>,
+    #[serde(default)]
+    pub scrape_configs: Vec<ScrapeConfig>,
+    #[serde(default)]
+    pub alerting: Option<AlertingConfig>,
+    #[serde(default)]
+    pub remote_write: Vec<RemoteWriteConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GlobalConfig {
+    pub scrape_interval: Option<PromDuration>,
+    pub scrape_timeout: Option<PromDuration>,
+    pub evaluation_interval: Option<PromDuration>,
+    #[serde(default)]
+    pub external_labels: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ScrapeConfig {
+    pub job_name: String,
+    pub scrape_interval: Option<PromDuration>,
+    pub scrape_timeout: Option<PromDuration>,
+    pub metrics_path: Option<String>,
+    pub scheme: Option<String>,
+    pub honor_labels: Option<bool>,
+    #[serde(default)]
+    pub static_configs: Vec<StaticConfig>,
+    #[serde(default)]
+    pub relabel_configs: Vec<RelabelConfig>,
+    #[serde(default)]
+    pub metric_relabel_configs: Vec<RelabelConfig>,
+    /// Keys not modelled above, e.g. `kubernetes_sd_configs` or `basic_auth`.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_yaml::Value>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StaticConfig {
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RelabelConfig {
+    pub source_labels: Option<Vec<String>>,
+    pub separator: Option<String>,
+    pub target_label: Option<String>,
+    pub regex: Option<String>,
+    pub modulus: Option<u64>,
+    pub replacement: Option<String>,
+    pub action: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AlertingConfig {
+    #[serde(default)]
+    pub alert_relabel_configs: Vec<RelabelConfig>,
+    #[serde(default)]
+    pub alertmanagers: Vec<AlertmanagerConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AlertmanagerConfig {
+    pub scheme: Option<String>,
+    pub path_prefix: Option<String>,
+    pub timeout: Option<PromDuration>,
+    pub api_version: Option<String>,
+    #[serde(default)]
+    pub static_configs: Vec<StaticConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    pub name: Option<String>,
+    pub remote_timeout: Option<PromDuration>,
+    #[serde(default)]
+    pub write_relabel_configs: Vec<RelabelConfig>,
+}
+
+impl PrometheusConfig {
+    pub fn from_yaml(yaml: &str) -> Result<PrometheusConfig> {
+        serde_yaml::from_str(yaml).map_err(Error::new_invalid_config_error)
+    }
+
+    pub fn scrape_config(&self, job_name: &str) -> Option<&ScrapeConfig> {
+        self.scrape_configs.iter().find(|c| c.job_name == job_name)
+    }
+
+    /// Scrape interval of `job_name`, falling back to the global interval.
+    pub fn scrape_interval(&self, job_name: &str) -> Option<PromDuration> {
+        self.scrape_config(job_name)?
+            .scrape_interval
+            .or(self.global.scrape_interval)
+    }
+}
+
+impl Config {
+    /// Parse the loaded configuration returned by [config](crate::PromClient::config).
+    pub fn parse(&self) -> Result<PrometheusConfig> {
+        PrometheusConfig::from_yaml(&self.yaml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::PrometheusConfig;
+    use crate::error::ErrorKind;
+    use crate::messages::Config;
+    use crate::units::PromDuration;
+
+    const YAML: &str = r#"
+global:
+  scrape_interval: 1m
+  scrape_timeout: 10s
+  evaluation_interval: 1m
+  external_labels:
+    region: eu-west-1
+alerting:
+  alertmanagers:
+  - scheme: http
+    timeout: 10s
+    api_version: v2
+    static_configs:
+    - targets:
+      - alertmanager:9093
+rule_files:
+- /etc/prometheus/rules/*.yml
+scrape_configs:
+- job_name: prometheus
+  honor_timestamps: true
+  scrape_interval: 15s
+  metrics_path: /metrics
+  static_configs:
+  - targets:
+    - localhost:9090
+- job_name: node
+  kubernetes_sd_configs:
+  - role: node
+  relabel_configs:
+  - source_labels: [__meta_kubernetes_node_name]
+    separator: ;
+    regex: (.*)
+    target_label: node
+    replacement: $1
+    action: replace
+remote_write:
+- url: http://cortex:9009/api/v1/push
+  remote_timeout: 30s
+"#;
+
+    #[test]
+    fn should_parse_prometheus_config() {
+        let config = Config {
+            yaml: YAML.to_owned(),
+        }
+        .parse()
+        .unwrap();
+
+        assert_eq!("eu-west-1", config.global.external_labels["region"]);
+        assert_eq!(vec!["/etc/prometheus/rules/*.yml"], config.rule_files);
+        assert_eq!(
+            vec!["alertmanager:9093"],
+            config.alerting.unwrap().alertmanagers[0].static_configs[0].targets
+        );
+        assert_eq!("http://cortex:9009/api/v1/push", config.remote_write[0].url);
+
+        let node = &config.scrape_configs[1];
+        assert_eq!(
+            Some("node"),
+            node.relabel_configs[0]
+                .target_label
+                .as_ref()
+                .map(String::as_str)
+        );
+        assert!(node.other.contains_key("kubernetes_sd_configs"));
+    }
+
+    #[test]
+    fn should_resolve_scrape_interval_from_global() {
+        let config = PrometheusConfig::from_yaml(YAML).unwrap();
+        assert_eq!(
+            Some(PromDuration::from_millis(15_000)),
+            config.scrape_interval("prometheus")
+        );
+        assert_eq!(
+            Some(PromDuration::from_millis(60_000)),
+            config.scrape_interval("node")
+        );
+        assert_eq!(None, config.scrape_interval("missing"));
+
+        // the same durations written differently compare equal
+        let respelled = YAML
+            .replace("scrape_interval: 1m", "scrape_interval: 60s")
+            .replace("remote_timeout: 30s", "remote_timeout: 30000ms");
+        assert_eq!(config, PrometheusConfig::from_yaml(&respelled).unwrap());
+    }
+
+    #[test]
+    fn should_reject_invalid_config() {
+        match PrometheusConfig::from_yaml("scrape_configs: 5")
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidConfigYaml { .. } => {}
+            k => panic!("unexpected error {:?}", k),
+        }
+    }
+}
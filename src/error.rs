@@ -25,9 +25,14 @@ use std::{
 use http;
 use http::uri;
 use hyper;
+use regex;
 use serde_json;
+#[cfg(feature = "config")]
+use serde_yaml;
 use url;
 
+use crate::messages::ApiErr;
+
 /// Type alias for `Result<T, prometheus_query::Error>`
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -69,6 +74,95 @@ pub enum ErrorKind {
         /// Underlying error type.
         err: serde_json::Error,
     },
+    /// Prometheus returned an error response.
+    /// Only raised by calls that don't return the full [ApiResult](crate::messages::ApiResult).
+    Api {
+        /// Error returned by Prometheus.
+        err: Box<ApiErr>,
+    },
+    /// Header that cannot be sent in an HTTP request.
+    /// Triggered by query options with invalid header names or values.
+    InvalidHeader {
+        /// Header name.
+        name: String,
+        /// Header value.
+        value: String,
+    },
+    /// Non-success HTTP status from an API that signals errors through
+    /// status codes rather than a JSON envelope (Alertmanager, Pushgateway).
+    UnexpectedStatus {
+        /// HTTP status returned by the server.
+        status: http::StatusCode,
+        /// Response body, which usually holds a human-readable error message.
+        body: String,
+    },
+    /// Label matcher with an invalid regular expression.
+    InvalidRegex {
+        /// Regular expression that could not be compiled.
+        pattern: String,
+        /// Underlying error type.
+        err: regex::Error,
+    },
+    /// Invalid Prometheus duration string, e.g. in a flag value.
+    InvalidDuration {
+        /// String that could not be parsed.
+        duration: String,
+    },
+    /// Invalid Prometheus byte size string, e.g. in a flag value.
+    InvalidByteSize {
+        /// String that could not be parsed.
+        size: String,
+    },
+    /// Flag value that could not be parsed into the expected type.
+    InvalidFlag {
+        /// Flag name.
+        name: String,
+        /// Flag value.
+        value: String,
+    },
+    /// Local file I/O error, e.g. while archiving a snapshot.
+    Io {
+        /// Underlying error type.
+        err: std::io::Error,
+    },
+    /// Prometheus configuration YAML that doesn't match the typed model.
+    #[cfg(feature = "config")]
+    InvalidConfigYaml {
+        /// Underlying error type.
+        err: serde_yaml::Error,
+    },
+    /// PromQL expression that could not be evaluated client-side.
+    Evaluation {
+        /// What went wrong.
+        message: String,
+    },
+    /// PromQL expression could not be parsed.
+    InvalidPromQl {
+        /// What went wrong.
+        message: String,
+        /// Byte offset of the offending token.
+        offset: usize,
+        /// 1-based line of the offending token.
+        line: usize,
+        /// 1-based column of the offending token.
+        column: usize,
+    },
+    /// Query builder was used in a way that has no PromQL equivalent.
+    InvalidQuery {
+        /// What went wrong.
+        message: String,
+    },
+    /// Successful response that lacks the data the call expects, e.g. a
+    /// snapshot response without a snapshot name.
+    UnexpectedResponse {
+        /// What was missing.
+        message: String,
+    },
+    /// Response contained fields this library doesn't know about (strict mode only).
+    UnknownFields {
+        /// Paths of the unknown fields, e.g. `activeTargets[0].scrapeClass`.
+        fields: Vec<String>,
+    },
     /// Destructuring should not be exhaustive.
     ///
     /// This enum may grow additional variants, so this makes sure clients
@@ -83,6 +177,21 @@ impl std::error::Error for Error {
             ErrorKind::InvalidHost { ref err, .. } => Some(err),
             ErrorKind::Http { ref err } => Some(err),
             ErrorKind::InvalidResponseJson { ref err, .. } => Some(err),
+            ErrorKind::Api { ref err } => Some(err.as_ref()),
+            ErrorKind::InvalidHeader { .. } => None,
+            ErrorKind::UnexpectedStatus { .. } => None,
+            ErrorKind::InvalidRegex { ref err, .. } => Some(err),
+            ErrorKind::InvalidDuration { .. } => None,
+            ErrorKind::InvalidByteSize { .. } => None,
+            ErrorKind::InvalidFlag { .. } => None,
+            ErrorKind::Io { ref err } => Some(err),
+            #[cfg(feature = "config")]
+            ErrorKind::InvalidConfigYaml { ref err } => Some(err),
+            ErrorKind::Evaluation { .. } => None,
+            ErrorKind::InvalidPromQl { .. } => None,
+            ErrorKind::InvalidQuery { .. } => None,
+            ErrorKind::UnexpectedResponse { .. } => None,
+            ErrorKind::UnknownFields { .. } => None,
             _ => unreachable!("unexpected match arm!"),
         }
     }
@@ -99,6 +208,54 @@ impl Display for Error {
                 f.write_str(&format!("Invalid API url '{}'", url))
             }
             ErrorKind::InvalidResponseJson { ref err, .. } => err.fmt(f),
+            ErrorKind::Api { ref err } => f.write_str(&format!(
+                "Prometheus error ({}): {}",
+                err.error_type, err.error_message
+            )),
+            ErrorKind::InvalidHeader { ref name, .. } => {
+                f.write_str(&format!("Invalid value for HTTP header '{}'", name))
+            }
+            ErrorKind::UnexpectedStatus {
+                ref status,
+                ref body,
+            } => f.write_str(&format!("Unexpected HTTP status {}: {}", status, body)),
+            ErrorKind::InvalidRegex { ref pattern, .. } => {
+                f.write_str(&format!("Invalid regular expression '{}'", pattern))
+            }
+            ErrorKind::InvalidDuration { ref duration } => {
+                f.write_str(&format!("Invalid duration '{}'", duration))
+            }
+            ErrorKind::InvalidByteSize { ref size } => {
+                f.write_str(&format!("Invalid byte size '{}'", size))
+            }
+            ErrorKind::InvalidFlag {
+                ref name,
+                ref value,
+            } => f.write_str(&format!("Invalid value '{}' for flag '{}'", value, name)),
+            ErrorKind::Io { ref err } => err.fmt(f),
+            #[cfg(feature = "config")]
+            ErrorKind::InvalidConfigYaml { ref err } => err.fmt(f),
+            ErrorKind::Evaluation { ref message } => {
+                f.write_str(&format!("PromQL evaluation error: {}", message))
+            }
+            ErrorKind::InvalidPromQl {
+                ref message,
+                line,
+                column,
+                ..
+            } => f.write_str(&format!(
+                "PromQL parse error at line {}, column {}: {}",
+                line, column, message
+            )),
+            ErrorKind::InvalidQuery { ref message } => {
+                f.write_str(&format!("Invalid query: {}", message))
+            }
+            ErrorKind::UnexpectedResponse { ref message } => {
+                f.write_str(&format!("Unexpected response: {}", message))
+            }
+            ErrorKind::UnknownFields { ref fields } => {
+                f.write_str(&format!("Unknown response fields: {}", fields.join(", ")))
+            }
             _ => unreachable!("unexpected match arm!"),
         }
     }
@@ -116,6 +273,137 @@ impl Error {
         }
     }
 
+    /// Create a new [Error::Api].
+    pub(crate) fn new_api_error(err: ApiErr) -> Error {
+        Error {
+            kind: ErrorKind::Api { err: Box::new(err) },
+        }
+    }
+
+    /// Create a new [Error::InvalidHeader].
+    pub(crate) fn new_invalid_header_error<N: Into<String>, V: Into<String>>(
+        name: N,
+        value: V,
+    ) -> Error {
+        Error {
+            kind: ErrorKind::InvalidHeader {
+                name: name.into(),
+                value: value.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::UnexpectedStatus].
+    pub(crate) fn new_unexpected_status_error<S: Into<String>>(
+        status: http::StatusCode,
+        body: S,
+    ) -> Error {
+        Error {
+            kind: ErrorKind::UnexpectedStatus {
+                status,
+                body: body.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidRegex].
+    pub(crate) fn new_invalid_regex_error<S: Into<String>>(pattern: S, err: regex::Error) -> Error {
+        Error {
+            kind: ErrorKind::InvalidRegex {
+                pattern: pattern.into(),
+                err,
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidDuration].
+    pub(crate) fn new_invalid_duration_error<S: Into<String>>(duration: S) -> Error {
+        Error {
+            kind: ErrorKind::InvalidDuration {
+                duration: duration.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidByteSize].
+    pub(crate) fn new_invalid_byte_size_error<S: Into<String>>(size: S) -> Error {
+        Error {
+            kind: ErrorKind::InvalidByteSize { size: size.into() },
+        }
+    }
+
+    /// Create a new [Error::InvalidFlag].
+    pub(crate) fn new_invalid_flag_error<N: Into<String>, V: Into<String>>(
+        name: N,
+        value: V,
+    ) -> Error {
+        Error {
+            kind: ErrorKind::InvalidFlag {
+                name: name.into(),
+                value: value.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidConfigYaml].
+    #[cfg(feature = "config")]
+    pub(crate) fn new_invalid_config_error(err: serde_yaml::Error) -> Error {
+        Error {
+            kind: ErrorKind::InvalidConfigYaml { err },
+        }
+    }
+
+    /// Create a new [Error::Evaluation].
+    pub(crate) fn new_evaluation_error<S: Into<String>>(message: S) -> Error {
+        Error {
+            kind: ErrorKind::Evaluation {
+                message: message.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidPromQl].
+    pub(crate) fn new_invalid_promql_error<S: Into<String>>(
+        message: S,
+        offset: usize,
+        line: usize,
+        column: usize,
+    ) -> Error {
+        Error {
+            kind: ErrorKind::InvalidPromQl {
+                message: message.into(),
+                offset,
+                line,
+                column,
+            },
+        }
+    }
+
+    /// Create a new [Error::InvalidQuery].
+    pub(crate) fn new_invalid_query_error<S: Into<String>>(message: S) -> Error {
+        Error {
+            kind: ErrorKind::InvalidQuery {
+                message: message.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::UnexpectedResponse].
+    pub(crate) fn new_unexpected_response_error<S: Into<String>>(message: S) -> Error {
+        Error {
+            kind: ErrorKind::UnexpectedResponse {
+                message: message.into(),
+            },
+        }
+    }
+
+    /// Create a new [Error::UnknownFields].
+    pub(crate) fn new_unknown_fields_error(fields: Vec<String>) -> Error {
+        Error {
+            kind: ErrorKind::UnknownFields { fields },
+        }
+    }
+
     /// Return the specific error type.
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
@@ -148,3 +436,11 @@ impl From<uri::InvalidUri> for Error {
         }
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error {
+            kind: ErrorKind::Io { err },
+        }
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CSV and JSON lines writers for query results.
+//!
+//! Every sample of an [Expression] becomes one row (or line), so scalars
+//! and strings produce a single row while vectors and matrices produce
+//! one row per sample of every series. Non-finite values are written as
+//! the same `NaN`, `Inf` and `-Inf` strings Prometheus uses in its API.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde_json::{self, Map, Value as JsonValue};
+
+use crate::messages::{epoch_to_millis, format_value, Expression, Metric};
+
+pub const TIMESTAMP_COLUMN: &str = "timestamp";
+
+pub const VALUE_COLUMN: &str = "value";
+
+/// Labels written alongside each sample.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelColumns {
+    /// Every label name present in the result, in sorted order.
+    All,
+    /// Only these labels, in the given order. Missing labels are left empty.
+    Only(Vec<String>),
+}
+
+/// How sample timestamps are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimestampFormat {
+    /// Fractional seconds since the Unix epoch, as returned by the API.
+    EpochSeconds,
+    /// Whole milliseconds since the Unix epoch.
+    EpochMillis,
+    /// RFC 3339 in UTC with millisecond precision, e.g. `2015-07-01T20:10:30.781Z`.
+    Rfc3339,
+}
+
+/// Options shared by [write_csv] and [write_json_lines].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub labels: LabelColumns,
+    pub timestamp_format: TimestampFormat,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            labels: LabelColumns::All,
+            timestamp_format: TimestampFormat::EpochSeconds,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn new() -> ExportOptions {
+        Default::default()
+    }
+
+    pub fn labels(mut self, labels: LabelColumns) -> ExportOptions {
+        self.labels = labels;
+        self
+    }
+
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> ExportOptions {
+        self.timestamp_format = format;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum RowValue<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+#[derive(Debug)]
+struct Row<'a> {
+    metric: Option<&'a Metric>,
+    epoch: f64,
+    value: RowValue<'a>,
+}
+
+fn rows_of(expr: &Expression) -> Vec<Row<'_>> {
+    match expr {
+        Expression::Scalar(s) => vec![Row {
+            metric: None,
+            epoch: s.epoch,
+            value: RowValue::Number(s.value),
+        }],
+        Expression::String(s) => vec![Row {
+            metric: None,
+            epoch: s.epoch,
+            value: RowValue::Text(&s.value),
+        }],
+        Expression::Instant(instants) => instants
+            .iter()
+            .map(|i| Row {
+                metric: Some(&i.metric),
+                epoch: i.sample.epoch,
+                value: RowValue::Number(i.sample.value),
+            })
+            .collect(),
+        Expression::Range(ranges) => ranges
+            .iter()
+            .flat_map(|r| {
+                r.samples.iter().map(move |s| Row {
+                    metric: Some(&r.metric),
+                    epoch: s.epoch,
+                    value: RowValue::Number(s.value),
+                })
+            })
+            .collect(),
+    }
+}
+
+fn label_names<'a>(labels: &'a LabelColumns, rows: &[Row<'a>]) -> Vec<&'a str> {
+    match labels {
+        LabelColumns::All => rows
+            .iter()
+            .filter_map(|r| r.metric)
+            .flat_map(|m| m.labels.keys().map(String::as_str))
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .collect(),
+        LabelColumns::Only(names) => names.iter().map(String::as_str).collect(),
+    }
+}
+
+fn format_rfc3339(epoch: f64) -> io::Result<String> {
+    let millis = epoch_to_millis(epoch);
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("timestamp {} is out of range", epoch),
+            )
+        })
+}
+
+/// Write `expr` as CSV with a header row.
+///
+/// Columns are `timestamp`, `value`, then one column per label.
+/// Fields are quoted only when they contain a comma, quote or newline.
+pub fn write_csv<W: Write>(
+    w: &mut W,
+    expr: &Expression,
+    options: &ExportOptions,
+) -> io::Result<()> {
+    let rows = rows_of(expr);
+    let labels = label_names(&options.labels, &rows);
+
+    let mut header = vec![TIMESTAMP_COLUMN, VALUE_COLUMN];
+    header.extend(labels.iter().cloned());
+    write_csv_record(w, header.into_iter().map(String::from))?;
+
+    for row in &rows {
+        let timestamp = match options.timestamp_format {
+            TimestampFormat::EpochSeconds => row.epoch.to_string(),
+            TimestampFormat::EpochMillis => epoch_to_millis(row.epoch).to_string(),
+            TimestampFormat::Rfc3339 => format_rfc3339(row.epoch)?,
+        };
+        let value = match row.value {
+            RowValue::Number(v) => format_value(v),
+            RowValue::Text(s) => s.to_owned(),
+        };
+        let label_values = labels.iter().map(|name| {
+            row.metric
+                .and_then(|m| m.labels.get(*name))
+                .cloned()
+                .unwrap_or_default()
+        });
+        write_csv_record(w, vec![timestamp, value].into_iter().chain(label_values))?;
+    }
+
+    Ok(())
+}
+
+fn write_csv_record<W: Write, I: Iterator<Item = String>>(w: &mut W, fields: I) -> io::Result<()> {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        if field.contains(&[',', '"', '\n', '\r'][..]) {
+            write!(w, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            w.write_all(field.as_bytes())?;
+        }
+    }
+    w.write_all(b"\n")
+}
+
+/// Write `expr` as JSON lines, one object per sample.
+///
+/// Each object has `timestamp` and `value` fields, and a `metric` object
+/// with the selected labels for vectors and matrices. Finite values are
+/// JSON numbers; non-finite values and string results are JSON strings.
+pub fn write_json_lines<W: Write>(
+    w: &mut W,
+    expr: &Expression,
+    options: &ExportOptions,
+) -> io::Result<()> {
+    let rows = rows_of(expr);
+
+    for row in &rows {
+        let mut line = Map::new();
+        if let Some(metric) = row.metric {
+            let labels: Map<String, JsonValue> = match options.labels {
+                LabelColumns::All => metric
+                    .labels
+                    .iter()
+                    .map(|(n, v)| (n.clone(), JsonValue::from(v.as_str())))
+                    .collect(),
+                LabelColumns::Only(ref names) => names
+                    .iter()
+                    .filter_map(|n| {
+                        metric
+                            .labels
+                            .get(n)
+                            .map(|v| (n.clone(), JsonValue::from(v.as_str())))
+                    })
+                    .collect(),
+            };
+            line.insert("metric".to_owned(), JsonValue::Object(labels));
+        }
+
+        let timestamp = match options.timestamp_format {
+            TimestampFormat::EpochSeconds => JsonValue::from(row.epoch),
+            TimestampFormat::EpochMillis => JsonValue::from(epoch_to_millis(row.epoch)),
+            TimestampFormat::Rfc3339 => JsonValue::from(format_rfc3339(row.epoch)?),
+        };
+        line.insert(TIMESTAMP_COLUMN.to_owned(), timestamp);
+
+        let value = match row.value {
+            RowValue::Number(v) if v.is_finite() => JsonValue::from(v),
+            RowValue::Number(v) => JsonValue::from(format_value(v)),
+            RowValue::Text(s) => JsonValue::from(s),
+        };
+        line.insert(VALUE_COLUMN.to_owned(), value);
+
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::{
+        write_csv, write_json_lines, ExportOptions, LabelColumns, TimestampFormat,
+    };
+    use crate::fixtures::range;
+    use crate::messages::{Expression, Sample, StringSample};
+
+    fn matrix() -> Expression {
+        Expression::Range(vec![
+            range(
+                &[("__name__", "up"), ("job", "node")],
+                &[(1435781430.781, 1.0), (1435781445.781, std::f64::NAN)],
+            ),
+            range(
+                &[("__name__", "up"), ("job", "a,\"b\"")],
+                &[(1435781430.781, std::f64::NEG_INFINITY)],
+            ),
+        ])
+    }
+
+    #[test]
+    fn should_write_matrix_as_csv() {
+        let mut out = Vec::new();
+        write_csv(&mut out, &matrix(), &ExportOptions::new()).unwrap();
+
+        assert_eq!(
+            "timestamp,value,__name__,job\n\
+             1435781430.781,1,up,node\n\
+             1435781445.781,NaN,up,node\n\
+             1435781430.781,-Inf,up,\"a,\"\"b\"\"\"\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_write_selected_labels_and_rfc3339_timestamps() {
+        let options = ExportOptions::new()
+            .labels(LabelColumns::Only(vec![
+                "job".to_owned(),
+                "instance".to_owned(),
+            ]))
+            .timestamp_format(TimestampFormat::Rfc3339);
+
+        let mut out = Vec::new();
+        write_csv(&mut out, &matrix(), &options).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(Some("timestamp,value,job,instance"), lines.next());
+        assert_eq!(Some("2015-07-01T20:10:30.781Z,1,node,"), lines.next());
+    }
+
+    #[test]
+    fn should_write_scalar_and_string_as_csv() {
+        let options = ExportOptions::new().timestamp_format(TimestampFormat::EpochMillis);
+
+        let mut out = Vec::new();
+        let scalar = Expression::Scalar(Sample {
+            epoch: 1435781451.781,
+            value: std::f64::INFINITY,
+        });
+        write_csv(&mut out, &scalar, &options).unwrap();
+        assert_eq!(
+            "timestamp,value\n1435781451781,Inf\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        let string = Expression::String(StringSample {
+            epoch: 1435781451.781,
+            value: "line\nbreak".to_owned(),
+        });
+        write_csv(&mut out, &string, &options).unwrap();
+        assert_eq!(
+            "timestamp,value\n1435781451781,\"line\nbreak\"\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_write_matrix_as_json_lines() {
+        let options = ExportOptions::new()
+            .labels(LabelColumns::Only(vec!["job".to_owned()]))
+            .timestamp_format(TimestampFormat::EpochMillis);
+
+        let mut out = Vec::new();
+        write_json_lines(&mut out, &matrix(), &options).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                serde_json::json!({"metric": {"job": "node"}, "timestamp": 1435781430781i64, "value": 1.0}),
+                serde_json::json!({"metric": {"job": "node"}, "timestamp": 1435781445781i64, "value": "NaN"}),
+                serde_json::json!({"metric": {"job": "a,\"b\""}, "timestamp": 1435781430781i64, "value": "-Inf"}),
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Series builders shared by unit tests.
+
+use crate::messages::{Metric, Range, Sample};
+
+pub(crate) fn metric(labels: &[(&str, &str)]) -> Metric {
+    Metric {
+        labels: labels
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect(),
+    }
+}
+
+/// Series with `labels` and `(epoch, value)` samples.
+pub(crate) fn range(labels: &[(&str, &str)], samples: &[(f64, f64)]) -> Range {
+    Range {
+        metric: metric(labels),
+        samples: samples
+            .iter()
+            .map(|&(epoch, value)| Sample { epoch, value })
+            .collect(),
+    }
+}
+
+/// Like [range], with whole-second `(epoch, value)` samples.
+pub(crate) fn range_secs(labels: &[(&str, &str)], samples: &[(i64, f64)]) -> Range {
+    Range {
+        metric: metric(labels),
+        samples: samples
+            .iter()
+            .map(|&(epoch, value)| Sample {
+                epoch: epoch as f64,
+                value,
+            })
+            .collect(),
+    }
+}
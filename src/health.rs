@@ -0,0 +1,416 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Target health reports built from the [targets](crate::PromClient::targets) API.
+//!
+//! A [TargetHealthReport] groups active targets by job, counts them by
+//! health, clusters similar scrape errors together and lists targets
+//! whose last scrape is older than a multiple of their scrape interval.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::messages::{ActiveTarget, ApiResult, Data, TargetHealth, Targets};
+use crate::{Error, PromClient, Result, TargetState};
+
+/// Default multiple of the scrape interval after which a target is stale.
+pub const DEFAULT_STALE_FACTOR: u32 = 3;
+
+/// Health of all active targets, grouped by job.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetHealthReport {
+    /// Time the report was computed at, used to find stale targets.
+    pub at: DateTime<Utc>,
+    /// Per-job health, sorted by job name.
+    pub jobs: Vec<JobHealth>,
+    /// Targets whose last scrape is older than `stale_factor` scrape intervals.
+    /// Targets that haven't been scraped yet are never stale.
+    pub stale: Vec<StaleTarget>,
+}
+
+/// Health of the active targets of a single job.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobHealth {
+    /// Value of the `job` label, or the scrape pool if the label is missing.
+    pub job: String,
+    pub up: usize,
+    pub down: usize,
+    pub unknown: usize,
+    /// Scrape errors grouped by [error_pattern], most common first.
+    pub errors: Vec<ErrorCluster>,
+}
+
+/// Scrape errors that only differ in addresses, URLs or numbers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorCluster {
+    /// The error with variable parts replaced, see [error_pattern].
+    pub pattern: String,
+    /// Scrape URLs of the targets reporting this error.
+    pub targets: Vec<Url>,
+}
+
+/// A target that hasn't been scraped recently enough.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaleTarget {
+    pub job: String,
+    pub scrape_url: Url,
+    pub last_scrape: DateTime<Utc>,
+    pub scrape_interval: Duration,
+}
+
+impl JobHealth {
+    pub fn total(&self) -> usize {
+        self.up + self.down + self.unknown
+    }
+
+    /// Fraction of the job's targets that are down, between 0 and 1.
+    pub fn fraction_down(&self) -> f64 {
+        if self.total() == 0 {
+            0.0
+        } else {
+            self.down as f64 / self.total() as f64
+        }
+    }
+}
+
+impl TargetHealthReport {
+    /// Build a report from the active targets in `targets`.
+    ///
+    /// A target is stale if its last scrape is more than `stale_factor`
+    /// times its scrape interval before `at`. Targets without a scrape
+    /// interval, which older Prometheus versions don't report, are never stale.
+    pub fn new(targets: &Targets, at: DateTime<Utc>, stale_factor: u32) -> TargetHealthReport {
+        let mut jobs: BTreeMap<String, (JobHealth, BTreeMap<String, Vec<Url>>)> = BTreeMap::new();
+        let mut stale = Vec::new();
+
+        for target in &targets.active {
+            let job = job_name(target);
+            let (health, errors) = jobs.entry(job.clone()).or_insert_with(|| {
+                (
+                    JobHealth {
+                        job: job.clone(),
+                        up: 0,
+                        down: 0,
+                        unknown: 0,
+                        errors: Vec::new(),
+                    },
+                    BTreeMap::new(),
+                )
+            });
+
+            match target.health {
+                TargetHealth::Up => health.up += 1,
+                TargetHealth::Down => health.down += 1,
+                TargetHealth::Unknown => health.unknown += 1,
+            }
+            if let Some(err) = &target.last_error {
+                errors
+                    .entry(error_pattern(err))
+                    .or_insert_with(Vec::new)
+                    .push(target.scrape_url.clone());
+            }
+
+            // unknown targets haven't been scraped yet, so `last_scrape`
+            // is the zero time rather than a real scrape
+            let interval = match (&target.health, target.scrape_interval) {
+                (TargetHealth::Unknown, _) => None,
+                (_, interval) => interval,
+            };
+            if let Some(interval) = interval {
+                let last_scrape = target.last_scrape.with_timezone(&Utc);
+                // a max age too large to represent is never exceeded
+                let is_stale = match (
+                    interval.checked_mul(stale_factor),
+                    at.signed_duration_since(last_scrape).to_std(),
+                ) {
+                    (Some(max_age), Ok(age)) => age > max_age,
+                    _ => false,
+                };
+                if is_stale {
+                    stale.push(StaleTarget {
+                        job,
+                        scrape_url: target.scrape_url.clone(),
+                        last_scrape,
+                        scrape_interval: interval,
+                    });
+                }
+            }
+        }
+
+        let jobs = jobs
+            .into_iter()
+            .map(|(_, (mut health, errors))| {
+                health.errors = errors
+                    .into_iter()
+                    .map(|(pattern, targets)| ErrorCluster { pattern, targets })
+                    .collect();
+                // stable, so equally common errors stay sorted by pattern
+                health.errors.sort_by_key(|c| Reverse(c.targets.len()));
+                health
+            })
+            .collect();
+
+        TargetHealthReport { at, jobs, stale }
+    }
+
+    pub fn job(&self, job: &str) -> Option<&JobHealth> {
+        self.jobs.iter().find(|j| j.job == job)
+    }
+}
+
+impl Display for TargetHealthReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for job in &self.jobs {
+            writeln!(
+                f,
+                "{}: {}/{} down ({:.1}%)",
+                job.job,
+                job.down,
+                job.total(),
+                job.fraction_down() * 100.0
+            )?;
+            for cluster in &job.errors {
+                writeln!(f, "  {} x {}", cluster.targets.len(), cluster.pattern)?;
+            }
+        }
+        if !self.stale.is_empty() {
+            writeln!(f, "stale:")?;
+            for target in &self.stale {
+                writeln!(
+                    f,
+                    "  {} {} last scraped {}",
+                    target.job,
+                    target.scrape_url,
+                    target.last_scrape.to_rfc3339()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn job_name(target: &ActiveTarget) -> String {
+    target
+        .labels
+        .get("job")
+        .cloned()
+        .unwrap_or_else(|| target.scrape_pool.clone())
+}
+
+/// Normalize a scrape error so that the same failure on different
+/// targets groups together.
+///
+/// URLs become `<url>` and any other word containing a digit (addresses,
+/// ports, byte counts, durations) becomes `<*>`, e.g.
+/// `Get "http://10.0.0.1:9100/metrics": dial tcp 10.0.0.1:9100: connect: connection refused`
+/// becomes `Get "<url>": dial tcp <*>: connect: connection refused`.
+pub fn error_pattern(err: &str) -> String {
+    err.split_whitespace()
+        .map(|word| {
+            let start = word.find(|c: char| c.is_alphanumeric()).unwrap_or(0);
+            let end = word
+                .rfind(|c: char| c.is_alphanumeric() || c == '/')
+                .map(|i| i + 1)
+                .unwrap_or_else(|| word.len())
+                .max(start);
+            let (prefix, token, suffix) = (&word[..start], &word[start..end], &word[end..]);
+            if token.starts_with("http://") || token.starts_with("https://") {
+                format!("{}<url>{}", prefix, suffix)
+            } else if token.chars().any(|c| c.is_ascii_digit()) {
+                format!("{}<*>{}", prefix, suffix)
+            } else {
+                word.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
+    /// Fetch the active targets and build a [TargetHealthReport] as of now.
+    pub async fn target_health(&mut self, stale_factor: u32) -> Result<TargetHealthReport> {
+        let targets = match await!(self.targets(Some(TargetState::Active), None))? {
+            ApiResult::ApiOk(ok) => match ok.data {
+                Some(Data::Targets(targets)) => targets,
+                _ => {
+                    return Err(Error::new_unexpected_response_error(
+                        "targets response has no targets",
+                    ));
+                }
+            },
+            ApiResult::ApiErr(err) => return Err(Error::new_api_error(err)),
+        };
+
+        Ok(TargetHealthReport::new(&targets, Utc::now(), stale_factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use chrono::{DateTime, Utc};
+    use serde_json::Map;
+    use url::Url;
+
+    use crate::health::{error_pattern, TargetHealthReport};
+    use crate::messages::{ActiveTarget, TargetHealth, Targets};
+
+    fn target(
+        job: &str,
+        instance: &str,
+        health: TargetHealth,
+        last_error: Option<String>,
+        last_scrape: &str,
+    ) -> ActiveTarget {
+        let mut labels = HashMap::new();
+        labels.insert("job".to_owned(), job.to_owned());
+        labels.insert("instance".to_owned(), instance.to_owned());
+        ActiveTarget {
+            discovered_labels: HashMap::new(),
+            labels,
+            scrape_url: Url::parse(&format!("http://{}/metrics", instance)).unwrap(),
+            last_error,
+            last_scrape: DateTime::parse_from_rfc3339(last_scrape).unwrap(),
+            health,
+            scrape_pool: job.to_owned(),
+            global_url: None,
+            last_scrape_duration: None,
+            scrape_interval: Some(Duration::from_secs(15)),
+            scrape_timeout: Some(Duration::from_secs(10)),
+            extra: Map::new(),
+        }
+    }
+
+    fn refused(instance: &str) -> Option<String> {
+        Some(format!(
+            "Get \"http://{0}/metrics\": dial tcp {0}: connect: connection refused",
+            instance
+        ))
+    }
+
+    #[test]
+    fn should_normalize_error_patterns() {
+        assert_eq!(
+            "Get \"<url>\": dial tcp <*>: connect: connection refused",
+            error_pattern(refused("10.0.0.1:9100").as_ref().unwrap())
+        );
+        assert_eq!(
+            "body size limit exceeded (<*> bytes)",
+            error_pattern("body size limit exceeded (1048576 bytes)")
+        );
+    }
+
+    #[test]
+    fn should_group_targets_by_job() {
+        let now = "2019-06-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let targets = Targets {
+            active: vec![
+                target("node", "10.0.0.1:9100", TargetHealth::Up, None, "2019-06-01T11:59:55Z"),
+                target(
+                    "node",
+                    "10.0.0.2:9100",
+                    TargetHealth::Down,
+                    refused("10.0.0.2:9100"),
+                    "2019-06-01T11:59:55Z",
+                ),
+                target(
+                    "node",
+                    "10.0.0.3:9100",
+                    TargetHealth::Down,
+                    refused("10.0.0.3:9100"),
+                    "2019-06-01T11:59:55Z",
+                ),
+                target(
+                    "node",
+                    "10.0.0.4:9100",
+                    TargetHealth::Down,
+                    Some("context deadline exceeded".to_owned()),
+                    "2019-06-01T11:59:55Z",
+                ),
+                target(
+                    "prometheus",
+                    "localhost:9090",
+                    TargetHealth::Up,
+                    None,
+                    "2019-06-01T11:50:00Z",
+                ),
+            ],
+            dropped: Vec::new(),
+            extra: Map::new(),
+        };
+
+        let report = TargetHealthReport::new(&targets, now, 3);
+        assert_eq!(
+            vec!["node", "prometheus"],
+            report.jobs.iter().map(|j| j.job.as_str()).collect::<Vec<_>>()
+        );
+
+        let node = report.job("node").unwrap();
+        assert_eq!((1, 3, 4), (node.up, node.down, node.total()));
+        assert_eq!(0.75, node.fraction_down());
+        assert_eq!(2, node.errors.len());
+        assert_eq!(2, node.errors[0].targets.len());
+        assert_eq!("context deadline exceeded", node.errors[1].pattern);
+
+        assert_eq!(0.0, report.job("prometheus").unwrap().fraction_down());
+        assert_eq!(1, report.stale.len());
+        assert_eq!("prometheus", report.stale[0].job);
+    }
+
+    #[test]
+    fn should_never_be_stale_when_max_age_overflows() {
+        let now = "2019-06-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut t = target(
+            "node",
+            "10.0.0.1:9100",
+            TargetHealth::Up,
+            None,
+            "2000-01-01T00:00:00Z",
+        );
+        t.scrape_interval = Some(Duration::from_secs(std::u64::MAX));
+        let targets = Targets {
+            active: vec![t],
+            dropped: Vec::new(),
+            extra: Map::new(),
+        };
+        assert!(TargetHealthReport::new(&targets, now, 3).stale.is_empty());
+    }
+
+    #[test]
+    fn should_never_report_unscraped_targets_as_stale() {
+        let now = "2019-06-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let targets = Targets {
+            active: vec![target(
+                "node",
+                "10.0.0.1:9100",
+                TargetHealth::Unknown,
+                None,
+                "0001-01-01T00:00:00Z",
+            )],
+            dropped: Vec::new(),
+            extra: Map::new(),
+        };
+        let report = TargetHealthReport::new(&targets, now, 3);
+        assert!(report.stale.is_empty());
+        assert_eq!(1, report.job("node").unwrap().unknown);
+    }
+}
@@ -19,11 +19,39 @@
 #![feature(custom_attribute)]
 #![feature(futures_api, async_await, await_macro)]
 
-pub use client::{PromClient, Step};
+pub use alertmanager::AlertmanagerClient;
+pub use client::{PromClient, Step, TargetState};
+pub use columnar::RangeColumns;
 pub use error::{Error, Result};
+pub use matcher::{LabelMatcher, MatchOp};
+pub use options::{MimirOptions, QueryOptions, ThanosEngine, ThanosOptions};
+pub use pushgateway::PushgatewayClient;
+#[cfg(feature = "victoriametrics")]
+pub use streaming::ChunkStream;
+pub use streaming::RangeStream;
+pub use units::PromDuration;
 
+pub mod alertmanager;
 mod client;
+pub mod columnar;
+#[cfg(feature = "config")]
+pub mod config;
 mod error;
+pub mod export;
+#[cfg(test)]
+mod fixtures;
+pub mod health;
+pub mod matcher;
 pub mod messages;
+pub mod options;
+pub mod promql;
+pub mod pushgateway;
+pub mod snapshot;
+pub mod split;
+mod streaming;
+mod transport;
+pub mod units;
+#[cfg(feature = "victoriametrics")]
+pub mod victoriametrics;
 
 // FIXME: remove need to have 'to_owned()' everywhere
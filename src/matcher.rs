@@ -0,0 +1,240 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Label matchers, as used in PromQL selectors like `up{job=~"node|prom"}`.
+
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+
+use regex::Regex;
+
+use crate::{Error, Result};
+
+/// Comparison performed by a [LabelMatcher].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchOp {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `=~`
+    RegexMatch,
+    /// `!~`
+    RegexNoMatch,
+}
+
+impl MatchOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MatchOp::Equal => "=",
+            MatchOp::NotEqual => "!=",
+            MatchOp::RegexMatch => "=~",
+            MatchOp::RegexNoMatch => "!~",
+        }
+    }
+}
+
+impl Display for MatchOp {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Matches the value of a single label.
+///
+/// As in Prometheus, a missing label is treated as having the empty
+/// value, and regular expressions are anchored at both ends.
+#[derive(Clone, Debug)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub op: MatchOp,
+    pub value: String,
+    // compiled `value` for regex matchers
+    regex: Option<Regex>,
+}
+
+impl LabelMatcher {
+    /// Create a matcher, compiling `value` if `op` is a regex match.
+    pub fn new<N: Into<String>, V: Into<String>>(
+        name: N,
+        op: MatchOp,
+        value: V,
+    ) -> Result<LabelMatcher> {
+        let value = value.into();
+        let regex = match op {
+            MatchOp::Equal | MatchOp::NotEqual => None,
+            MatchOp::RegexMatch | MatchOp::RegexNoMatch => {
+                let anchored = format!("^(?:{})$", value);
+                Some(
+                    Regex::new(&anchored)
+                        .map_err(|err| Error::new_invalid_regex_error(value.clone(), err))?,
+                )
+            }
+        };
+        Ok(LabelMatcher {
+            name: name.into(),
+            op,
+            value,
+            regex,
+        })
+    }
+
+    pub fn equal<N: Into<String>, V: Into<String>>(name: N, value: V) -> LabelMatcher {
+        LabelMatcher {
+            name: name.into(),
+            op: MatchOp::Equal,
+            value: value.into(),
+            regex: None,
+        }
+    }
+
+    pub fn not_equal<N: Into<String>, V: Into<String>>(name: N, value: V) -> LabelMatcher {
+        LabelMatcher {
+            name: name.into(),
+            op: MatchOp::NotEqual,
+            value: value.into(),
+            regex: None,
+        }
+    }
+
+    pub fn regex_match<N: Into<String>, V: Into<String>>(
+        name: N,
+        pattern: V,
+    ) -> Result<LabelMatcher> {
+        LabelMatcher::new(name, MatchOp::RegexMatch, pattern)
+    }
+
+    pub fn regex_no_match<N: Into<String>, V: Into<String>>(
+        name: N,
+        pattern: V,
+    ) -> Result<LabelMatcher> {
+        LabelMatcher::new(name, MatchOp::RegexNoMatch, pattern)
+    }
+
+    /// Whether a label with `value` (or `None` if absent) satisfies this matcher.
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        let value = value.unwrap_or("");
+        match self.op {
+            MatchOp::Equal => value == self.value,
+            MatchOp::NotEqual => value != self.value,
+            MatchOp::RegexMatch => self.regex.iter().any(|r| r.is_match(value)),
+            MatchOp::RegexNoMatch => !self.regex.iter().any(|r| r.is_match(value)),
+        }
+    }
+}
+
+impl PartialEq for LabelMatcher {
+    fn eq(&self, other: &LabelMatcher) -> bool {
+        self.name == other.name && self.op == other.op && self.value == other.value
+    }
+}
+
+impl Display for LabelMatcher {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write_label_name(f, &self.name)?;
+        f.write_str(self.op.as_str())?;
+        write_quoted(f, &self.value)
+    }
+}
+
+/// Whether `name` is a valid metric name, i.e. `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+pub fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Whether `name` is a valid label name, i.e. `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Write `s` as a double-quoted PromQL string literal.
+pub(crate) fn write_quoted<W: std::fmt::Write>(w: &mut W, s: &str) -> FmtResult {
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '\\' => w.write_str(r"\\")?,
+            '"' => w.write_str("\\\"")?,
+            '\n' => w.write_str(r"\n")?,
+            '\r' => w.write_str(r"\r")?,
+            '\t' => w.write_str(r"\t")?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+/// Write a label name, quoting it if it isn't a valid identifier
+/// (as allowed for UTF-8 label names since Prometheus 3.0).
+pub(crate) fn write_label_name<W: std::fmt::Write>(w: &mut W, name: &str) -> FmtResult {
+    if is_valid_label_name(name) {
+        w.write_str(name)
+    } else {
+        write_quoted(w, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+    use crate::matcher::{LabelMatcher, MatchOp};
+
+    #[test]
+    fn should_anchor_regex_matchers() {
+        let m = LabelMatcher::regex_match("job", "node|prom").unwrap();
+        assert!(m.matches(Some("node")));
+        assert!(!m.matches(Some("node-exporter")));
+        assert!(!m.matches(None));
+
+        let m = LabelMatcher::regex_no_match("job", ".+").unwrap();
+        assert!(m.matches(None));
+        assert!(!m.matches(Some("node")));
+    }
+
+    #[test]
+    fn should_treat_missing_labels_as_empty() {
+        assert!(LabelMatcher::equal("env", "").matches(None));
+        assert!(LabelMatcher::not_equal("env", "prod").matches(None));
+    }
+
+    #[test]
+    fn should_reject_invalid_regex() {
+        match LabelMatcher::new("job", MatchOp::RegexMatch, "(")
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidRegex { pattern, .. } => assert_eq!("(", pattern),
+            k => panic!("unexpected error {:?}", k),
+        }
+    }
+
+    #[test]
+    fn should_display_escaped_matchers() {
+        let m = LabelMatcher::regex_match("path", r#"C:\\tmp\\"x""#).unwrap();
+        assert_eq!(r#"path=~"C:\\\\tmp\\\\\"x\"""#, m.to_string());
+        assert_eq!(
+            r#""host.name"!="a""#,
+            LabelMatcher::not_equal("host.name", "a").to_string()
+        );
+    }
+}
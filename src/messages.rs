@@ -18,22 +18,68 @@ use std::fmt::Result as FmtResult;
 use std::fmt::{Display, Formatter};
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::time::Duration;
 
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
 use serde::{
     de,
     de::{MapAccess, SeqAccess, Unexpected, Visitor},
-    ser::{SerializeStruct, SerializeTuple},
+    ser::{SerializeMap, SerializeTuple},
     {Deserialize, Deserializer, Serialize, Serializer},
 };
+use serde_json::{Map, Value as JsonValue};
 use url::Url;
 use url_serde::{De, Ser};
 
-const PROM_INFINITY: &str = "Inf";
+use crate::matcher::{self, LabelMatcher};
+use crate::units;
 
-const PROM_NEGATIVE_INFINITY: &str = "-Inf";
+pub mod borrowed;
 
-const PROM_NAN: &str = "NaN";
+pub(crate) const PROM_INFINITY: &str = "Inf";
+
+pub(crate) const PROM_NEGATIVE_INFINITY: &str = "-Inf";
+
+pub(crate) const PROM_NAN: &str = "NaN";
+
+/// Positive infinity as Go's `strconv` writes it, e.g. in the text
+/// exposition format and `count_values` labels.
+pub(crate) const PROM_PLUS_INFINITY: &str = "+Inf";
+
+/// How Prometheus spells `v` if it isn't finite.
+pub(crate) fn non_finite_value(v: f64) -> Option<&'static str> {
+    if v.is_nan() {
+        Some(PROM_NAN)
+    } else if v == std::f64::INFINITY {
+        Some(PROM_INFINITY)
+    } else if v == std::f64::NEG_INFINITY {
+        Some(PROM_NEGATIVE_INFINITY)
+    } else {
+        None
+    }
+}
+
+/// Format `v` as the HTTP API writes sample values.
+pub(crate) fn format_value(v: f64) -> String {
+    match non_finite_value(v) {
+        Some(s) => s.to_owned(),
+        None => v.to_string(),
+    }
+}
+
+/// Like [format_value], but with positive infinity spelled
+/// [PROM_PLUS_INFINITY].
+pub(crate) fn format_plus_inf_value(v: f64) -> String {
+    if v == std::f64::INFINITY {
+        PROM_PLUS_INFINITY.to_owned()
+    } else {
+        format_value(v)
+    }
+}
+
+/// Bit pattern of the `NaN` Prometheus writes to mark a series as stale.
+/// It's distinct from the `NaN` produced by arithmetic, e.g. `0 / 0`.
+pub const STALE_NAN_BITS: u64 = 0x7ff0_0000_0000_0002;
 
 // FIXME: test all serializations
 // FIXME: create convenience functions
@@ -47,6 +93,79 @@ pub enum ApiResult {
     ApiErr(ApiErr),
 }
 
+impl ApiResult {
+    /// Warnings returned alongside the result.
+    ///
+    /// Thanos, Cortex and Mimir use these to report partial responses,
+    /// e.g. when a store or ingester could not be queried.
+    pub fn warnings(&self) -> &[String] {
+        match self {
+            ApiResult::ApiOk(ok) => &ok.warnings,
+            ApiResult::ApiErr(err) => &err.warnings,
+        }
+    }
+
+    /// Paths of response fields this version of the library doesn't
+    /// model, e.g. `activeTargets[0].scrapeClass`.
+    ///
+    /// Unknown fields are kept in the `extra` map of the struct they
+    /// appeared in, so this is empty unless the API has grown new fields.
+    pub fn unknown_fields(&self) -> Vec<String> {
+        let data = match self {
+            ApiResult::ApiOk(ok) => &ok.data,
+            ApiResult::ApiErr(err) => &err.data,
+        };
+
+        let mut fields = Vec::new();
+        match data {
+            Some(Data::Targets(targets)) => {
+                push_unknown_fields(&mut fields, "", &targets.extra);
+                for (i, t) in targets.active.iter().enumerate() {
+                    push_unknown_fields(&mut fields, &format!("activeTargets[{}].", i), &t.extra);
+                }
+                for (i, t) in targets.dropped.iter().enumerate() {
+                    push_unknown_fields(&mut fields, &format!("droppedTargets[{}].", i), &t.extra);
+                }
+            }
+            Some(Data::AlertManagers(managers)) => {
+                push_unknown_fields(&mut fields, "", &managers.extra);
+                for (i, m) in managers.active.iter().enumerate() {
+                    push_unknown_fields(
+                        &mut fields,
+                        &format!("activeAlertmanagers[{}].", i),
+                        &m.extra,
+                    );
+                }
+                for (i, m) in managers.dropped.iter().enumerate() {
+                    push_unknown_fields(
+                        &mut fields,
+                        &format!("droppedAlertmanagers[{}].", i),
+                        &m.extra,
+                    );
+                }
+            }
+            _ => {}
+        }
+        fields
+    }
+
+    /// Fail if the response has any [unknown fields](ApiResult::unknown_fields).
+    ///
+    /// Useful in tests that should notice when the API drifts from this library.
+    pub fn deny_unknown_fields(self) -> crate::Result<ApiResult> {
+        let fields = self.unknown_fields();
+        if fields.is_empty() {
+            Ok(self)
+        } else {
+            Err(crate::Error::new_unknown_fields_error(fields))
+        }
+    }
+}
+
+fn push_unknown_fields(fields: &mut Vec<String>, prefix: &str, extra: &Map<String, JsonValue>) {
+    fields.extend(extra.keys().map(|k| format!("{}{}", prefix, k)));
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct ApiOk {
     #[serde(default)]
@@ -95,7 +214,7 @@ pub enum Data {
     // Since `Flags` is a map, it captures any other map-like
     // types, including `Config`, `Snapshot`, etc. To give those
     // variants a chance to be matches this variant must be the last
-    Flags(HashMap<String, String>),
+    Flags(Flags),
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -131,12 +250,180 @@ pub struct Metric {
     pub labels: HashMap<String, String>,
 }
 
+pub(crate) const METRIC_NAME_LABEL: &str = "__name__";
+
+// FNV-1a, as used by Prometheus' `model.LabelSet.Fingerprint()`
+const FNV_OFFSET: u64 = 14_695_981_039_346_656_037;
+
+const FNV_PRIME: u64 = 1_099_511_628_211;
+
+const FINGERPRINT_SEPARATOR: u8 = 0xff;
+
+impl Metric {
+    /// The metric name, i.e. the value of the `__name__` label.
+    pub fn name(&self) -> Option<&str> {
+        self.get(METRIC_NAME_LABEL)
+    }
+
+    pub fn get(&self, label: &str) -> Option<&str> {
+        self.labels.get(label).map(String::as_str)
+    }
+
+    /// Labels as `(name, value)` pairs sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let mut labels: Vec<(&str, &str)> = self
+            .labels
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .collect();
+        labels.sort();
+        labels.into_iter()
+    }
+
+    /// Hash of the label set that is stable across processes and platforms.
+    ///
+    /// This is the same value Prometheus computes for `model.LabelSet.Fingerprint()`,
+    /// so it can be used to identify a series across queries.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        let mut add = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= u64::from(*b);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+        for (name, value) in self.iter() {
+            add(name.as_bytes());
+            add(&[FINGERPRINT_SEPARATOR]);
+            add(value.as_bytes());
+            add(&[FINGERPRINT_SEPARATOR]);
+        }
+        hash
+    }
+
+    /// Whether this metric satisfies all `matchers`.
+    pub fn matches(&self, matchers: &[LabelMatcher]) -> bool {
+        matchers.iter().all(|m| m.matches(self.get(&m.name)))
+    }
+
+    /// A copy of this metric without the given labels.
+    pub fn without<S: AsRef<str>>(&self, labels: &[S]) -> Metric {
+        Metric {
+            labels: self
+                .labels
+                .iter()
+                .filter(|(n, _)| !labels.iter().any(|l| l.as_ref() == n.as_str()))
+                .map(|(n, v)| (n.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    /// A copy of this metric with only the given labels.
+    pub fn only<S: AsRef<str>>(&self, labels: &[S]) -> Metric {
+        Metric {
+            labels: self
+                .labels
+                .iter()
+                .filter(|(n, _)| labels.iter().any(|l| l.as_ref() == n.as_str()))
+                .map(|(n, v)| (n.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    /// Render a PromQL selector for this series, e.g. `up{job="node"}`.
+    ///
+    /// Labels are sorted by name and values are escaped. Metric names that
+    /// aren't valid identifiers are rendered as a `__name__` matcher instead.
+    ///
+    /// A metric without labels, such as the result of `sum(up)`, renders as
+    /// `{}`. That is a useful series name but not a valid PromQL selector.
+    pub fn to_selector(&self) -> String {
+        let name = self.name().filter(|n| matcher::is_valid_metric_name(n));
+
+        let mut selector = String::new();
+        if let Some(name) = name {
+            selector.push_str(name);
+        }
+        selector.push('{');
+        let labels = self
+            .iter()
+            .filter(|(n, _)| name.is_none() || *n != METRIC_NAME_LABEL);
+        for (i, (n, v)) in labels.enumerate() {
+            if i > 0 {
+                selector.push(',');
+            }
+            // writing to a String cannot fail
+            let _ = matcher::write_label_name(&mut selector, n);
+            selector.push('=');
+            let _ = matcher::write_quoted(&mut selector, v);
+        }
+        selector.push('}');
+        selector
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Sample {
     pub epoch: f64,
     pub value: f64,
 }
 
+impl Sample {
+    /// Sample time in UTC.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `epoch` is outside the range `DateTime` can represent.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        timestamp_from_millis(self.timestamp_millis())
+    }
+
+    /// Sample time in milliseconds since the Unix epoch.
+    ///
+    /// Prometheus timestamps have millisecond precision, so this is exact.
+    pub fn timestamp_millis(&self) -> i64 {
+        epoch_to_millis(self.epoch)
+    }
+
+    /// Whether this is a staleness marker, i.e. the series disappeared at this time.
+    ///
+    /// The HTTP API writes every `NaN` as the string `"NaN"`, so samples
+    /// decoded from an API response are never stale markers. This only
+    /// detects markers in samples built locally with [STALE_NAN_BITS].
+    pub fn is_stale(&self) -> bool {
+        self.value.to_bits() == STALE_NAN_BITS
+    }
+}
+
+/// Convert an epoch in fractional seconds to whole milliseconds.
+///
+/// Prometheus timestamps have millisecond precision, so rounding
+/// recovers the exact value the server sent.
+pub fn epoch_to_millis(epoch: f64) -> i64 {
+    (epoch * 1000.0).round() as i64
+}
+
+/// Convert milliseconds to an epoch in fractional seconds.
+///
+/// The division is correctly rounded, so this returns the same `f64`
+/// that parsing the server's decimal timestamp produces.
+pub fn millis_to_epoch(millis: i64) -> f64 {
+    millis as f64 / 1000.0
+}
+
+pub(crate) fn timestamp_from_millis(millis: i64) -> DateTime<Utc> {
+    // Explicitly unwrapping here because, as with chrono's own
+    // `timestamp` constructors, an out-of-range time is a caller bug
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .expect("Sample timestamp out of range")
+}
+
+/// Round a decimal epoch parsed from JSON to exactly the millisecond it denotes.
+fn normalize_epoch(epoch: f64) -> f64 {
+    millis_to_epoch(epoch_to_millis(epoch))
+}
+
 impl<'de> Deserialize<'de> for Sample {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
     where
@@ -158,6 +445,7 @@ impl<'de> Deserialize<'de> for Sample {
                 let epoch = seq
                     .next_element::<f64>()?
                     .ok_or_else(|| de::Error::missing_field("sample time"))?;
+                let epoch = normalize_epoch(epoch);
                 let value = seq
                     .next_element::<&str>()?
                     .ok_or_else(|| de::Error::missing_field("sample value"))?;
@@ -197,6 +485,18 @@ pub struct StringSample {
     pub value: String,
 }
 
+impl StringSample {
+    /// Sample time in UTC. Panics like [Sample::timestamp].
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        timestamp_from_millis(self.timestamp_millis())
+    }
+
+    /// Sample time in milliseconds since the Unix epoch.
+    pub fn timestamp_millis(&self) -> i64 {
+        epoch_to_millis(self.epoch)
+    }
+}
+
 impl<'de> Deserialize<'de> for StringSample {
     fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
     where
@@ -218,6 +518,7 @@ impl<'de> Deserialize<'de> for StringSample {
                 let epoch = seq
                     .next_element::<f64>()?
                     .ok_or_else(|| de::Error::missing_field("sample time"))?;
+                let epoch = normalize_epoch(epoch);
                 let value = seq
                     .next_element::<String>()?
                     .ok_or_else(|| de::Error::missing_field("sample value"))?;
@@ -248,13 +549,17 @@ pub struct Series(Vec<Metric>);
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct LabelsOrValues(Vec<String>);
 
+// `activeTargets` is required (Prometheus always sends it, even when
+// filtering by state) so that other map-like `Data` variants don't match
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Targets {
-    #[serde(default, rename = "activeTargets")]
+    #[serde(rename = "activeTargets")]
     pub active: Vec<ActiveTarget>,
     #[serde(default, rename = "droppedTargets")]
     pub dropped: Vec<DroppedTarget>,
+    /// Fields this version of the library doesn't know about.
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -279,6 +584,31 @@ pub struct ActiveTarget {
         serialize_with = "serialize_health"
     )]
     pub health: TargetHealth,
+    #[serde(default)]
+    pub scrape_pool: String,
+    #[serde(default, with = "url_serde")]
+    pub global_url: Option<Url>,
+    #[serde(
+        default,
+        deserialize_with = "seconds_to_duration",
+        serialize_with = "duration_to_seconds"
+    )]
+    pub last_scrape_duration: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "prom_duration_to_duration",
+        serialize_with = "duration_to_prom_duration"
+    )]
+    pub scrape_interval: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "prom_duration_to_duration",
+        serialize_with = "duration_to_prom_duration"
+    )]
+    pub scrape_timeout: Option<Duration>,
+    /// Fields this version of the library doesn't know about.
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -318,6 +648,50 @@ fn date_time_to_rfc3339<S: Serializer>(
     serializer.serialize_str(&v.to_rfc3339())
 }
 
+fn seconds_to_duration<'de, D: Deserializer<'de>>(d: D) -> StdResult<Option<Duration>, D::Error> {
+    let o: Option<f64> = Option::deserialize(d)?;
+    match o {
+        Some(secs) if secs >= 0.0 && secs < std::u64::MAX as f64 => {
+            let whole = secs.trunc();
+            let nanos = ((secs - whole) * 1e9) as u32;
+            Ok(Some(Duration::new(whole as u64, nanos)))
+        }
+        Some(secs) => Err(de::Error::invalid_value(
+            Unexpected::Float(secs),
+            &"a non-negative number of seconds",
+        )),
+        None => Ok(None),
+    }
+}
+
+fn duration_to_seconds<S: Serializer>(
+    v: &Option<Duration>,
+    serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    match v {
+        Some(d) => serializer.serialize_f64(d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn prom_duration_to_duration<'de, D: Deserializer<'de>>(
+    d: D,
+) -> StdResult<Option<Duration>, D::Error> {
+    let o: Option<String> = Option::deserialize(d)?;
+    o.map(|s| units::parse_duration(&s).map_err(de::Error::custom))
+        .transpose()
+}
+
+fn duration_to_prom_duration<S: Serializer>(
+    v: &Option<Duration>,
+    serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    match v {
+        Some(d) => serializer.serialize_str(&units::format_duration(*d)),
+        None => serializer.serialize_none(),
+    }
+}
+
 fn deserialize_health<'de, D: Deserializer<'de>>(d: D) -> StdResult<TargetHealth, D::Error> {
     let o: Option<String> = Option::deserialize(d)?;
     Ok(o.map_or(TargetHealth::Unknown, |s| match s.as_str() {
@@ -344,20 +718,28 @@ fn serialize_health<S: Serializer>(
 #[serde(rename_all = "camelCase")]
 pub struct DroppedTarget {
     pub discovered_labels: HashMap<String, String>,
+    /// Fields this version of the library doesn't know about.
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
 }
 
+// `activeAlertmanagers` is required for the same reason as `activeTargets`
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct AlertManagers {
-    #[serde(default, rename = "activeAlertmanagers")]
+    #[serde(rename = "activeAlertmanagers")]
     pub active: Vec<AlertManager>,
     #[serde(default, rename = "droppedAlertmanagers")]
     pub dropped: Vec<AlertManager>,
+    /// Fields this version of the library doesn't know about.
+    #[serde(flatten)]
+    pub extra: Map<String, JsonValue>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct AlertManager {
     pub url: Url,
+    /// Fields this version of the library doesn't know about.
+    pub extra: Map<String, JsonValue>,
 }
 
 impl<'de> Deserialize<'de> for AlertManager {
@@ -369,12 +751,6 @@ impl<'de> Deserialize<'de> for AlertManager {
 
         struct VisitorImpl;
 
-        #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field {
-            Url,
-        };
-
         const FIELDS: &[&str] = &["url"];
 
         impl<'de> Visitor<'de> for VisitorImpl {
@@ -389,18 +765,19 @@ impl<'de> Deserialize<'de> for AlertManager {
                 V: MapAccess<'de>,
             {
                 let mut url: Option<Url> = None;
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        Field::Url => {
-                            if url.is_some() {
-                                return Err(de::Error::duplicate_field("url"));
-                            }
-                            url = De::into_inner(map.next_value()?); // FIXME: how does this work??!
+                let mut extra = Map::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "url" {
+                        if url.is_some() {
+                            return Err(de::Error::duplicate_field("url"));
                         }
+                        url = De::into_inner(map.next_value()?); // FIXME: how does this work??!
+                    } else {
+                        extra.insert(key, map.next_value()?);
                     }
                 }
                 let url = url.ok_or_else(|| de::Error::missing_field("url"))?;
-                Ok(AlertManager { url })
+                Ok(AlertManager { url, extra })
             }
         }
 
@@ -413,34 +790,107 @@ impl Serialize for AlertManager {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("AlertManager", 1)?;
-        s.serialize_field("url", &Ser::new(&self.url))?;
+        let mut s = serializer.serialize_map(Some(1 + self.extra.len()))?;
+        s.serialize_entry("url", &Ser::new(&self.url))?;
+        for (k, v) in &self.extra {
+            s.serialize_entry(k, v)?;
+        }
         s.end()
     }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Snapshot {
-    name: String,
+    /// Directory name of the snapshot under `<data dir>/snapshots`.
+    pub name: String,
+}
+
+/// Command-line flags Prometheus was started with, as returned by `/api/v1/status/flags`.
+///
+/// Values are strings. The typed accessors parse the flags most often
+/// inspected, returning `Ok(None)` when the flag isn't present.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Flags(pub HashMap<String, String>);
+
+impl Flags {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Parse flag `name` as a Prometheus duration, e.g. `5m`.
+    pub fn duration(&self, name: &str) -> crate::Result<Option<Duration>> {
+        self.get(name).map(units::parse_duration).transpose()
+    }
+
+    /// Parse flag `name` as a Prometheus byte size, e.g. `512MB`.
+    pub fn byte_size(&self, name: &str) -> crate::Result<Option<u64>> {
+        self.get(name).map(units::parse_byte_size).transpose()
+    }
+
+    /// Parse flag `name` with `FromStr`, e.g. as a number or boolean.
+    pub fn parse<T: FromStr>(&self, name: &str) -> crate::Result<Option<T>> {
+        self.get(name)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|_| crate::Error::new_invalid_flag_error(name, v))
+            })
+            .transpose()
+    }
+
+    /// `storage.tsdb.retention.time`. Zero means the time limit isn't set.
+    pub fn retention_time(&self) -> crate::Result<Option<Duration>> {
+        self.duration("storage.tsdb.retention.time")
+    }
+
+    /// `storage.tsdb.retention.size` in bytes. Zero means the size limit isn't set.
+    pub fn retention_size(&self) -> crate::Result<Option<u64>> {
+        self.byte_size("storage.tsdb.retention.size")
+    }
+
+    /// `query.lookback-delta`, how far back an instant query looks for samples.
+    pub fn query_lookback_delta(&self) -> crate::Result<Option<Duration>> {
+        self.duration("query.lookback-delta")
+    }
+
+    /// `query.timeout`
+    pub fn query_timeout(&self) -> crate::Result<Option<Duration>> {
+        self.duration("query.timeout")
+    }
+
+    /// `query.max-concurrency`
+    pub fn query_max_concurrency(&self) -> crate::Result<Option<usize>> {
+        self.parse("query.max-concurrency")
+    }
+
+    /// `query.max-samples`
+    pub fn query_max_samples(&self) -> crate::Result<Option<usize>> {
+        self.parse("query.max-samples")
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Config {
-    yaml: String,
+    /// Loaded configuration file, as YAML.
+    pub yaml: String,
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use std::result::Result as StdResult;
+    use std::time::Duration;
 
-    use chrono::{DateTime, FixedOffset};
+    use chrono::{DateTime, FixedOffset, Utc};
+    use serde_json::{json, Map};
     use url::Url;
 
+    use crate::error::ErrorKind;
+    use crate::fixtures::metric;
+    use crate::matcher::LabelMatcher;
     use crate::messages::{
         ActiveTarget, AlertManager, AlertManagers, ApiErr, ApiOk, ApiResult, Config, Data,
-        DroppedTarget, Expression, Instant, LabelsOrValues, Metric, Range, Sample, Series,
-        Snapshot, StringSample, TargetHealth, Targets,
+        DroppedTarget, Expression, Flags, Instant, LabelsOrValues, Metric, Range, Sample, Series,
+        Snapshot, StringSample, TargetHealth, Targets, STALE_NAN_BITS,
     };
 
     #[test]
@@ -967,7 +1417,12 @@ mod tests {
                         "scrapeUrl": "http://127.0.0.1:9090/metrics",
                         "lastError": "",
                         "lastScrape": "2017-01-17T15:07:44.723715405+01:00",
-                        "health": "up"
+                        "health": "up",
+                        "scrapePool": "prometheus",
+                        "globalUrl": "http://prometheus.example.com:9090/metrics",
+                        "lastScrapeDuration": 0.050688943,
+                        "scrapeInterval": "1m",
+                        "scrapeTimeout": "10s"
                     }
                 ],
                 "droppedTargets": [
@@ -1017,11 +1472,21 @@ mod tests {
                         scrape_url: Url::parse("http://127.0.0.1:9090/metrics").unwrap(),
                         last_error: None,
                         last_scrape,
-                        health: TargetHealth::Up
+                        health: TargetHealth::Up,
+                        scrape_pool: "prometheus".to_owned(),
+                        global_url: Some(
+                            Url::parse("http://prometheus.example.com:9090/metrics").unwrap()
+                        ),
+                        last_scrape_duration: Some(Duration::from_nanos(50_688_943)),
+                        scrape_interval: Some(Duration::from_secs(60)),
+                        scrape_timeout: Some(Duration::from_secs(10)),
+                        extra: Map::new(),
                     },],
                     dropped: vec![DroppedTarget {
-                        discovered_labels: dropped_discovered_labels
+                        discovered_labels: dropped_discovered_labels,
+                        extra: Map::new(),
                     },],
+                    extra: Map::new(),
                 })),
                 warnings: Vec::new(),
             })
@@ -1056,10 +1521,13 @@ mod tests {
                 data: Some(Data::AlertManagers(AlertManagers {
                     active: vec![AlertManager {
                         url: Url::parse("http://127.0.0.1:9090/api/v1/alerts").unwrap(),
+                        extra: Map::new(),
                     },],
                     dropped: vec![AlertManager {
                         url: Url::parse("http://127.0.0.1:9093/api/v1/alerts").unwrap(),
+                        extra: Map::new(),
                     },],
+                    extra: Map::new(),
                 })),
                 warnings: Vec::new(),
             }),
@@ -1069,6 +1537,85 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn should_retain_unknown_fields() -> StdResult<(), std::io::Error> {
+        let j = r#"
+        {
+            "status": "success",
+            "data": {
+                "activeAlertmanagers": [
+                    {
+                        "url": "http://127.0.0.1:9090/api/v1/alerts",
+                        "health": "up"
+                    }
+                ],
+                "droppedAlertmanagers": [],
+                "total": 1
+            }
+        }
+        "#;
+
+        let res = serde_json::from_str::<ApiResult>(j)?;
+        match &res {
+            ApiResult::ApiOk(ApiOk {
+                data: Some(Data::AlertManagers(managers)),
+                ..
+            }) => {
+                assert_eq!(Some(&json!(1)), managers.extra.get("total"));
+                assert_eq!(Some(&json!("up")), managers.active[0].extra.get("health"));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            vec!["total", "activeAlertmanagers[0].health"],
+            res.unknown_fields()
+        );
+
+        let reserialized = serde_json::to_value(&res)?;
+        assert_eq!(
+            json!("up"),
+            reserialized["data"]["activeAlertmanagers"][0]["health"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_deny_unknown_fields_in_strict_mode() -> StdResult<(), std::io::Error> {
+        let j = r#"
+        {
+            "status": "success",
+            "data": {
+                "activeTargets": [],
+                "droppedTargets": [
+                    {
+                        "discoveredLabels": {},
+                        "scrapePool": "node"
+                    }
+                ]
+            }
+        }
+        "#;
+
+        match serde_json::from_str::<ApiResult>(j)?
+            .deny_unknown_fields()
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::UnknownFields { fields } => {
+                assert_eq!(&vec!["droppedTargets[0].scrapePool".to_owned()], fields)
+            }
+            k => panic!("unexpected error {:?}", k),
+        }
+
+        let j = r#"{"status": "success", "data": {"activeTargets": []}}"#;
+        assert!(serde_json::from_str::<ApiResult>(j)?
+            .deny_unknown_fields()
+            .is_ok());
+
+        Ok(())
+    }
+
     #[test]
     fn should_deserialize_json_prom_flags() -> StdResult<(), std::io::Error> {
         let j = r#"
@@ -1098,7 +1645,7 @@ mod tests {
         let res = serde_json::from_str::<ApiResult>(j)?;
         assert_eq!(
             ApiResult::ApiOk(ApiOk {
-                data: Some(Data::Flags(flags)),
+                data: Some(Data::Flags(Flags(flags))),
                 warnings: Vec::new(),
             }),
             res
@@ -1107,6 +1654,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn should_parse_typed_flags() {
+        let mut flags: HashMap<String, String> = HashMap::new();
+        flags.insert("storage.tsdb.retention.time".to_owned(), "15d".to_owned());
+        flags.insert("storage.tsdb.retention.size".to_owned(), "512MB".to_owned());
+        flags.insert("query.lookback-delta".to_owned(), "5m".to_owned());
+        flags.insert("query.max-concurrency".to_owned(), "20".to_owned());
+        flags.insert("query.max-samples".to_owned(), "lots".to_owned());
+        let flags = Flags(flags);
+
+        assert_eq!(
+            Some(Duration::from_secs(15 * 24 * 60 * 60)),
+            flags.retention_time().unwrap()
+        );
+        assert_eq!(Some(512 * 1024 * 1024), flags.retention_size().unwrap());
+        assert_eq!(
+            Some(Duration::from_secs(300)),
+            flags.query_lookback_delta().unwrap()
+        );
+        assert_eq!(Some(20), flags.query_max_concurrency().unwrap());
+        assert_eq!(None, flags.query_timeout().unwrap());
+        assert!(flags.query_max_samples().is_err());
+    }
+
     #[test]
     fn should_deserialize_json_prom_snapshot() -> StdResult<(), std::io::Error> {
         let j = r#"
@@ -1186,4 +1757,89 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn should_convert_sample_time_with_millisecond_precision() -> StdResult<(), std::io::Error> {
+        let s = serde_json::from_str::<Sample>(r#"[1435781430.781, "1"]"#)?;
+        assert_eq!(1435781430781, s.timestamp_millis());
+        assert_eq!(
+            "2015-07-01T20:10:30.781Z".parse::<DateTime<Utc>>().unwrap(),
+            s.timestamp()
+        );
+
+        // 0.1 + 0.2 != 0.3 in floating point, but both denote the same millisecond
+        let s = serde_json::from_str::<Sample>(r#"[0.30000000000000004, "1"]"#)?;
+        assert_eq!(0.3, s.epoch);
+        assert_eq!(300, s.timestamp_millis());
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_stale_markers() {
+        let stale = Sample {
+            epoch: 1.0,
+            value: f64::from_bits(STALE_NAN_BITS),
+        };
+        assert!(stale.value.is_nan());
+        assert!(stale.is_stale());
+
+        let nan = Sample {
+            epoch: 1.0,
+            value: std::f64::NAN,
+        };
+        assert!(!nan.is_stale());
+    }
+
+    #[test]
+    fn should_iterate_metric_labels_in_sorted_order() {
+        let m = metric(&[("job", "node"), ("__name__", "up"), ("instance", "a:9100")]);
+        assert_eq!(Some("up"), m.name());
+        assert_eq!(Some("node"), m.get("job"));
+        assert_eq!(None, m.get("env"));
+        assert_eq!(
+            vec![("__name__", "up"), ("instance", "a:9100"), ("job", "node")],
+            m.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_fingerprint_metric_like_prometheus() {
+        assert_eq!(14695981039346656037, metric(&[]).fingerprint());
+        assert_eq!(
+            5799056148416392346,
+            metric(&[("name", "garland, briggs"), ("fear", "love is not enough")]).fingerprint()
+        );
+    }
+
+    #[test]
+    fn should_match_and_project_metric() {
+        let m = metric(&[("__name__", "up"), ("job", "node"), ("instance", "a:9100")]);
+        assert!(m.matches(&[
+            LabelMatcher::equal("__name__", "up"),
+            LabelMatcher::regex_match("job", "node|prom").unwrap(),
+            LabelMatcher::equal("env", ""),
+        ]));
+        assert!(!m.matches(&[LabelMatcher::not_equal("job", "node")]));
+
+        assert_eq!(
+            metric(&[("__name__", "up"), ("job", "node")]),
+            m.without(&["instance"])
+        );
+        assert_eq!(metric(&[("job", "node")]), m.only(&["job", "env"]));
+    }
+
+    #[test]
+    fn should_render_metric_as_selector() {
+        let m = metric(&[("__name__", "up"), ("job", "no\"de"), ("path", "C:\\tmp\n")]);
+        assert_eq!(r#"up{job="no\"de",path="C:\\tmp\n"}"#, m.to_selector());
+
+        let m = metric(&[("__name__", "http.requests"), ("service.name", "api")]);
+        assert_eq!(
+            r#"{__name__="http.requests","service.name"="api"}"#,
+            m.to_selector()
+        );
+
+        assert_eq!("{}", metric(&[]).to_selector());
+    }
 }
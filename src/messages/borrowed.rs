@@ -0,0 +1,405 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query results that borrow their strings from the response buffer.
+//!
+//! These mirror [Expression](super::Expression), [Instant](super::Instant),
+//! [Range](super::Range) and [Metric](super::Metric), but label names and
+//! values point into the buffer returned by `instant_query_raw` or
+//! `range_query_raw` instead of being allocated. Strings containing JSON
+//! escapes can't be borrowed and are the only ones allocated.
+
+use std::borrow::{Borrow, Cow};
+use std::collections::HashSet;
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::result::Result as StdResult;
+use std::sync::Arc;
+
+use serde::{
+    de,
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json;
+
+use crate::messages::{self, Sample};
+use crate::Result;
+
+/// Parse a query response, borrowing from `buf` wherever possible.
+pub fn from_slice(buf: &[u8]) -> Result<QueryResponse<'_>> {
+    serde_json::from_slice::<QueryResponse>(buf).map_err(From::from)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    Error,
+}
+
+/// Flattened equivalent of [ApiResult](super::ApiResult).
+///
+/// A plain struct rather than an internally-tagged enum, because serde
+/// buffers the whole document to deserialize internally-tagged enums.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct QueryResponse<'a> {
+    pub status: Status,
+    #[serde(borrow, default)]
+    pub data: Option<Expression<'a>>,
+    #[serde(borrow, default, rename = "errorType")]
+    pub error_type: Option<BorrowedStr<'a>>,
+    #[serde(borrow, default, rename = "error")]
+    pub error_message: Option<BorrowedStr<'a>>,
+    #[serde(borrow, default)]
+    pub warnings: Vec<BorrowedStr<'a>>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "resultType", content = "result")]
+pub enum Expression<'a> {
+    #[serde(rename = "scalar")]
+    Scalar(Sample),
+    #[serde(rename = "string")]
+    String(#[serde(borrow)] StringSample<'a>),
+    #[serde(rename = "vector")]
+    Instant(#[serde(borrow)] Vec<Instant<'a>>),
+    #[serde(rename = "matrix")]
+    Range(#[serde(borrow)] Vec<Range<'a>>),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Instant<'a> {
+    #[serde(borrow)]
+    pub metric: Metric<'a>,
+    #[serde(rename = "value")]
+    pub sample: Sample,
+}
+
+impl<'a> Instant<'a> {
+    pub fn into_owned(self) -> messages::Instant {
+        messages::Instant {
+            metric: self.metric.into_owned(),
+            sample: self.sample,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Range<'a> {
+    #[serde(borrow)]
+    pub metric: Metric<'a>,
+    #[serde(rename = "values")]
+    pub samples: Vec<Sample>,
+}
+
+impl<'a> Range<'a> {
+    pub fn into_owned(self) -> messages::Range {
+        messages::Range {
+            metric: self.metric.into_owned(),
+            samples: self.samples,
+        }
+    }
+}
+
+/// Labels in the order they appear in the response.
+/// Lookups are linear, which is faster than hashing for typical label counts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metric<'a> {
+    pub labels: Vec<(BorrowedStr<'a>, BorrowedStr<'a>)>,
+}
+
+impl<'a> Metric<'a> {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(n, _)| n.as_ref() == name)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub fn into_owned(self) -> messages::Metric {
+        messages::Metric {
+            labels: self
+                .labels
+                .into_iter()
+                .map(|(n, v)| (n.0.into_owned(), v.0.into_owned()))
+                .collect(),
+        }
+    }
+
+    /// Copy this metric out of the response buffer, sharing
+    /// label names with all other metrics interned by `interner`.
+    pub fn to_interned(&self, interner: &mut LabelInterner) -> InternedMetric {
+        InternedMetric {
+            labels: self
+                .labels
+                .iter()
+                .map(|(n, v)| (interner.intern(n), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Metric<'a> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VisitorImpl<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for VisitorImpl<'a> {
+            type Value = Metric<'a>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("Prometheus metric labels")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> StdResult<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut labels = Vec::with_capacity(map.size_hint().unwrap_or(8));
+                while let Some(entry) = map.next_entry()? {
+                    labels.push(entry);
+                }
+                Ok(Metric { labels })
+            }
+        }
+
+        deserializer.deserialize_map(VisitorImpl(std::marker::PhantomData))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StringSample<'a> {
+    pub epoch: f64,
+    pub value: BorrowedStr<'a>,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for StringSample<'a> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VisitorImpl<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for VisitorImpl<'a> {
+            type Value = StringSample<'a>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("Prometheus string sample")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let epoch = seq
+                    .next_element::<f64>()?
+                    .ok_or_else(|| de::Error::missing_field("sample time"))?;
+                let epoch = messages::normalize_epoch(epoch);
+                let value = seq
+                    .next_element::<BorrowedStr<'a>>()?
+                    .ok_or_else(|| de::Error::missing_field("sample value"))?;
+
+                Ok(StringSample { epoch, value })
+            }
+        }
+
+        deserializer.deserialize_seq(VisitorImpl(std::marker::PhantomData))
+    }
+}
+
+/// A string borrowed from the response buffer, or owned if it contained escapes.
+///
+/// Unlike `Cow<str>`, this borrows even when nested inside other
+/// containers, where `#[serde(borrow)]` doesn't reach.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct BorrowedStr<'a>(pub Cow<'a, str>);
+
+impl<'a> BorrowedStr<'a> {
+    pub fn is_borrowed(&self) -> bool {
+        match self.0 {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        }
+    }
+}
+
+impl<'a> Deref for BorrowedStr<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> AsRef<str> for BorrowedStr<'a> {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> Borrow<str> for BorrowedStr<'a> {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> Display for BorrowedStr<'a> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for BorrowedStr<'a> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VisitorImpl<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for VisitorImpl<'a> {
+            type Value = BorrowedStr<'a>;
+
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> StdResult<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> StdResult<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(v.to_owned())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> StdResult<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(VisitorImpl(std::marker::PhantomData))
+    }
+}
+
+/// A metric that no longer borrows from the response buffer,
+/// with label names shared through a [LabelInterner].
+#[derive(Clone, Debug, PartialEq)]
+pub struct InternedMetric {
+    pub labels: Vec<(Arc<str>, String)>,
+}
+
+/// Deduplicates label names, which repeat across nearly every series in a result.
+#[derive(Clone, Debug, Default)]
+pub struct LabelInterner {
+    names: HashSet<Arc<str>>,
+}
+
+impl LabelInterner {
+    pub fn new() -> LabelInterner {
+        Default::default()
+    }
+
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::messages::borrowed::{from_slice, Expression, LabelInterner, Status};
+    use crate::messages::Sample;
+
+    #[test]
+    fn should_borrow_labels_from_matrix_response() {
+        let j = br#"
+        {
+            "status" : "success",
+            "data" : {
+                "resultType" : "matrix",
+                "result" : [
+                    {
+                        "metric" : { "__name__" : "up", "job" : "prometheus" },
+                        "values" : [ [ 1435781430.781, "1" ], [ 1435781445.781, "NaN" ] ]
+                    },
+                    {
+                        "metric" : { "__name__" : "up", "job" : "no\"de" },
+                        "values" : [ [ 1435781430.781, "0" ] ]
+                    }
+                ]
+            }
+        }
+        "#;
+
+        let res = from_slice(j).unwrap();
+        assert_eq!(Status::Success, res.status);
+        let series = match res.data {
+            Some(Expression::Range(series)) => series,
+            d => panic!("unexpected data {:?}", d),
+        };
+
+        assert_eq!(2, series.len());
+        assert_eq!(Some("prometheus"), series[0].metric.get("job"));
+        assert!(series[0].metric.labels.iter().all(|(n, v)| n.is_borrowed() && v.is_borrowed()));
+        assert!(series[0].samples[1].value.is_nan());
+
+        // escaped values can't be borrowed, but still decode correctly
+        assert_eq!(Some("no\"de"), series[1].metric.get("job"));
+        assert!(!series[1].metric.labels[1].1.is_borrowed());
+        assert_eq!(
+            vec![Sample {
+                epoch: 1435781430.781,
+                value: 0.0
+            }],
+            series[1].samples
+        );
+
+        let mut interner = LabelInterner::new();
+        let first = series[0].metric.to_interned(&mut interner);
+        let second = series[1].metric.to_interned(&mut interner);
+        assert_eq!(2, interner.len());
+        assert!(Arc::ptr_eq(&first.labels[0].0, &second.labels[0].0));
+
+        let owned = series[1].clone().into_owned();
+        assert_eq!("no\"de", owned.metric.labels["job"]);
+    }
+
+    #[test]
+    fn should_borrow_error_response() {
+        let j = br#"{"status":"error","errorType":"bad_data","error":"parse error"}"#;
+
+        let res = from_slice(j).unwrap();
+        assert_eq!(Status::Error, res.status);
+        assert_eq!("bad_data", &**res.error_type.as_ref().unwrap());
+        assert_eq!("parse error", &**res.error_message.as_ref().unwrap());
+        assert!(res.data.is_none());
+    }
+}
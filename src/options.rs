@@ -0,0 +1,206 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backend-specific query options.
+//!
+//! Prometheus-compatible backends like Thanos, Cortex and Mimir accept
+//! parameters and headers that Prometheus itself doesn't know about.
+//! Implement [QueryOptions] to send them with `instant_query_with_options`
+//! and `range_query_with_options`, or use one of the provided option sets.
+
+/// Extra parameters and headers to send with a query.
+pub trait QueryOptions {
+    /// URL query parameters appended to the API call.
+    fn query_params(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// HTTP headers added to the API call.
+    fn headers(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// No extra parameters. Used by the plain query methods.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoOptions;
+
+impl QueryOptions for NoOptions {}
+
+/// Combine two option sets, e.g. Thanos parameters and a tenant header.
+impl<A: QueryOptions, B: QueryOptions> QueryOptions for (A, B) {
+    fn query_params(&self) -> Vec<(String, String)> {
+        let mut params = self.0.query_params();
+        params.extend(self.1.query_params());
+        params
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.0.headers();
+        headers.extend(self.1.headers());
+        headers
+    }
+}
+
+/// PromQL engine used by Thanos Query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThanosEngine {
+    Prometheus,
+    Thanos,
+}
+
+/// Query parameters understood by [Thanos Query](https://thanos.io/tip/components/query.md/).
+/// Unset fields are not sent, so Thanos applies its own defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThanosOptions {
+    pub dedup: Option<bool>,
+    pub partial_response: Option<bool>,
+    pub replica_labels: Vec<String>,
+    /// Downsampling resolution, e.g. `"5m"`, `"1h"` or `"auto"`.
+    pub max_source_resolution: Option<String>,
+    pub engine: Option<ThanosEngine>,
+}
+
+impl ThanosOptions {
+    pub fn new() -> ThanosOptions {
+        Default::default()
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> ThanosOptions {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    pub fn partial_response(mut self, partial_response: bool) -> ThanosOptions {
+        self.partial_response = Some(partial_response);
+        self
+    }
+
+    pub fn replica_label<S: Into<String>>(mut self, label: S) -> ThanosOptions {
+        self.replica_labels.push(label.into());
+        self
+    }
+
+    pub fn max_source_resolution<S: Into<String>>(mut self, resolution: S) -> ThanosOptions {
+        self.max_source_resolution = Some(resolution.into());
+        self
+    }
+
+    pub fn engine(mut self, engine: ThanosEngine) -> ThanosOptions {
+        self.engine = Some(engine);
+        self
+    }
+}
+
+impl QueryOptions for ThanosOptions {
+    fn query_params(&self) -> Vec<(String, String)> {
+        let mut params = Vec::new();
+        if let Some(dedup) = self.dedup {
+            params.push(("dedup".to_owned(), dedup.to_string()));
+        }
+        if let Some(partial_response) = self.partial_response {
+            params.push((
+                "partial_response".to_owned(),
+                partial_response.to_string(),
+            ));
+        }
+        for label in &self.replica_labels {
+            params.push(("replicaLabels[]".to_owned(), label.clone()));
+        }
+        if let Some(ref resolution) = self.max_source_resolution {
+            params.push(("max_source_resolution".to_owned(), resolution.clone()));
+        }
+        if let Some(engine) = self.engine {
+            let engine = match engine {
+                ThanosEngine::Prometheus => "prometheus",
+                ThanosEngine::Thanos => "thanos",
+            };
+            params.push(("engine".to_owned(), engine.to_owned()));
+        }
+        params
+    }
+}
+
+/// Tenancy options for Cortex and [Grafana Mimir](https://grafana.com/docs/mimir/latest/).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MimirOptions {
+    /// Tenants to query. More than one tenant requires
+    /// cross-tenant query federation to be enabled on the server.
+    pub tenants: Vec<String>,
+}
+
+impl MimirOptions {
+    pub fn tenant<S: Into<String>>(tenant: S) -> MimirOptions {
+        MimirOptions {
+            tenants: vec![tenant.into()],
+        }
+    }
+}
+
+impl QueryOptions for MimirOptions {
+    fn headers(&self) -> Vec<(String, String)> {
+        if self.tenants.is_empty() {
+            Vec::new()
+        } else {
+            vec![("X-Scope-OrgID".to_owned(), self.tenants.join("|"))]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::{MimirOptions, QueryOptions, ThanosEngine, ThanosOptions};
+
+    #[test]
+    fn should_render_thanos_query_params() {
+        let options = ThanosOptions::new()
+            .dedup(true)
+            .partial_response(false)
+            .replica_label("replica")
+            .replica_label("rule_replica")
+            .max_source_resolution("5m")
+            .engine(ThanosEngine::Thanos);
+
+        let expected: Vec<(String, String)> = vec![
+            ("dedup", "true"),
+            ("partial_response", "false"),
+            ("replicaLabels[]", "replica"),
+            ("replicaLabels[]", "rule_replica"),
+            ("max_source_resolution", "5m"),
+            ("engine", "thanos"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+
+        assert_eq!(expected, options.query_params());
+        assert!(options.headers().is_empty());
+    }
+
+    #[test]
+    fn should_combine_options() {
+        let mut mimir = MimirOptions::tenant("team-a");
+        mimir.tenants.push("team-b".to_owned());
+        let options = (ThanosOptions::new().dedup(false), mimir);
+
+        assert_eq!(
+            vec![("dedup".to_owned(), "false".to_owned())],
+            options.query_params()
+        );
+        assert_eq!(
+            vec![("X-Scope-OrgID".to_owned(), "team-a|team-b".to_owned())],
+            options.headers()
+        );
+    }
+}
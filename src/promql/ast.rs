@@ -0,0 +1,585 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed PromQL expression tree.
+//!
+//! Expressions display as single-line PromQL, adding parentheses where
+//! the tree's structure differs from operator precedence.
+
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::matcher::{self, LabelMatcher};
+use crate::promql::functions;
+use crate::units;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// `1.5`, `Inf`, `NaN`
+    Number(f64),
+    /// `"foo"`
+    String(String),
+    /// `up{job="node"} offset 5m`
+    VectorSelector(VectorSelector),
+    /// `http_requests_total{job="api"}[5m]`
+    MatrixSelector(MatrixSelector),
+    /// `max_over_time(rate(x[1m])[1h:5m])`
+    Subquery(Subquery),
+    /// `rate(http_requests_total[5m])`
+    Call(Call),
+    /// `sum by (job) (up)`
+    Aggregate(Aggregate),
+    /// `a / on (job) group_left b`
+    Binary(Binary),
+    /// `-up`
+    Negate(Box<Expr>),
+    /// `(up)`
+    Paren(Box<Expr>),
+}
+
+/// Selects the latest sample of each matching series.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorSelector {
+    /// Metric name, i.e. an implicit `__name__="..."` matcher.
+    pub name: Option<String>,
+    pub matchers: Vec<LabelMatcher>,
+    pub offset: Option<Offset>,
+    pub at: Option<At>,
+}
+
+/// Selects all samples of each matching series within `range`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatrixSelector {
+    pub selector: VectorSelector,
+    pub range: Duration,
+}
+
+/// Evaluates `expr` every `step` over `range`, producing a range vector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subquery {
+    pub expr: Box<Expr>,
+    pub range: Duration,
+    /// Defaults to the evaluation interval if `None`.
+    pub step: Option<Duration>,
+    pub offset: Option<Offset>,
+    pub at: Option<At>,
+}
+
+/// `@` modifier, fixing the evaluation time of a selector or subquery.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum At {
+    /// Milliseconds since the Unix epoch.
+    Timestamp(i64),
+    /// `@ start()`, the start of the range query.
+    Start,
+    /// `@ end()`, the end of the range query.
+    End,
+}
+
+/// Time shift of a selector. Negative offsets look ahead of the evaluation time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Offset {
+    pub duration: Duration,
+    pub negative: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    /// Function name, e.g. `rate`.
+    pub func: String,
+    pub args: Vec<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    pub op: AggregateOp,
+    pub expr: Box<Expr>,
+    /// `k` of `topk`, `φ` of `quantile` or the label of `count_values`.
+    pub param: Option<Box<Expr>>,
+    pub grouping: Option<Grouping>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AggregateOp {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    Group,
+    Stddev,
+    Stdvar,
+    Topk,
+    Bottomk,
+    Quantile,
+    CountValues,
+}
+
+/// `by (...)` or `without (...)` of an aggregation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Grouping {
+    By(Vec<String>),
+    Without(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binary {
+    pub op: BinaryOp,
+    pub lhs: Box<Expr>,
+    pub rhs: Box<Expr>,
+    /// `bool` modifier of a comparison.
+    pub return_bool: bool,
+    pub matching: Option<VectorMatching>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Atan2,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    And,
+    Or,
+    Unless,
+}
+
+/// How the series of both sides of a binary operation are matched.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorMatching {
+    pub labels: Matching,
+    pub group: Option<GroupModifier>,
+}
+
+/// `on (...)` or `ignoring (...)`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Matching {
+    On(Vec<String>),
+    Ignoring(Vec<String>),
+}
+
+/// `group_left (...)` or `group_right (...)`, with the labels to copy
+/// from the "one" side.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupModifier {
+    Left(Vec<String>),
+    Right(Vec<String>),
+}
+
+/// Type an expression evaluates to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Scalar,
+    String,
+    /// Instant vector.
+    Vector,
+    /// Range vector.
+    Matrix,
+}
+
+impl Expr {
+    /// Type this expression evaluates to.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Expr::Number(_) => ValueType::Scalar,
+            Expr::String(_) => ValueType::String,
+            Expr::VectorSelector(_) | Expr::Aggregate(_) => ValueType::Vector,
+            Expr::MatrixSelector(_) | Expr::Subquery(_) => ValueType::Matrix,
+            Expr::Call(c) => functions::function(&c.func).map_or(ValueType::Vector, |f| f.returns),
+            Expr::Binary(b) => {
+                if b.lhs.value_type() == ValueType::Scalar
+                    && b.rhs.value_type() == ValueType::Scalar
+                {
+                    ValueType::Scalar
+                } else {
+                    ValueType::Vector
+                }
+            }
+            Expr::Negate(e) | Expr::Paren(e) => e.value_type(),
+        }
+    }
+
+    /// Call `f` on this expression and then on each of its sub-expressions.
+    pub fn walk_mut<F: FnMut(&mut Expr)>(&mut self, f: &mut F) {
+        f(self);
+        match self {
+            Expr::Number(_)
+            | Expr::String(_)
+            | Expr::VectorSelector(_)
+            | Expr::MatrixSelector(_) => {}
+            Expr::Subquery(s) => s.expr.walk_mut(f),
+            Expr::Call(c) => {
+                for arg in c.args.iter_mut() {
+                    arg.walk_mut(f);
+                }
+            }
+            Expr::Aggregate(a) => {
+                if let Some(p) = a.param.as_mut() {
+                    p.walk_mut(f);
+                }
+                a.expr.walk_mut(f);
+            }
+            Expr::Binary(b) => {
+                b.lhs.walk_mut(f);
+                b.rhs.walk_mut(f);
+            }
+            Expr::Negate(e) | Expr::Paren(e) => e.walk_mut(f),
+        }
+    }
+}
+
+impl VectorSelector {
+    pub fn new<S: Into<String>>(name: S) -> VectorSelector {
+        VectorSelector {
+            name: Some(name.into()),
+            matchers: Vec::new(),
+            offset: None,
+            at: None,
+        }
+    }
+}
+
+impl Offset {
+    /// Signed offset in milliseconds, positive for the past.
+    pub fn millis(self) -> i64 {
+        let millis =
+            self.duration.as_secs() as i64 * 1000 + i64::from(self.duration.subsec_millis());
+        if self.negative {
+            -millis
+        } else {
+            millis
+        }
+    }
+}
+
+impl AggregateOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AggregateOp::Sum => "sum",
+            AggregateOp::Avg => "avg",
+            AggregateOp::Min => "min",
+            AggregateOp::Max => "max",
+            AggregateOp::Count => "count",
+            AggregateOp::Group => "group",
+            AggregateOp::Stddev => "stddev",
+            AggregateOp::Stdvar => "stdvar",
+            AggregateOp::Topk => "topk",
+            AggregateOp::Bottomk => "bottomk",
+            AggregateOp::Quantile => "quantile",
+            AggregateOp::CountValues => "count_values",
+        }
+    }
+
+    /// Whether the aggregation takes a parameter before the expression.
+    pub fn has_param(self) -> bool {
+        match self {
+            AggregateOp::Topk
+            | AggregateOp::Bottomk
+            | AggregateOp::Quantile
+            | AggregateOp::CountValues => true,
+            _ => false,
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::Atan2 => "atan2",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Lt => "<",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Le => "<=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Unless => "unless",
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        match self {
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Gt
+            | BinaryOp::Lt
+            | BinaryOp::Ge
+            | BinaryOp::Le => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is one of the set operators `and`, `or` and `unless`.
+    pub fn is_set(self) -> bool {
+        match self {
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Unless => true,
+            _ => false,
+        }
+    }
+
+    /// Binding strength, higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And | BinaryOp::Unless => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Gt
+            | BinaryOp::Lt
+            | BinaryOp::Ge
+            | BinaryOp::Le => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Atan2 => 5,
+            BinaryOp::Pow => 6,
+        }
+    }
+
+    /// Only `^` is right-associative, i.e. `a ^ b ^ c` is `a ^ (b ^ c)`.
+    pub fn is_right_associative(self) -> bool {
+        self == BinaryOp::Pow
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Expr::Number(n) => write_number(f, *n),
+            Expr::String(s) => matcher::write_quoted(f, s),
+            Expr::VectorSelector(s) => {
+                write_selector(f, s)?;
+                write_modifiers(f, s.offset, s.at)
+            }
+            Expr::MatrixSelector(s) => {
+                write_selector(f, &s.selector)?;
+                write!(f, "[{}]", units::format_duration(s.range))?;
+                write_modifiers(f, s.selector.offset, s.selector.at)
+            }
+            Expr::Subquery(s) => {
+                write_operand(f, &s.expr, needs_parens_for_postfix(&s.expr))?;
+                write!(f, "[{}:", units::format_duration(s.range))?;
+                if let Some(step) = s.step {
+                    f.write_str(&units::format_duration(step))?;
+                }
+                f.write_str("]")?;
+                write_modifiers(f, s.offset, s.at)
+            }
+            Expr::Call(c) => {
+                write!(f, "{}(", c.func)?;
+                for (i, arg) in c.args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            Expr::Aggregate(a) => {
+                f.write_str(a.op.as_str())?;
+                if let Some(g) = &a.grouping {
+                    write!(f, " {} ", g)?;
+                }
+                f.write_str("(")?;
+                if let Some(p) = &a.param {
+                    write!(f, "{}, ", p)?;
+                }
+                write!(f, "{})", a.expr)
+            }
+            Expr::Binary(b) => {
+                write_operand(f, &b.lhs, needs_parens(&b.lhs, b.op, false))?;
+                write!(f, " {}", b.op.as_str())?;
+                if b.return_bool {
+                    f.write_str(" bool")?;
+                }
+                if let Some(m) = &b.matching {
+                    write!(f, " {}", m)?;
+                }
+                f.write_str(" ")?;
+                write_operand(f, &b.rhs, needs_parens(&b.rhs, b.op, true))
+            }
+            Expr::Negate(e) => {
+                f.write_str("-")?;
+                let parens = match **e {
+                    Expr::Binary(ref b) => b.op != BinaryOp::Pow,
+                    Expr::Negate(_) => true,
+                    Expr::Number(n) => n < 0.0,
+                    _ => false,
+                };
+                write_operand(f, e, parens)
+            }
+            Expr::Paren(e) => write!(f, "({})", e),
+        }
+    }
+}
+
+impl Display for Grouping {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let (keyword, labels) = match self {
+            Grouping::By(labels) => ("by", labels),
+            Grouping::Without(labels) => ("without", labels),
+        };
+        write!(f, "{} ", keyword)?;
+        write_label_list(f, labels)
+    }
+}
+
+impl Display for VectorMatching {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match &self.labels {
+            Matching::On(labels) => {
+                f.write_str("on ")?;
+                write_label_list(f, labels)?;
+            }
+            Matching::Ignoring(labels) => {
+                f.write_str("ignoring ")?;
+                write_label_list(f, labels)?;
+            }
+        }
+        match &self.group {
+            Some(GroupModifier::Left(labels)) => {
+                f.write_str(" group_left")?;
+                if !labels.is_empty() {
+                    f.write_str(" ")?;
+                    write_label_list(f, labels)?;
+                }
+            }
+            Some(GroupModifier::Right(labels)) => {
+                f.write_str(" group_right")?;
+                if !labels.is_empty() {
+                    f.write_str(" ")?;
+                    write_label_list(f, labels)?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write_number<W: std::fmt::Write>(f: &mut W, n: f64) -> FmtResult {
+    if n.is_nan() {
+        f.write_str("NaN")
+    } else if n.is_infinite() {
+        f.write_str(if n > 0.0 { "Inf" } else { "-Inf" })
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+pub(crate) fn write_label_list<W: std::fmt::Write>(f: &mut W, labels: &[String]) -> FmtResult {
+    f.write_str("(")?;
+    for (i, label) in labels.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        matcher::write_label_name(f, label)?;
+    }
+    f.write_str(")")
+}
+
+/// Write a selector without its modifiers, e.g. `up{job="node"}`.
+pub(crate) fn write_selector<W: std::fmt::Write>(f: &mut W, s: &VectorSelector) -> FmtResult {
+    let name = s.name.as_ref().filter(|n| matcher::is_valid_metric_name(n));
+    if let Some(name) = name {
+        f.write_str(name)?;
+        if s.matchers.is_empty() {
+            return Ok(());
+        }
+    }
+    f.write_str("{")?;
+    let mut first = true;
+    if let (Some(name), None) = (&s.name, name) {
+        // names that aren't identifiers are quoted inside the braces
+        matcher::write_quoted(f, name)?;
+        first = false;
+    }
+    for m in &s.matchers {
+        if !first {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", m)?;
+        first = false;
+    }
+    f.write_str("}")
+}
+
+pub(crate) fn write_modifiers<W: std::fmt::Write>(
+    f: &mut W,
+    offset: Option<Offset>,
+    at: Option<At>,
+) -> FmtResult {
+    if let Some(at) = at {
+        match at {
+            At::Timestamp(millis) => write!(f, " @ {:.3}", millis as f64 / 1000.0)?,
+            At::Start => f.write_str(" @ start()")?,
+            At::End => f.write_str(" @ end()")?,
+        }
+    }
+    if let Some(offset) = offset {
+        f.write_str(" offset ")?;
+        if offset.negative {
+            f.write_str("-")?;
+        }
+        f.write_str(&units::format_duration(offset.duration))?;
+    }
+    Ok(())
+}
+
+fn write_operand(f: &mut Formatter, e: &Expr, parens: bool) -> FmtResult {
+    if parens {
+        write!(f, "({})", e)
+    } else {
+        write!(f, "{}", e)
+    }
+}
+
+/// Whether `operand` needs parentheses on the given side of `op`.
+pub(crate) fn needs_parens(operand: &Expr, op: BinaryOp, is_rhs: bool) -> bool {
+    match operand {
+        Expr::Binary(b) => {
+            let (inner, outer) = (b.op.precedence(), op.precedence());
+            inner < outer || (inner == outer && is_rhs != op.is_right_associative())
+        }
+        // `-a ^ b` is `-(a ^ b)`
+        Expr::Negate(_) => op == BinaryOp::Pow && !is_rhs,
+        Expr::Number(n) => *n < 0.0 && op == BinaryOp::Pow && !is_rhs,
+        _ => false,
+    }
+}
+
+/// Whether `e` needs parentheses before a subquery's `[range:step]`.
+pub(crate) fn needs_parens_for_postfix(e: &Expr) -> bool {
+    match e {
+        Expr::Binary(_) | Expr::Negate(_) => true,
+        Expr::Number(n) => *n < 0.0,
+        Expr::VectorSelector(s) => s.offset.is_some() || s.at.is_some(),
+        Expr::Subquery(s) => s.offset.is_some() || s.at.is_some(),
+        _ => false,
+    }
+}
@@ -0,0 +1,589 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed builder for PromQL expressions.
+//!
+//! ```ignore
+//! use prometheus_query::promql::builder::metric;
+//!
+//! let q = metric("http_requests_total")
+//!     .eq("job", job)
+//!     .rate("5m")
+//!     .sum_by(&["code"]);
+//! await!(client.instant_query(q, None))?;
+//! ```
+//!
+//! Label values are escaped when the query is rendered. Errors, such as an
+//! invalid regex or duration, are kept until the query is
+//! [built](Builder::build) or sent, so calls can be chained without `?`.
+
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::time::Duration;
+
+use crate::matcher::{LabelMatcher, MatchOp};
+use crate::promql::ast::{
+    Aggregate, AggregateOp, Binary, BinaryOp, Call, Expr, GroupModifier, Grouping, Matching,
+    MatrixSelector, Offset, Subquery, VectorMatching, VectorSelector,
+};
+use crate::promql::parser;
+use crate::units::{self, PromDuration};
+use crate::{Error, Result};
+
+/// PromQL expression under construction.
+#[derive(Debug)]
+pub struct Builder {
+    expr: Result<Expr>,
+}
+
+/// Start a query from the series named `name`.
+pub fn metric<S: Into<String>>(name: S) -> Builder {
+    Builder::from(Expr::VectorSelector(VectorSelector::new(name)))
+}
+
+/// A scalar literal.
+pub fn scalar(n: f64) -> Builder {
+    Builder::from(Expr::Number(n))
+}
+
+/// `histogram_quantile(q, buckets)`.
+pub fn histogram_quantile<B: Into<Builder>>(q: f64, buckets: B) -> Builder {
+    buckets.into().and_then(|buckets| {
+        Ok(Expr::Call(Call {
+            func: "histogram_quantile".to_owned(),
+            args: vec![Expr::Number(q), buckets],
+        }))
+    })
+}
+
+/// Range or offset given to a [Builder]: either Prometheus duration text,
+/// checked when the query is built, or a duration.
+#[derive(Clone, Debug)]
+pub enum DurationArg {
+    Text(String),
+    Duration(PromDuration),
+}
+
+impl DurationArg {
+    fn into_duration(self) -> Result<Duration> {
+        match self {
+            DurationArg::Text(s) => units::parse_duration(&s),
+            DurationArg::Duration(d) => Ok(d.into()),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for DurationArg {
+    fn from(s: &'a str) -> DurationArg {
+        DurationArg::Text(s.to_owned())
+    }
+}
+
+impl From<String> for DurationArg {
+    fn from(s: String) -> DurationArg {
+        DurationArg::Text(s)
+    }
+}
+
+impl From<PromDuration> for DurationArg {
+    fn from(d: PromDuration) -> DurationArg {
+        DurationArg::Duration(d)
+    }
+}
+
+impl From<Duration> for DurationArg {
+    fn from(d: Duration) -> DurationArg {
+        DurationArg::Duration(d.into())
+    }
+}
+
+impl From<Expr> for Builder {
+    fn from(expr: Expr) -> Builder {
+        Builder { expr: Ok(expr) }
+    }
+}
+
+impl From<f64> for Builder {
+    fn from(n: f64) -> Builder {
+        scalar(n)
+    }
+}
+
+// range functions that take a matrix as their only argument
+macro_rules! range_functions {
+    ($($name:ident,)*) => {
+        $(
+            /// Apply the function over `range`, e.g. `rate(x[5m])`.
+            pub fn $name<D: Into<DurationArg>>(self, range: D) -> Builder {
+                self.range(range).call(stringify!($name))
+            }
+        )*
+    };
+}
+
+// aggregations without a parameter, each with `_by` and `_without` forms
+macro_rules! aggregations {
+    ($($name:ident, $by:ident, $without:ident => $op:expr;)*) => {
+        $(
+            /// Aggregate over all series.
+            pub fn $name(self) -> Builder {
+                self.aggregate($op, None, None)
+            }
+
+            /// Aggregate, keeping only `labels`.
+            pub fn $by(self, labels: &[&str]) -> Builder {
+                self.aggregate($op, None, Some(Grouping::By(to_strings(labels))))
+            }
+
+            /// Aggregate, dropping `labels`.
+            pub fn $without(self, labels: &[&str]) -> Builder {
+                self.aggregate($op, None, Some(Grouping::Without(to_strings(labels))))
+            }
+        )*
+    };
+}
+
+impl Builder {
+    fn and_then<F: FnOnce(Expr) -> Result<Expr>>(self, f: F) -> Builder {
+        Builder {
+            expr: self.expr.and_then(f),
+        }
+    }
+
+    fn selector<F: FnOnce(&mut VectorSelector) -> Result<()>>(self, f: F) -> Builder {
+        self.and_then(|mut expr| {
+            match &mut expr {
+                Expr::VectorSelector(s) => f(s)?,
+                Expr::MatrixSelector(s) => f(&mut s.selector)?,
+                e => {
+                    return Err(Error::new_invalid_query_error(format!(
+                        "label matchers and offsets only apply to selectors, not {}",
+                        e
+                    )));
+                }
+            }
+            Ok(expr)
+        })
+    }
+
+    fn matcher<N: Into<String>, V: Into<String>>(self, label: N, op: MatchOp, value: V) -> Builder {
+        let matcher = LabelMatcher::new(label, op, value);
+        self.selector(|s| {
+            s.matchers.push(matcher?);
+            Ok(())
+        })
+    }
+
+    /// Match series whose `label` is `value`.
+    pub fn eq<N: Into<String>, V: Into<String>>(self, label: N, value: V) -> Builder {
+        self.matcher(label, MatchOp::Equal, value)
+    }
+
+    /// Match series whose `label` isn't `value`.
+    pub fn ne<N: Into<String>, V: Into<String>>(self, label: N, value: V) -> Builder {
+        self.matcher(label, MatchOp::NotEqual, value)
+    }
+
+    /// Match series whose `label` matches the regex `value`.
+    pub fn re<N: Into<String>, V: Into<String>>(self, label: N, value: V) -> Builder {
+        self.matcher(label, MatchOp::RegexMatch, value)
+    }
+
+    /// Match series whose `label` doesn't match the regex `value`.
+    pub fn not_re<N: Into<String>, V: Into<String>>(self, label: N, value: V) -> Builder {
+        self.matcher(label, MatchOp::RegexNoMatch, value)
+    }
+
+    /// Shift a selector `offset` into the past, e.g. `1h`.
+    pub fn offset<D: Into<DurationArg>>(self, offset: D) -> Builder {
+        let duration = offset.into().into_duration();
+        self.selector(|s| {
+            s.offset = Some(Offset {
+                duration: duration?,
+                negative: false,
+            });
+            Ok(())
+        })
+    }
+
+    /// Select a range, e.g. `5m`: `x[5m]` for a selector, or the subquery
+    /// `(...)[5m:]` for anything else.
+    pub fn range<D: Into<DurationArg>>(self, range: D) -> Builder {
+        let range = range.into().into_duration();
+        self.and_then(|expr| {
+            let range = range?;
+            Ok(match expr {
+                Expr::VectorSelector(selector) => {
+                    Expr::MatrixSelector(MatrixSelector { selector, range })
+                }
+                expr => Expr::Subquery(Subquery {
+                    expr: Box::new(expr),
+                    range,
+                    step: None,
+                    offset: None,
+                    at: None,
+                }),
+            })
+        })
+    }
+
+    /// Call the single-argument function `func`, e.g. `abs`.
+    pub fn call(self, func: &str) -> Builder {
+        self.and_then(|expr| {
+            Ok(Expr::Call(Call {
+                func: func.to_owned(),
+                args: vec![expr],
+            }))
+        })
+    }
+
+    range_functions! {
+        rate,
+        irate,
+        increase,
+        delta,
+        idelta,
+        deriv,
+        changes,
+        resets,
+        avg_over_time,
+        min_over_time,
+        max_over_time,
+        sum_over_time,
+        count_over_time,
+        last_over_time,
+        stddev_over_time,
+    }
+
+    fn aggregate(
+        self,
+        op: AggregateOp,
+        param: Option<Expr>,
+        grouping: Option<Grouping>,
+    ) -> Builder {
+        self.and_then(|expr| {
+            Ok(Expr::Aggregate(Aggregate {
+                op,
+                expr: Box::new(expr),
+                param: param.map(Box::new),
+                grouping,
+            }))
+        })
+    }
+
+    aggregations! {
+        sum, sum_by, sum_without => AggregateOp::Sum;
+        avg, avg_by, avg_without => AggregateOp::Avg;
+        min, min_by, min_without => AggregateOp::Min;
+        max, max_by, max_without => AggregateOp::Max;
+        count, count_by, count_without => AggregateOp::Count;
+        group, group_by, group_without => AggregateOp::Group;
+        stddev, stddev_by, stddev_without => AggregateOp::Stddev;
+        stdvar, stdvar_by, stdvar_without => AggregateOp::Stdvar;
+    }
+
+    /// `topk(k, ...)`. Add grouping with [by](Builder::by).
+    pub fn topk(self, k: usize) -> Builder {
+        self.aggregate(AggregateOp::Topk, Some(Expr::Number(k as f64)), None)
+    }
+
+    /// `bottomk(k, ...)`. Add grouping with [by](Builder::by).
+    pub fn bottomk(self, k: usize) -> Builder {
+        self.aggregate(AggregateOp::Bottomk, Some(Expr::Number(k as f64)), None)
+    }
+
+    /// `quantile(q, ...)`. Add grouping with [by](Builder::by).
+    pub fn quantile(self, q: f64) -> Builder {
+        self.aggregate(AggregateOp::Quantile, Some(Expr::Number(q)), None)
+    }
+
+    fn grouping(self, grouping: Grouping) -> Builder {
+        self.and_then(|expr| match expr {
+            Expr::Aggregate(mut a) => {
+                a.grouping = Some(grouping);
+                Ok(Expr::Aggregate(a))
+            }
+            e => Err(Error::new_invalid_query_error(format!(
+                "by and without only apply to aggregations, not {}",
+                e
+            ))),
+        })
+    }
+
+    /// Group the outermost aggregation by `labels`.
+    pub fn by(self, labels: &[&str]) -> Builder {
+        self.grouping(Grouping::By(to_strings(labels)))
+    }
+
+    /// Group the outermost aggregation by all labels except `labels`.
+    pub fn without(self, labels: &[&str]) -> Builder {
+        self.grouping(Grouping::Without(to_strings(labels)))
+    }
+
+    fn binary<B: Into<Builder>>(self, op: BinaryOp, rhs: B) -> Builder {
+        let rhs = rhs.into().expr;
+        self.and_then(|lhs| {
+            Ok(Expr::Binary(Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs?),
+                return_bool: false,
+                matching: None,
+            }))
+        })
+    }
+
+    /// `self ^ rhs`.
+    pub fn pow<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Pow, rhs)
+    }
+
+    /// `self == rhs`. Use [bool](Builder::bool) to return 0 or 1 instead of filtering.
+    pub fn equals<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Eq, rhs)
+    }
+
+    /// `self != rhs`.
+    pub fn not_equals<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Ne, rhs)
+    }
+
+    /// `self > rhs`.
+    pub fn gt<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Gt, rhs)
+    }
+
+    /// `self >= rhs`.
+    pub fn ge<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Ge, rhs)
+    }
+
+    /// `self < rhs`.
+    pub fn lt<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Lt, rhs)
+    }
+
+    /// `self <= rhs`.
+    pub fn le<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Le, rhs)
+    }
+
+    /// `self and rhs`.
+    pub fn and<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::And, rhs)
+    }
+
+    /// `self or rhs`.
+    pub fn or<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Or, rhs)
+    }
+
+    /// `self unless rhs`.
+    pub fn unless<B: Into<Builder>>(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Unless, rhs)
+    }
+
+    fn modify_binary<F: FnOnce(&mut Binary) -> Result<()>>(self, f: F) -> Builder {
+        self.and_then(|expr| match expr {
+            Expr::Binary(mut b) => {
+                f(&mut b)?;
+                Ok(Expr::Binary(b))
+            }
+            e => Err(Error::new_invalid_query_error(format!(
+                "binary operator modifiers only apply to binary expressions, not {}",
+                e
+            ))),
+        })
+    }
+
+    /// Make the outermost comparison return 0 or 1 instead of filtering.
+    pub fn bool(self) -> Builder {
+        self.modify_binary(|b| {
+            b.return_bool = true;
+            Ok(())
+        })
+    }
+
+    fn matching(self, labels: Matching) -> Builder {
+        self.modify_binary(|b| {
+            b.matching = Some(VectorMatching {
+                labels,
+                group: b.matching.take().and_then(|m| m.group),
+            });
+            Ok(())
+        })
+    }
+
+    /// Match the sides of the outermost binary operator on `labels` only.
+    pub fn on(self, labels: &[&str]) -> Builder {
+        self.matching(Matching::On(to_strings(labels)))
+    }
+
+    /// Match the sides of the outermost binary operator on all labels except `labels`.
+    pub fn ignoring(self, labels: &[&str]) -> Builder {
+        self.matching(Matching::Ignoring(to_strings(labels)))
+    }
+
+    fn group_modifier(self, group: GroupModifier) -> Builder {
+        self.modify_binary(|b| match b.matching.as_mut() {
+            Some(m) => {
+                m.group = Some(group);
+                Ok(())
+            }
+            None => Err(Error::new_invalid_query_error(
+                "group modifiers must follow on or ignoring",
+            )),
+        })
+    }
+
+    /// Many-to-one matching, copying `labels` from the right-hand side.
+    /// Must follow [on](Builder::on) or [ignoring](Builder::ignoring).
+    pub fn group_left(self, labels: &[&str]) -> Builder {
+        self.group_modifier(GroupModifier::Left(to_strings(labels)))
+    }
+
+    /// One-to-many matching, copying `labels` from the left-hand side.
+    /// Must follow [on](Builder::on) or [ignoring](Builder::ignoring).
+    pub fn group_right(self, labels: &[&str]) -> Builder {
+        self.group_modifier(GroupModifier::Right(to_strings(labels)))
+    }
+
+    /// The finished expression, checked the same way the server would check it.
+    pub fn build(self) -> Result<Expr> {
+        let expr = self.expr?;
+        parser::parse(&expr.to_string())?;
+        Ok(expr)
+    }
+}
+
+impl<B: Into<Builder>> Add<B> for Builder {
+    type Output = Builder;
+
+    fn add(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Add, rhs)
+    }
+}
+
+impl<B: Into<Builder>> Sub<B> for Builder {
+    type Output = Builder;
+
+    fn sub(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Sub, rhs)
+    }
+}
+
+impl<B: Into<Builder>> Mul<B> for Builder {
+    type Output = Builder;
+
+    fn mul(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Mul, rhs)
+    }
+}
+
+impl<B: Into<Builder>> Div<B> for Builder {
+    type Output = Builder;
+
+    fn div(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Div, rhs)
+    }
+}
+
+impl<B: Into<Builder>> Rem<B> for Builder {
+    type Output = Builder;
+
+    fn rem(self, rhs: B) -> Builder {
+        self.binary(BinaryOp::Mod, rhs)
+    }
+}
+
+impl Neg for Builder {
+    type Output = Builder;
+
+    fn neg(self) -> Builder {
+        self.and_then(|expr| Ok(Expr::Negate(Box::new(expr))))
+    }
+}
+
+fn to_strings(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|l| (*l).to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::error::ErrorKind;
+    use crate::promql::builder::{histogram_quantile, metric};
+    use crate::promql::Query;
+    use crate::units::PromDuration;
+
+    #[test]
+    fn should_build_escaped_queries() {
+        let q = metric("http_requests_total")
+            .eq("job", "api \"v2\"\\n")
+            .re("code", "5..")
+            .rate("5m")
+            .sum_by(&["code"]);
+        assert_eq!(
+            r#"sum by (code) (rate(http_requests_total{job="api \"v2\"\\n", code=~"5.."}[5m]))"#,
+            q.build().unwrap().to_string()
+        );
+
+        let ratio = metric("errors_total").rate("1m").sum_by(&["job"])
+            / (metric("requests_total").rate("1m").sum_by(&["job"]) + 1.0);
+        assert_eq!(
+            "sum by (job) (rate(errors_total[1m])) / (sum by (job) (rate(requests_total[1m])) + 1)",
+            ratio.build().unwrap().to_string()
+        );
+
+        let p99 = histogram_quantile(0.99, metric("latency_bucket").rate("5m").sum_by(&["le"]))
+            .max_over_time("1h");
+        assert_eq!(
+            "max_over_time(histogram_quantile(0.99, sum by (le) (rate(latency_bucket[5m])))[1h:])",
+            p99.build().unwrap().to_string()
+        );
+
+        let joined = (metric("a") * metric("info"))
+            .on(&["instance"])
+            .group_left(&["version"]);
+        assert_eq!(
+            "a * on (instance) group_left (version) info",
+            joined.build().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn should_defer_errors_until_built() {
+        let window = metric("up")
+            .offset(Duration::from_secs(3600))
+            .avg_over_time(PromDuration::from_millis(90_500));
+        assert_eq!(
+            "avg_over_time(up[1m30s500ms] offset 1h)",
+            window.build().unwrap().to_string()
+        );
+
+        let bad_regex = metric("up").re("job", "(").sum();
+        assert!(bad_regex.build().is_err());
+
+        let bad_duration: Query = metric("up").rate("5 minutes").into();
+        assert!(bad_duration.into_string().is_err());
+
+        // rate of an instant vector doesn't type-check
+        match metric("up").call("rate").build().unwrap_err().kind() {
+            ErrorKind::InvalidPromQl { message, .. } => {
+                assert!(message.contains("expected type Matrix"), "{}", message)
+            }
+            k => panic!("unexpected error {:?}", k),
+        }
+    }
+}
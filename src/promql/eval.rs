@@ -0,0 +1,1644 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-side evaluation of PromQL over already-fetched series.
+//!
+//! This follows the Prometheus engine's semantics (lookback, staleness
+//! markers, counter-reset handling and extrapolation in `rate`, vector
+//! matching) for the supported subset of the language:
+//!
+//! * selectors, including `offset`
+//! * range functions: `rate`, `irate`, `increase`, `delta`, `idelta`,
+//!   `changes`, `resets` and `{avg,sum,min,max,count,last,stddev,stdvar,quantile,present}_over_time`
+//! * instant functions: `abs`, `ceil`, `floor`, `round`, `sqrt`, `exp`,
+//!   `ln`, `log2`, `log10`, `sgn`, `clamp`, `clamp_min`, `clamp_max`,
+//!   `histogram_quantile`, `absent`, `scalar`, `vector` and `time`
+//! * all aggregation operators
+//! * all binary operators, with `on`/`ignoring` and `group_left`/`group_right`
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::matcher::MatchOp;
+use crate::messages::{
+    epoch_to_millis, format_plus_inf_value, millis_to_epoch, Expression, Instant, Metric, Range,
+    Sample, StringSample, METRIC_NAME_LABEL, PROM_INFINITY, PROM_NAN, PROM_NEGATIVE_INFINITY,
+    PROM_PLUS_INFINITY, STALE_NAN_BITS,
+};
+use crate::promql::ast::{
+    Aggregate, AggregateOp, At, Binary, BinaryOp, Call, Expr, GroupModifier, Grouping, Matching,
+    MatrixSelector, Offset, Subquery, VectorMatching, VectorSelector,
+};
+use crate::{Error, Result};
+
+/// Default time Prometheus looks back for the latest sample of a series.
+pub const DEFAULT_LOOKBACK_DELTA: Duration = Duration::from_secs(5 * 60);
+
+/// Default resolution of subqueries that don't set a step.
+pub const DEFAULT_EVALUATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Evaluates PromQL expressions over a fixed set of series.
+///
+/// The series should cover the time range the expression looks at,
+/// including ranges, offsets and the lookback delta.
+pub struct Evaluator {
+    series: Vec<Series>,
+    lookback_delta: i64,
+    evaluation_interval: i64,
+}
+
+// (timestamp in milliseconds, value)
+type Point = (i64, f64);
+
+struct Series {
+    metric: Metric,
+    points: Vec<Point>,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    metric: Metric,
+    value: f64,
+}
+
+#[derive(Clone, Debug)]
+struct MatrixSeries {
+    metric: Metric,
+    points: Vec<Point>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Scalar(f64),
+    String(String),
+    Vector(Vec<Element>),
+    Matrix(Vec<MatrixSeries>),
+}
+
+// left-open interval `(start, end]` a range selector covers
+#[derive(Clone, Copy)]
+struct Window {
+    start: i64,
+    end: i64,
+}
+
+impl Evaluator {
+    pub fn new(series: &[Range]) -> Evaluator {
+        let series = series
+            .iter()
+            .map(|r| {
+                let mut points: Vec<Point> = r
+                    .samples
+                    .iter()
+                    .map(|s| (epoch_to_millis(s.epoch), s.value))
+                    .collect();
+                points.sort_by_key(|p| p.0);
+                Series {
+                    metric: r.metric.clone(),
+                    points,
+                }
+            })
+            .collect();
+        Evaluator {
+            series,
+            lookback_delta: duration_millis(DEFAULT_LOOKBACK_DELTA),
+            evaluation_interval: duration_millis(DEFAULT_EVALUATION_INTERVAL),
+        }
+    }
+
+    /// How far back a selector looks for the latest sample, `5m` by default.
+    pub fn lookback_delta(mut self, lookback_delta: Duration) -> Evaluator {
+        self.lookback_delta = duration_millis(lookback_delta);
+        self
+    }
+
+    /// Step of subqueries that don't set one, `1m` by default.
+    pub fn evaluation_interval(mut self, evaluation_interval: Duration) -> Evaluator {
+        self.evaluation_interval = duration_millis(evaluation_interval);
+        self
+    }
+
+    /// Evaluate `expr` at a single instant, like an instant query.
+    pub fn instant(&self, expr: &Expr, at: DateTime<Utc>) -> Result<Expression> {
+        let t = at.timestamp_millis();
+        let epoch = millis_to_epoch(t);
+        let expr = resolve_at(expr, t, t);
+        let expression = match self.eval(&expr, t)? {
+            Value::Scalar(value) => Expression::Scalar(Sample { epoch, value }),
+            Value::String(value) => Expression::String(StringSample { epoch, value }),
+            Value::Vector(v) => Expression::Instant(
+                v.into_iter()
+                    .map(|e| Instant {
+                        metric: e.metric,
+                        sample: Sample {
+                            epoch,
+                            value: e.value,
+                        },
+                    })
+                    .collect(),
+            ),
+            Value::Matrix(m) => Expression::Range(
+                m.into_iter()
+                    .map(|s| Range {
+                        metric: s.metric,
+                        samples: to_samples(&s.points),
+                    })
+                    .collect(),
+            ),
+        };
+        Ok(expression)
+    }
+
+    /// Evaluate `expr` at every `step` from `start` to `end`, like a range query.
+    ///
+    /// Series are sorted by their labels.
+    pub fn range(
+        &self,
+        expr: &Expr,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: Duration,
+    ) -> Result<Expression> {
+        let step = duration_millis(step);
+        if step <= 0 {
+            return Err(Error::new_evaluation_error(
+                "zero or negative query resolution step",
+            ));
+        }
+
+        let expr = resolve_at(expr, start.timestamp_millis(), end.timestamp_millis());
+        let mut series: BTreeMap<Vec<(String, String)>, Range> = BTreeMap::new();
+        let mut t = start.timestamp_millis();
+        while t <= end.timestamp_millis() {
+            let elements = match self.eval(&expr, t)? {
+                Value::Scalar(value) => vec![Element {
+                    metric: empty_metric(),
+                    value,
+                }],
+                Value::Vector(v) => v,
+                Value::String(_) => {
+                    return Err(Error::new_evaluation_error(
+                        "invalid expression type \"string\" for range query",
+                    ));
+                }
+                Value::Matrix(_) => {
+                    return Err(Error::new_evaluation_error(
+                        "invalid expression type \"range vector\" for range query",
+                    ));
+                }
+            };
+            for e in elements {
+                let sample = Sample {
+                    epoch: millis_to_epoch(t),
+                    value: e.value,
+                };
+                series
+                    .entry(label_key(&e.metric))
+                    .or_insert_with(|| Range {
+                        metric: e.metric,
+                        samples: Vec::new(),
+                    })
+                    .samples
+                    .push(sample);
+            }
+            t += step;
+        }
+
+        Ok(Expression::Range(
+            series.into_iter().map(|(_, r)| r).collect(),
+        ))
+    }
+
+    fn eval(&self, expr: &Expr, t: i64) -> Result<Value> {
+        let value = self.eval_expr(expr, t)?;
+        if let Value::Vector(v) = &value {
+            check_unique_labels(v)?;
+        }
+        Ok(value)
+    }
+
+    fn eval_expr(&self, expr: &Expr, t: i64) -> Result<Value> {
+        match expr {
+            Expr::Number(n) => Ok(Value::Scalar(*n)),
+            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::Paren(e) => self.eval(e, t),
+            Expr::Negate(e) => match self.eval(e, t)? {
+                Value::Scalar(v) => Ok(Value::Scalar(-v)),
+                Value::Vector(v) => Ok(Value::Vector(
+                    v.into_iter()
+                        .map(|e| Element {
+                            metric: drop_name(&e.metric),
+                            value: -e.value,
+                        })
+                        .collect(),
+                )),
+                _ => Err(Error::new_evaluation_error(
+                    "unary expression only allowed on expressions of type scalar or instant vector",
+                )),
+            },
+            Expr::VectorSelector(s) => Ok(Value::Vector(self.select(s, t))),
+            Expr::MatrixSelector(s) => Ok(Value::Matrix(self.select_range(s, t).0)),
+            Expr::Subquery(s) => Ok(Value::Matrix(self.subquery(s, t)?.0)),
+            Expr::Call(c) => self.call(c, t),
+            Expr::Aggregate(a) => self.aggregate(a, t),
+            Expr::Binary(b) => self.binary(b, t),
+        }
+    }
+
+    fn eval_vector(&self, expr: &Expr, t: i64) -> Result<Vec<Element>> {
+        match self.eval(expr, t)? {
+            Value::Vector(v) => Ok(v),
+            _ => Err(Error::new_evaluation_error(
+                "expected instant vector argument",
+            )),
+        }
+    }
+
+    fn eval_scalar(&self, expr: &Expr, t: i64) -> Result<f64> {
+        match self.eval(expr, t)? {
+            Value::Scalar(v) => Ok(v),
+            _ => Err(Error::new_evaluation_error("expected scalar argument")),
+        }
+    }
+
+    fn eval_matrix(&self, expr: &Expr, t: i64) -> Result<(Vec<MatrixSeries>, Window)> {
+        match expr {
+            Expr::MatrixSelector(s) => Ok(self.select_range(s, t)),
+            Expr::Subquery(s) => self.subquery(s, t),
+            Expr::Paren(e) => self.eval_matrix(e, t),
+            _ => Err(Error::new_evaluation_error(
+                "expected range vector argument",
+            )),
+        }
+    }
+
+    fn matching_series<'a>(
+        &'a self,
+        selector: &'a VectorSelector,
+    ) -> impl Iterator<Item = &'a Series> + 'a {
+        self.series.iter().filter(move |s| {
+            selector
+                .name
+                .iter()
+                .all(|n| s.metric.name() == Some(n.as_str()))
+                && s.metric.matches(&selector.matchers)
+        })
+    }
+
+    fn select(&self, selector: &VectorSelector, t: i64) -> Vec<Element> {
+        let t = modified_time(t, selector.at, selector.offset);
+        self.matching_series(selector)
+            .filter_map(|s| {
+                let end = upper_bound(&s.points, t);
+                if end == 0 {
+                    return None;
+                }
+                let (ts, value) = s.points[end - 1];
+                if ts <= t - self.lookback_delta || is_stale(value) {
+                    return None;
+                }
+                Some(Element {
+                    metric: s.metric.clone(),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn select_range(&self, selector: &MatrixSelector, t: i64) -> (Vec<MatrixSeries>, Window) {
+        let end = modified_time(t, selector.selector.at, selector.selector.offset);
+        let window = Window {
+            start: end - duration_millis(selector.range),
+            end,
+        };
+        let series = self
+            .matching_series(&selector.selector)
+            .filter_map(|s| {
+                let points: Vec<Point> = s.points
+                    [upper_bound(&s.points, window.start)..upper_bound(&s.points, window.end)]
+                    .iter()
+                    .filter(|p| !is_stale(p.1))
+                    .cloned()
+                    .collect();
+                if points.is_empty() {
+                    None
+                } else {
+                    Some(MatrixSeries {
+                        metric: s.metric.clone(),
+                        points,
+                    })
+                }
+            })
+            .collect();
+        (series, window)
+    }
+
+    // evaluates the inner expression at every multiple of the step in the
+    // window, as Prometheus aligns subquery steps to the epoch
+    fn subquery(&self, subquery: &Subquery, t: i64) -> Result<(Vec<MatrixSeries>, Window)> {
+        let step = subquery
+            .step
+            .map_or(self.evaluation_interval, duration_millis);
+        if step <= 0 {
+            return Err(Error::new_evaluation_error(
+                "zero or negative subquery resolution step",
+            ));
+        }
+        let end = modified_time(t, subquery.at, subquery.offset);
+        let window = Window {
+            start: end - duration_millis(subquery.range),
+            end,
+        };
+
+        let mut series: BTreeMap<Vec<(String, String)>, MatrixSeries> = BTreeMap::new();
+        // `/` truncates toward zero, so round negative starts down by hand
+        let mut ts = window.start / step * step;
+        if ts > window.start {
+            ts -= step;
+        }
+        if ts <= window.start {
+            ts += step;
+        }
+        while ts <= window.end {
+            for e in self.eval_vector(&subquery.expr, ts)? {
+                let point = (ts, e.value);
+                series
+                    .entry(label_key(&e.metric))
+                    .or_insert_with(|| MatrixSeries {
+                        metric: e.metric,
+                        points: Vec::new(),
+                    })
+                    .points
+                    .push(point);
+            }
+            ts += step;
+        }
+        Ok((series.into_iter().map(|(_, s)| s).collect(), window))
+    }
+
+    fn call(&self, call: &Call, t: i64) -> Result<Value> {
+        let args = &call.args;
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(Error::new_evaluation_error(format!(
+                    "expected {} argument(s) in call to \"{}\", got {}",
+                    n,
+                    call.func,
+                    args.len()
+                )))
+            }
+        };
+
+        if let Some(f) = range_function(&call.func) {
+            let (matrix, window) = match call.func.as_str() {
+                "quantile_over_time" => {
+                    arity(2)?;
+                    self.eval_matrix(&args[1], t)?
+                }
+                _ => {
+                    arity(1)?;
+                    self.eval_matrix(&args[0], t)?
+                }
+            };
+            let param = match call.func.as_str() {
+                "quantile_over_time" => self.eval_scalar(&args[0], t)?,
+                _ => 0.0,
+            };
+            let keep_name = call.func == "last_over_time";
+            return Ok(Value::Vector(
+                matrix
+                    .into_iter()
+                    .filter_map(|s| {
+                        f(&s.points, window, param).map(|value| Element {
+                            metric: if keep_name {
+                                s.metric
+                            } else {
+                                drop_name(&s.metric)
+                            },
+                            value,
+                        })
+                    })
+                    .collect(),
+            ));
+        }
+
+        if let Some(f) = math_function(&call.func) {
+            arity(1)?;
+            return Ok(map_vector(self.eval_vector(&args[0], t)?, f));
+        }
+
+        match call.func.as_str() {
+            "round" => {
+                if args.is_empty() || args.len() > 2 {
+                    arity(1)?;
+                }
+                let to_nearest = match args.get(1) {
+                    Some(a) => self.eval_scalar(a, t)?,
+                    None => 1.0,
+                };
+                // same as Prometheus: round half up, dividing by the inverse for precision
+                let inverse = 1.0 / to_nearest;
+                Ok(map_vector(self.eval_vector(&args[0], t)?, |v| {
+                    (v * inverse + 0.5).floor() / inverse
+                }))
+            }
+            "clamp" => {
+                arity(3)?;
+                let min = self.eval_scalar(&args[1], t)?;
+                let max = self.eval_scalar(&args[2], t)?;
+                if max < min {
+                    return Ok(Value::Vector(Vec::new()));
+                }
+                Ok(map_vector(self.eval_vector(&args[0], t)?, |v| {
+                    v.max(min).min(max)
+                }))
+            }
+            "clamp_min" => {
+                arity(2)?;
+                let min = self.eval_scalar(&args[1], t)?;
+                Ok(map_vector(self.eval_vector(&args[0], t)?, |v| v.max(min)))
+            }
+            "clamp_max" => {
+                arity(2)?;
+                let max = self.eval_scalar(&args[1], t)?;
+                Ok(map_vector(self.eval_vector(&args[0], t)?, |v| v.min(max)))
+            }
+            "histogram_quantile" => {
+                arity(2)?;
+                let q = self.eval_scalar(&args[0], t)?;
+                Ok(Value::Vector(histogram_quantile(
+                    q,
+                    self.eval_vector(&args[1], t)?,
+                )))
+            }
+            "absent" => {
+                arity(1)?;
+                if !self.eval_vector(&args[0], t)?.is_empty() {
+                    return Ok(Value::Vector(Vec::new()));
+                }
+                let mut metric = empty_metric();
+                if let Expr::VectorSelector(s) = &args[0] {
+                    for m in &s.matchers {
+                        if m.op == MatchOp::Equal && m.name != METRIC_NAME_LABEL {
+                            metric.labels.insert(m.name.clone(), m.value.clone());
+                        }
+                    }
+                }
+                Ok(Value::Vector(vec![Element { metric, value: 1.0 }]))
+            }
+            "scalar" => {
+                arity(1)?;
+                let v = self.eval_vector(&args[0], t)?;
+                Ok(Value::Scalar(if v.len() == 1 {
+                    v[0].value
+                } else {
+                    std::f64::NAN
+                }))
+            }
+            "vector" => {
+                arity(1)?;
+                Ok(Value::Vector(vec![Element {
+                    metric: empty_metric(),
+                    value: self.eval_scalar(&args[0], t)?,
+                }]))
+            }
+            "time" => {
+                arity(0)?;
+                Ok(Value::Scalar(millis_to_epoch(t)))
+            }
+            f => Err(Error::new_evaluation_error(format!(
+                "unsupported function \"{}\"",
+                f
+            ))),
+        }
+    }
+
+    fn aggregate(&self, aggregate: &Aggregate, t: i64) -> Result<Value> {
+        let param = match &aggregate.param {
+            Some(p) => Some(self.eval(p, t)?),
+            None => None,
+        };
+        let scalar_param = || match param {
+            Some(Value::Scalar(v)) => Ok(v),
+            _ => Err(Error::new_evaluation_error(format!(
+                "expected scalar parameter for \"{}\"",
+                aggregate.op.as_str()
+            ))),
+        };
+        let elements = self.eval_vector(&aggregate.expr, t)?;
+
+        // groups in order of first appearance
+        let mut groups: Vec<(Metric, Vec<Element>)> = Vec::new();
+        let mut index: HashMap<Vec<(String, String)>, usize> = HashMap::new();
+        for e in elements {
+            let metric = match &aggregate.grouping {
+                Some(Grouping::By(labels)) => e.metric.only(labels),
+                Some(Grouping::Without(labels)) => drop_name(&e.metric.without(labels)),
+                None => empty_metric(),
+            };
+            let i = *index.entry(label_key(&metric)).or_insert_with(|| {
+                groups.push((metric, Vec::new()));
+                groups.len() - 1
+            });
+            groups[i].1.push(e);
+        }
+
+        let mut result = Vec::new();
+        for (metric, elements) in groups {
+            let values: Vec<f64> = elements.iter().map(|e| e.value).collect();
+            let value = match aggregate.op {
+                AggregateOp::Sum => values.iter().sum(),
+                AggregateOp::Avg => mean(&values),
+                AggregateOp::Min => min(&values),
+                AggregateOp::Max => max(&values),
+                AggregateOp::Count => values.len() as f64,
+                AggregateOp::Group => 1.0,
+                AggregateOp::Stddev => variance(&values).sqrt(),
+                AggregateOp::Stdvar => variance(&values),
+                AggregateOp::Quantile => quantile(scalar_param()?, values),
+                AggregateOp::Topk | AggregateOp::Bottomk => {
+                    let k = scalar_param()?;
+                    let mut elements = elements;
+                    // NaN sorts last for both, as in Prometheus
+                    elements.sort_by(|a, b| {
+                        let (a, b) = (a.value, b.value);
+                        match (a.is_nan(), b.is_nan()) {
+                            (true, true) => Ordering::Equal,
+                            (true, false) => Ordering::Greater,
+                            (false, true) => Ordering::Less,
+                            _ if aggregate.op == AggregateOp::Topk => {
+                                b.partial_cmp(&a).unwrap_or(Ordering::Equal)
+                            }
+                            _ => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                        }
+                    });
+                    let k = if k < 1.0 { 0 } else { k as usize };
+                    result.extend(elements.into_iter().take(k));
+                    continue;
+                }
+                AggregateOp::CountValues => {
+                    let label = match &param {
+                        Some(Value::String(s)) => s,
+                        _ => {
+                            return Err(Error::new_evaluation_error(
+                                "expected string parameter for \"count_values\"",
+                            ));
+                        }
+                    };
+                    let mut counts: Vec<(String, f64)> = Vec::new();
+                    for v in values {
+                        let v = format_plus_inf_value(v);
+                        match counts.iter_mut().find(|(s, _)| *s == v) {
+                            Some((_, c)) => *c += 1.0,
+                            None => counts.push((v, 1.0)),
+                        }
+                    }
+                    for (v, count) in counts {
+                        let mut metric = metric.clone();
+                        metric.labels.insert(label.clone(), v);
+                        result.push(Element {
+                            metric,
+                            value: count,
+                        });
+                    }
+                    continue;
+                }
+            };
+            result.push(Element { metric, value });
+        }
+        Ok(Value::Vector(result))
+    }
+
+    fn binary(&self, binary: &Binary, t: i64) -> Result<Value> {
+        let op = binary.op;
+        let lhs = self.eval(&binary.lhs, t)?;
+        let rhs = self.eval(&binary.rhs, t)?;
+
+        match (lhs, rhs) {
+            (Value::Scalar(l), Value::Scalar(r)) => {
+                if op.is_set() {
+                    return Err(Error::new_evaluation_error(format!(
+                        "set operator \"{}\" not allowed in binary scalar expression",
+                        op.as_str()
+                    )));
+                }
+                if op.is_comparison() && !binary.return_bool {
+                    return Err(Error::new_evaluation_error(
+                        "comparisons between scalars must use BOOL modifier",
+                    ));
+                }
+                let (value, keep) = apply(op, l, r);
+                Ok(Value::Scalar(if op.is_comparison() {
+                    bool_value(keep)
+                } else {
+                    value
+                }))
+            }
+            (Value::Vector(v), Value::Scalar(s)) => {
+                vector_scalar(op, v, s, false, binary.return_bool).map(Value::Vector)
+            }
+            (Value::Scalar(s), Value::Vector(v)) => {
+                vector_scalar(op, v, s, true, binary.return_bool).map(Value::Vector)
+            }
+            (Value::Vector(l), Value::Vector(r)) => {
+                let matching = binary.matching.as_ref();
+                if op.is_set() {
+                    Ok(Value::Vector(set_operation(op, l, r, matching)))
+                } else {
+                    vector_vector(op, l, r, matching, binary.return_bool).map(Value::Vector)
+                }
+            }
+            _ => Err(Error::new_evaluation_error(format!(
+                "binary expression \"{}\" must contain only scalar and instant vector types",
+                op.as_str()
+            ))),
+        }
+    }
+}
+
+fn vector_scalar(
+    op: BinaryOp,
+    vector: Vec<Element>,
+    scalar: f64,
+    scalar_is_lhs: bool,
+    return_bool: bool,
+) -> Result<Vec<Element>> {
+    if op.is_set() {
+        return Err(Error::new_evaluation_error(format!(
+            "set operator \"{}\" not allowed in binary scalar expression",
+            op.as_str()
+        )));
+    }
+
+    Ok(vector
+        .into_iter()
+        .filter_map(|e| {
+            let (l, r) = if scalar_is_lhs {
+                (scalar, e.value)
+            } else {
+                (e.value, scalar)
+            };
+            let (mut value, keep) = apply(op, l, r);
+            if op.is_comparison() {
+                // comparisons filter by, and keep, the vector's value
+                value = e.value;
+            }
+            if return_bool {
+                value = bool_value(keep);
+            } else if !keep {
+                return None;
+            }
+            let metric = if return_bool || !op.is_comparison() {
+                drop_name(&e.metric)
+            } else {
+                e.metric
+            };
+            Some(Element { metric, value })
+        })
+        .collect())
+}
+
+fn vector_vector(
+    op: BinaryOp,
+    lhs: Vec<Element>,
+    rhs: Vec<Element>,
+    matching: Option<&VectorMatching>,
+    return_bool: bool,
+) -> Result<Vec<Element>> {
+    let group = matching.and_then(|m| m.group.as_ref());
+    let (include, swapped) = match group {
+        Some(GroupModifier::Left(include)) => (&include[..], false),
+        Some(GroupModifier::Right(include)) => (&include[..], true),
+        None => (&[][..], false),
+    };
+    // the "many" side is always iterated, the "one" side is looked up
+    let (many, one) = if swapped { (rhs, lhs) } else { (lhs, rhs) };
+
+    let mut one_by_signature: HashMap<Vec<(String, String)>, Element> = HashMap::new();
+    for e in one {
+        let signature = signature(&e.metric, matching);
+        if one_by_signature.contains_key(&signature) {
+            return Err(Error::new_evaluation_error(format!(
+                "found duplicate series for the match group {:?} on the {} hand-side of the operation; \
+                 many-to-many matching not allowed: matching labels must be unique on one side",
+                signature,
+                if swapped { "left" } else { "right" }
+            )));
+        }
+        one_by_signature.insert(signature, e);
+    }
+
+    let mut matched: HashSet<Vec<(String, String)>> = HashSet::new();
+    let mut result = Vec::new();
+    for e in many {
+        let signature = signature(&e.metric, matching);
+        let other = match one_by_signature.get(&signature) {
+            Some(other) => other,
+            None => continue,
+        };
+
+        let (l, r) = if swapped {
+            (other.value, e.value)
+        } else {
+            (e.value, other.value)
+        };
+        let (mut value, keep) = apply(op, l, r);
+        if return_bool {
+            value = bool_value(keep);
+        } else if !keep {
+            continue;
+        }
+
+        let mut metric = if return_bool || !op.is_comparison() {
+            drop_name(&e.metric)
+        } else {
+            e.metric.clone()
+        };
+        match (group, matching.map(|m| &m.labels)) {
+            (None, Some(Matching::On(labels))) => metric = metric.only(labels),
+            (None, Some(Matching::Ignoring(labels))) => metric = metric.without(labels),
+            _ => {}
+        }
+        for label in include {
+            match other.metric.labels.get(label) {
+                Some(v) if !v.is_empty() => {
+                    metric.labels.insert(label.clone(), v.clone());
+                }
+                _ => {
+                    metric.labels.remove(label);
+                }
+            }
+        }
+
+        let key = if group.is_none() {
+            signature
+        } else {
+            label_key(&metric)
+        };
+        if !matched.insert(key) {
+            return Err(Error::new_evaluation_error(if group.is_none() {
+                "multiple matches for labels: many-to-one matching must be explicit (group_left/group_right)"
+            } else {
+                "multiple matches for labels: grouping labels must ensure unique matches"
+            }));
+        }
+        result.push(Element { metric, value });
+    }
+    Ok(result)
+}
+
+fn set_operation(
+    op: BinaryOp,
+    lhs: Vec<Element>,
+    rhs: Vec<Element>,
+    matching: Option<&VectorMatching>,
+) -> Vec<Element> {
+    let rhs_signatures: HashSet<_> = rhs.iter().map(|e| signature(&e.metric, matching)).collect();
+    match op {
+        BinaryOp::And => lhs
+            .into_iter()
+            .filter(|e| rhs_signatures.contains(&signature(&e.metric, matching)))
+            .collect(),
+        BinaryOp::Unless => lhs
+            .into_iter()
+            .filter(|e| !rhs_signatures.contains(&signature(&e.metric, matching)))
+            .collect(),
+        _ => {
+            let lhs_signatures: HashSet<_> =
+                lhs.iter().map(|e| signature(&e.metric, matching)).collect();
+            let mut result = lhs;
+            result.extend(
+                rhs.into_iter()
+                    .filter(|e| !lhs_signatures.contains(&signature(&e.metric, matching))),
+            );
+            result
+        }
+    }
+}
+
+// Labels two series must agree on to be matched.
+fn signature(metric: &Metric, matching: Option<&VectorMatching>) -> Vec<(String, String)> {
+    match matching.map(|m| &m.labels) {
+        Some(Matching::On(labels)) => label_key(&metric.only(labels)),
+        Some(Matching::Ignoring(labels)) => label_key(&drop_name(&metric.without(labels))),
+        None => label_key(&drop_name(metric)),
+    }
+}
+
+fn apply(op: BinaryOp, l: f64, r: f64) -> (f64, bool) {
+    match op {
+        BinaryOp::Add => (l + r, true),
+        BinaryOp::Sub => (l - r, true),
+        BinaryOp::Mul => (l * r, true),
+        BinaryOp::Div => (l / r, true),
+        BinaryOp::Mod => (l % r, true),
+        BinaryOp::Pow => (l.powf(r), true),
+        BinaryOp::Atan2 => (l.atan2(r), true),
+        BinaryOp::Eq => (l, l == r),
+        BinaryOp::Ne => (l, l != r),
+        BinaryOp::Gt => (l, l > r),
+        BinaryOp::Lt => (l, l < r),
+        BinaryOp::Ge => (l, l >= r),
+        BinaryOp::Le => (l, l <= r),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Unless => (l, true),
+    }
+}
+
+fn bool_value(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+type RangeFunction = fn(&[Point], Window, f64) -> Option<f64>;
+
+// time a selector or subquery looks at when evaluated at `t`
+fn modified_time(t: i64, at: Option<At>, offset: Option<Offset>) -> i64 {
+    let t = match at {
+        Some(At::Timestamp(at)) => at,
+        _ => t,
+    };
+    t - offset.map_or(0, |o| o.millis())
+}
+
+// replaces `@ start()` and `@ end()` with the timestamps they stand for
+fn resolve_at(expr: &Expr, start: i64, end: i64) -> Expr {
+    let resolve = |at: &mut Option<At>| {
+        *at = match *at {
+            Some(At::Start) => Some(At::Timestamp(start)),
+            Some(At::End) => Some(At::Timestamp(end)),
+            other => other,
+        }
+    };
+    let mut expr = expr.clone();
+    expr.walk_mut(&mut |e| match e {
+        Expr::VectorSelector(s) => resolve(&mut s.at),
+        Expr::MatrixSelector(s) => resolve(&mut s.selector.at),
+        Expr::Subquery(s) => resolve(&mut s.at),
+        _ => {}
+    });
+    expr
+}
+
+fn range_function(name: &str) -> Option<RangeFunction> {
+    let f: RangeFunction = match name {
+        "rate" => |p, w, _| extrapolated_rate(p, w, true, true),
+        "increase" => |p, w, _| extrapolated_rate(p, w, true, false),
+        "delta" => |p, w, _| extrapolated_rate(p, w, false, false),
+        "irate" => |p, _, _| instant_delta(p, true),
+        "idelta" => |p, _, _| instant_delta(p, false),
+        "changes" => |p, _, _| Some(p.windows(2).filter(|w| w[0].1 != w[1].1).count() as f64),
+        "resets" => |p, _, _| Some(p.windows(2).filter(|w| w[1].1 < w[0].1).count() as f64),
+        "avg_over_time" => |p, _, _| Some(mean(&values(p))),
+        "sum_over_time" => |p, _, _| Some(p.iter().map(|p| p.1).sum()),
+        "min_over_time" => |p, _, _| Some(min(&values(p))),
+        "max_over_time" => |p, _, _| Some(max(&values(p))),
+        "count_over_time" => |p, _, _| Some(p.len() as f64),
+        "last_over_time" => |p, _, _| p.last().map(|p| p.1),
+        "present_over_time" => |_, _, _| Some(1.0),
+        "stddev_over_time" => |p, _, _| Some(variance(&values(p)).sqrt()),
+        "stdvar_over_time" => |p, _, _| Some(variance(&values(p))),
+        "quantile_over_time" => |p, _, q| Some(quantile(q, values(p))),
+        _ => return None,
+    };
+    Some(f)
+}
+
+fn math_function(name: &str) -> Option<fn(f64) -> f64> {
+    let f: fn(f64) -> f64 = match name {
+        "abs" => f64::abs,
+        "ceil" => f64::ceil,
+        "floor" => f64::floor,
+        "sqrt" => f64::sqrt,
+        "exp" => f64::exp,
+        "ln" => f64::ln,
+        "log2" => f64::log2,
+        "log10" => f64::log10,
+        "sgn" => |v| {
+            if v > 0.0 {
+                1.0
+            } else if v < 0.0 {
+                -1.0
+            } else {
+                v
+            }
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+fn map_vector<F: Fn(f64) -> f64>(v: Vec<Element>, f: F) -> Value {
+    Value::Vector(
+        v.into_iter()
+            .map(|e| Element {
+                metric: drop_name(&e.metric),
+                value: f(e.value),
+            })
+            .collect(),
+    )
+}
+
+// Port of `extrapolatedRate` from the Prometheus engine.
+fn extrapolated_rate(
+    points: &[Point],
+    window: Window,
+    is_counter: bool,
+    is_rate: bool,
+) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+
+    let mut result = last.1 - first.1;
+    if is_counter {
+        for w in points.windows(2) {
+            if w[1].1 < w[0].1 {
+                result += w[0].1;
+            }
+        }
+    }
+
+    let mut duration_to_start = (first.0 - window.start) as f64 / 1000.0;
+    let duration_to_end = (window.end - last.0) as f64 / 1000.0;
+    let sampled_interval = (last.0 - first.0) as f64 / 1000.0;
+    let average_interval = sampled_interval / (points.len() - 1) as f64;
+
+    if is_counter && result > 0.0 && first.1 >= 0.0 {
+        // counters can't go below zero, so don't extrapolate past that
+        let duration_to_zero = sampled_interval * (first.1 / result);
+        if duration_to_zero < duration_to_start {
+            duration_to_start = duration_to_zero;
+        }
+    }
+
+    let threshold = average_interval * 1.1;
+    let mut extrapolate_to = sampled_interval;
+    extrapolate_to += if duration_to_start < threshold {
+        duration_to_start
+    } else {
+        average_interval / 2.0
+    };
+    extrapolate_to += if duration_to_end < threshold {
+        duration_to_end
+    } else {
+        average_interval / 2.0
+    };
+
+    result *= extrapolate_to / sampled_interval;
+    if is_rate {
+        result /= (window.end - window.start) as f64 / 1000.0;
+    }
+    Some(result)
+}
+
+fn instant_delta(points: &[Point], is_rate: bool) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let (previous, last) = (points[points.len() - 2], points[points.len() - 1]);
+    if !is_rate {
+        return Some(last.1 - previous.1);
+    }
+
+    let interval = (last.0 - previous.0) as f64 / 1000.0;
+    if interval == 0.0 {
+        return None;
+    }
+    let delta = if last.1 < previous.1 {
+        // counter reset
+        last.1
+    } else {
+        last.1 - previous.1
+    };
+    Some(delta / interval)
+}
+
+// Port of `bucketQuantile` from the Prometheus engine, over series grouped
+// by all labels except `le`.
+fn histogram_quantile(q: f64, elements: Vec<Element>) -> Vec<Element> {
+    let mut histograms: Vec<(Metric, Vec<(f64, f64)>)> = Vec::new();
+    let mut index: HashMap<Vec<(String, String)>, usize> = HashMap::new();
+    for e in elements {
+        let upper_bound = match e.metric.get("le").map(parse_value) {
+            Some(Some(le)) => le,
+            _ => continue,
+        };
+        let metric = drop_name(&e.metric.without(&["le"]));
+        let i = *index.entry(label_key(&metric)).or_insert_with(|| {
+            histograms.push((metric, Vec::new()));
+            histograms.len() - 1
+        });
+        histograms[i].1.push((upper_bound, e.value));
+    }
+
+    histograms
+        .into_iter()
+        .map(|(metric, buckets)| Element {
+            metric,
+            value: bucket_quantile(q, buckets),
+        })
+        .collect()
+}
+
+fn bucket_quantile(q: f64, mut buckets: Vec<(f64, f64)>) -> f64 {
+    if q.is_nan() {
+        return std::f64::NAN;
+    }
+    if q < 0.0 {
+        return std::f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return std::f64::INFINITY;
+    }
+
+    buckets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    match buckets.last() {
+        Some((le, _)) if *le == std::f64::INFINITY => {}
+        _ => return std::f64::NAN,
+    }
+
+    // merge buckets with the same bound and force counts to be monotonic
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(buckets.len());
+    for (le, count) in buckets {
+        match merged.last_mut() {
+            Some(last) if last.0 == le => last.1 += count,
+            _ => merged.push((le, count)),
+        }
+    }
+    let mut max = std::f64::NEG_INFINITY;
+    for b in merged.iter_mut() {
+        if b.1 < max {
+            b.1 = max;
+        }
+        max = b.1;
+    }
+    let buckets = merged;
+
+    if buckets.len() < 2 {
+        return std::f64::NAN;
+    }
+    let observations = buckets[buckets.len() - 1].1;
+    if observations == 0.0 {
+        return std::f64::NAN;
+    }
+
+    let mut rank = q * observations;
+    let b = buckets
+        .iter()
+        .position(|(_, count)| *count >= rank)
+        .unwrap_or(buckets.len() - 1);
+    if b == buckets.len() - 1 {
+        return buckets[buckets.len() - 2].0;
+    }
+    if b == 0 && buckets[0].0 <= 0.0 {
+        return buckets[0].0;
+    }
+
+    let mut bucket_start = 0.0;
+    let bucket_end = buckets[b].0;
+    let mut count = buckets[b].1;
+    if b > 0 {
+        bucket_start = buckets[b - 1].0;
+        count -= buckets[b - 1].1;
+        rank -= buckets[b - 1].1;
+    }
+    bucket_start + (bucket_end - bucket_start) * (rank / count)
+}
+
+fn values(points: &[Point]) -> Vec<f64> {
+    points.iter().map(|p| p.1).collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// NaN is only returned if all values are NaN
+fn min(values: &[f64]) -> f64 {
+    values.iter().fold(
+        std::f64::NAN,
+        |m, v| if m.is_nan() || *v < m { *v } else { m },
+    )
+}
+
+fn max(values: &[f64]) -> f64 {
+    values.iter().fold(
+        std::f64::NAN,
+        |m, v| if m.is_nan() || *v > m { *v } else { m },
+    )
+}
+
+// population variance
+fn variance(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+}
+
+// φ-quantile with linear interpolation, as in the `quantile` aggregation
+fn quantile(q: f64, mut values: Vec<f64>) -> f64 {
+    if values.is_empty() || q.is_nan() {
+        return std::f64::NAN;
+    }
+    if q < 0.0 {
+        return std::f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return std::f64::INFINITY;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let rank = q * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = (lower + 1).min(values.len() - 1);
+    let weight = rank - rank.floor();
+    values[lower] * (1.0 - weight) + values[upper] * weight
+}
+
+fn parse_value(s: &str) -> Option<f64> {
+    match s {
+        PROM_PLUS_INFINITY | PROM_INFINITY => Some(std::f64::INFINITY),
+        PROM_NEGATIVE_INFINITY => Some(std::f64::NEG_INFINITY),
+        PROM_NAN => Some(std::f64::NAN),
+        s => s.parse().ok(),
+    }
+}
+
+fn to_samples(points: &[Point]) -> Vec<Sample> {
+    points
+        .iter()
+        .map(|(t, v)| Sample {
+            epoch: millis_to_epoch(*t),
+            value: *v,
+        })
+        .collect()
+}
+
+fn is_stale(v: f64) -> bool {
+    v.to_bits() == STALE_NAN_BITS
+}
+
+// index of the first point after `t`
+fn upper_bound(points: &[Point], t: i64) -> usize {
+    match points.binary_search_by(|p| p.0.cmp(&t)) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    }
+}
+
+fn duration_millis(d: Duration) -> i64 {
+    d.as_secs() as i64 * 1000 + i64::from(d.subsec_millis())
+}
+
+fn empty_metric() -> Metric {
+    Metric {
+        labels: HashMap::new(),
+    }
+}
+
+fn drop_name(metric: &Metric) -> Metric {
+    metric.without(&[METRIC_NAME_LABEL])
+}
+
+fn label_key(metric: &Metric) -> Vec<(String, String)> {
+    metric
+        .iter()
+        .map(|(n, v)| (n.to_owned(), v.to_owned()))
+        .collect()
+}
+
+// Prometheus rejects vectors whose series can't be told apart, e.g. after
+// a function drops `__name__` from two metrics with otherwise equal labels.
+fn check_unique_labels(vector: &[Element]) -> Result<()> {
+    let mut seen = HashSet::with_capacity(vector.len());
+    for e in vector {
+        if !seen.insert(label_key(&e.metric)) {
+            return Err(Error::new_evaluation_error(
+                "vector cannot contain metrics with the same labelset",
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::error::ErrorKind;
+    use crate::fixtures::{metric, range_secs};
+    use crate::matcher::LabelMatcher;
+    use crate::messages::{Expression, Metric, Range};
+    use crate::promql::ast::{
+        Aggregate, AggregateOp, Binary, BinaryOp, Call, Expr, GroupModifier, Grouping, Matching,
+        MatrixSelector, Offset, VectorMatching, VectorSelector,
+    };
+    use crate::promql::{parse, Evaluator};
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp(secs, 0)
+    }
+
+    fn selector(name: &str) -> Expr {
+        Expr::VectorSelector(VectorSelector::new(name))
+    }
+
+    fn range(name: &str, secs: u64) -> Expr {
+        Expr::MatrixSelector(MatrixSelector {
+            selector: VectorSelector::new(name),
+            range: Duration::from_secs(secs),
+        })
+    }
+
+    fn call(func: &str, args: Vec<Expr>) -> Expr {
+        Expr::Call(Call {
+            func: func.to_owned(),
+            args,
+        })
+    }
+
+    fn binary(op: BinaryOp, lhs: Expr, rhs: Expr, matching: Option<VectorMatching>) -> Expr {
+        Expr::Binary(Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            return_bool: false,
+            matching,
+        })
+    }
+
+    fn instant_values(e: Expression) -> Vec<(Metric, f64)> {
+        match e {
+            Expression::Instant(v) => v.into_iter().map(|i| (i.metric, i.sample.value)).collect(),
+            e => panic!("unexpected expression {:?}", e),
+        }
+    }
+
+    fn requests() -> Vec<Range> {
+        // counters scraped every 15s, with a reset on instance b
+        let a: Vec<(i64, f64)> = (0..=20).map(|i| (i * 15, i as f64 * 30.0)).collect();
+        let b: Vec<(i64, f64)> = (0..=20)
+            .map(|i| {
+                (
+                    i * 15,
+                    if i < 10 {
+                        i as f64 * 15.0
+                    } else {
+                        (i - 10) as f64 * 15.0
+                    },
+                )
+            })
+            .collect();
+        vec![
+            range_secs(
+                &[
+                    ("__name__", "http_requests_total"),
+                    ("job", "api"),
+                    ("instance", "a"),
+                    ("code", "200"),
+                ],
+                &a,
+            ),
+            range_secs(
+                &[
+                    ("__name__", "http_requests_total"),
+                    ("job", "api"),
+                    ("instance", "b"),
+                    ("code", "500"),
+                ],
+                &b,
+            ),
+        ]
+    }
+
+    #[test]
+    fn should_select_latest_sample_within_lookback() {
+        let data = vec![range_secs(
+            &[("__name__", "up"), ("job", "node")],
+            &[(0, 1.0), (60, 0.0)],
+        )];
+        let e = Evaluator::new(&data);
+
+        assert_eq!(
+            vec![(metric(&[("__name__", "up"), ("job", "node")]), 0.0)],
+            instant_values(e.instant(&selector("up"), at(90)).unwrap())
+        );
+        // past the default 5m lookback
+        assert!(instant_values(e.instant(&selector("up"), at(60 + 301)).unwrap()).is_empty());
+
+        let offset = Expr::VectorSelector(VectorSelector {
+            offset: Some(Offset {
+                duration: Duration::from_secs(60),
+                negative: false,
+            }),
+            ..VectorSelector::new("up")
+        });
+        assert_eq!(
+            1.0,
+            instant_values(e.instant(&offset, at(90)).unwrap())[0].1
+        );
+    }
+
+    #[test]
+    fn should_ignore_stale_samples() {
+        let stale = f64::from_bits(crate::messages::STALE_NAN_BITS);
+        let data = vec![range_secs(&[("__name__", "up")], &[(0, 1.0), (30, stale)])];
+        let e = Evaluator::new(&data);
+        assert!(instant_values(e.instant(&selector("up"), at(45)).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn should_reject_series_with_the_same_labelset() {
+        let data = vec![
+            range_secs(&[("__name__", "up"), ("job", "x")], &[(0, 1.0), (15, 1.0)]),
+            range_secs(
+                &[("__name__", "down"), ("job", "x")],
+                &[(0, 0.0), (15, 0.0)],
+            ),
+        ];
+        let e = Evaluator::new(&data);
+        let by_job = parse(r#"{job="x"}"#).unwrap();
+        assert_eq!(2, instant_values(e.instant(&by_job, at(15)).unwrap()).len());
+
+        for query in [r#"abs({job="x"})"#, r#"rate({job="x"}[1m])"#].iter() {
+            let expr = parse(query).unwrap();
+            let results = [
+                e.instant(&expr, at(15)),
+                e.range(&expr, at(0), at(15), Duration::from_secs(15)),
+            ];
+            for result in results.iter() {
+                match result.as_ref().unwrap_err().kind() {
+                    ErrorKind::Evaluation { message } => {
+                        assert!(message.contains("same labelset"), "{}", message)
+                    }
+                    k => panic!("unexpected error {:?}", k),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_compute_rate_with_counter_resets() {
+        let data = requests();
+        let e = Evaluator::new(&data);
+
+        let rates = instant_values(
+            e.instant(
+                &call("rate", vec![range("http_requests_total", 60)]),
+                at(300),
+            )
+            .unwrap(),
+        );
+        assert_eq!(2, rates.len());
+        for (m, v) in rates {
+            assert_eq!(None, m.name());
+            match m.get("instance") {
+                Some("a") => assert!((v - 2.0).abs() < 1e-9, "{}", v),
+                Some("b") => assert!((v - 1.0).abs() < 1e-9, "{}", v),
+                i => panic!("unexpected instance {:?}", i),
+            }
+        }
+
+        let increase = instant_values(
+            e.instant(
+                &call("increase", vec![range("http_requests_total", 60)]),
+                at(150),
+            )
+            .unwrap(),
+        );
+        let b = increase
+            .iter()
+            .find(|(m, _)| m.get("instance") == Some("b"))
+            .unwrap();
+        // 105, 120, 135 and a reset to 0: 30 over 45s, extrapolated to the minute
+        assert!((b.1 - 40.0).abs() < 1e-9, "{}", b.1);
+    }
+
+    #[test]
+    fn should_evaluate_subqueries_and_at_modifiers() {
+        let data = requests();
+        let e = Evaluator::new(&data);
+        let eval = |q: &str, t: i64| instant_values(e.instant(&parse(q).unwrap(), at(t)).unwrap());
+
+        // steps at 255, 270, 285 and 300
+        let count = eval(
+            r#"count_over_time(http_requests_total{instance="a"}[1m:15s])"#,
+            300,
+        );
+        assert_eq!(4.0, count[0].1);
+        let max = eval(
+            r#"max_over_time(http_requests_total{instance="a"}[1m:15s] offset 1m)"#,
+            300,
+        );
+        assert_eq!(480.0, max[0].1);
+
+        let pinned = eval(r#"http_requests_total{instance="a"} @ 150"#, 300);
+        assert_eq!(300.0, pinned[0].1);
+
+        let start = parse(r#"http_requests_total{instance="a"} @ start()"#).unwrap();
+        match e
+            .range(&start, at(60), at(120), Duration::from_secs(30))
+            .unwrap()
+        {
+            Expression::Range(r) => {
+                let values: Vec<f64> = r[0].samples.iter().map(|s| s.value).collect();
+                assert_eq!(vec![120.0, 120.0, 120.0], values);
+            }
+            e => panic!("unexpected expression {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_aggregate_by_labels() {
+        let data = requests();
+        let e = Evaluator::new(&data);
+
+        let sum = Expr::Aggregate(Aggregate {
+            op: AggregateOp::Sum,
+            expr: Box::new(call("rate", vec![range("http_requests_total", 60)])),
+            param: None,
+            grouping: Some(Grouping::By(vec!["job".to_owned()])),
+        });
+        let result = instant_values(e.instant(&sum, at(300)).unwrap());
+        assert_eq!(1, result.len());
+        assert_eq!(metric(&[("job", "api")]), result[0].0);
+        assert!((result[0].1 - 3.0).abs() < 1e-9);
+
+        let topk = Expr::Aggregate(Aggregate {
+            op: AggregateOp::Topk,
+            expr: Box::new(selector("http_requests_total")),
+            param: Some(Box::new(Expr::Number(1.0))),
+            grouping: None,
+        });
+        let result = instant_values(e.instant(&topk, at(300)).unwrap());
+        assert_eq!(vec![(data[0].metric.clone(), 600.0)], result);
+    }
+
+    #[test]
+    fn should_compute_histogram_quantile() {
+        let bucket = |le: &str, v: f64| {
+            range_secs(
+                &[("__name__", "latency_bucket"), ("job", "api"), ("le", le)],
+                &[(0, v)],
+            )
+        };
+        let data = vec![
+            bucket("0.1", 50.0),
+            bucket("0.5", 90.0),
+            bucket("1", 100.0),
+            bucket("+Inf", 100.0),
+        ];
+        let e = Evaluator::new(&data);
+
+        let q = call(
+            "histogram_quantile",
+            vec![Expr::Number(0.9), selector("latency_bucket")],
+        );
+        assert_eq!(
+            vec![(metric(&[("job", "api")]), 0.5)],
+            instant_values(e.instant(&q, at(0)).unwrap())
+        );
+
+        let q = call(
+            "histogram_quantile",
+            vec![Expr::Number(0.7), selector("latency_bucket")],
+        );
+        let v = instant_values(e.instant(&q, at(0)).unwrap())[0].1;
+        assert!((v - 0.3).abs() < 1e-9, "{}", v);
+    }
+
+    #[test]
+    fn should_match_vectors_with_group_left() {
+        let data = vec![
+            range_secs(
+                &[("__name__", "errors"), ("job", "api"), ("instance", "a")],
+                &[(0, 5.0)],
+            ),
+            range_secs(
+                &[("__name__", "errors"), ("job", "api"), ("instance", "b")],
+                &[(0, 10.0)],
+            ),
+            range_secs(
+                &[("__name__", "requests"), ("job", "api"), ("team", "web")],
+                &[(0, 100.0)],
+            ),
+        ];
+        let e = Evaluator::new(&data);
+
+        let ratio = binary(
+            BinaryOp::Div,
+            selector("errors"),
+            selector("requests"),
+            Some(VectorMatching {
+                labels: Matching::On(vec!["job".to_owned()]),
+                group: Some(GroupModifier::Left(vec!["team".to_owned()])),
+            }),
+        );
+        let mut result = instant_values(e.instant(&ratio, at(0)).unwrap());
+        result.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        assert_eq!(
+            vec![
+                (
+                    metric(&[("job", "api"), ("instance", "a"), ("team", "web")]),
+                    0.05
+                ),
+                (
+                    metric(&[("job", "api"), ("instance", "b"), ("team", "web")]),
+                    0.1
+                ),
+            ],
+            result
+        );
+
+        // without group_left the many-to-one match is an error
+        let ratio = binary(
+            BinaryOp::Div,
+            selector("errors"),
+            selector("requests"),
+            Some(VectorMatching {
+                labels: Matching::On(vec!["job".to_owned()]),
+                group: None,
+            }),
+        );
+        match e.instant(&ratio, at(0)).unwrap_err().kind() {
+            ErrorKind::Evaluation { .. } => {}
+            k => panic!("unexpected error {:?}", k),
+        }
+    }
+
+    #[test]
+    fn should_filter_with_comparisons_and_set_operators() {
+        let data = requests();
+        let e = Evaluator::new(&data);
+
+        let high = binary(
+            BinaryOp::Gt,
+            selector("http_requests_total"),
+            Expr::Number(200.0),
+            None,
+        );
+        let result = instant_values(e.instant(&high, at(300)).unwrap());
+        assert_eq!(vec![(data[0].metric.clone(), 600.0)], result);
+
+        let errors = Expr::VectorSelector(VectorSelector {
+            matchers: vec![LabelMatcher::equal("code", "500")],
+            ..VectorSelector::new("http_requests_total")
+        });
+        let unless = binary(
+            BinaryOp::Unless,
+            selector("http_requests_total"),
+            errors,
+            None,
+        );
+        let result = instant_values(e.instant(&unless, at(300)).unwrap());
+        assert_eq!(vec![(data[0].metric.clone(), 600.0)], result);
+    }
+
+    #[test]
+    fn should_evaluate_range_queries() {
+        let data = requests();
+        let e = Evaluator::new(&data);
+
+        let sum = Expr::Aggregate(Aggregate {
+            op: AggregateOp::Sum,
+            expr: Box::new(selector("http_requests_total")),
+            param: None,
+            grouping: None,
+        });
+        match e
+            .range(&sum, at(0), at(60), Duration::from_secs(30))
+            .unwrap()
+        {
+            Expression::Range(r) => {
+                assert_eq!(1, r.len());
+                assert_eq!(
+                    vec![0.0, 90.0, 180.0],
+                    r[0].samples.iter().map(|s| s.value).collect::<Vec<_>>()
+                );
+                assert_eq!(60.0, r[0].samples[2].epoch);
+            }
+            e => panic!("unexpected expression {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splits PromQL text into tokens.
+
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::units;
+use crate::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Metric, label, function and keyword names.
+    Identifier(String),
+    Number(f64),
+    Duration(Duration),
+    String(String),
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    At,
+    /// `=`
+    Assign,
+    /// `!=`
+    NotEqual,
+    /// `=~`
+    RegexMatch,
+    /// `!~`
+    RegexNoMatch,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    /// `==`
+    Equal,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Eof,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    /// Byte offset of the token in the input.
+    pub pos: usize,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            TokenKind::Identifier(s) => write!(f, "identifier \"{}\"", s),
+            TokenKind::Number(n) => write!(f, "number \"{}\"", n),
+            TokenKind::Duration(d) => write!(f, "duration \"{}\"", units::format_duration(*d)),
+            TokenKind::String(s) => write!(f, "string {:?}", s),
+            TokenKind::LeftParen => f.write_str("\"(\""),
+            TokenKind::RightParen => f.write_str("\")\""),
+            TokenKind::LeftBrace => f.write_str("\"{\""),
+            TokenKind::RightBrace => f.write_str("\"}\""),
+            TokenKind::LeftBracket => f.write_str("\"[\""),
+            TokenKind::RightBracket => f.write_str("\"]\""),
+            TokenKind::Comma => f.write_str("\",\""),
+            TokenKind::Colon => f.write_str("\":\""),
+            TokenKind::At => f.write_str("\"@\""),
+            TokenKind::Assign => f.write_str("\"=\""),
+            TokenKind::NotEqual => f.write_str("\"!=\""),
+            TokenKind::RegexMatch => f.write_str("\"=~\""),
+            TokenKind::RegexNoMatch => f.write_str("\"!~\""),
+            TokenKind::Add => f.write_str("\"+\""),
+            TokenKind::Sub => f.write_str("\"-\""),
+            TokenKind::Mul => f.write_str("\"*\""),
+            TokenKind::Div => f.write_str("\"/\""),
+            TokenKind::Mod => f.write_str("\"%\""),
+            TokenKind::Pow => f.write_str("\"^\""),
+            TokenKind::Equal => f.write_str("\"==\""),
+            TokenKind::Greater => f.write_str("\">\""),
+            TokenKind::Less => f.write_str("\"<\""),
+            TokenKind::GreaterEqual => f.write_str("\">=\""),
+            TokenKind::LessEqual => f.write_str("\"<=\""),
+            TokenKind::Eof => f.write_str("end of input"),
+        }
+    }
+}
+
+/// Tokenize `input`, ending with a [TokenKind::Eof] token.
+pub(crate) fn lex(input: &str) -> Result<Vec<Token>> {
+    let mut lexer = Lexer { input, pos: 0 };
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => self.pos += c.len_utf8(),
+                Some('#') => {
+                    self.pos += self.rest().find('\n').unwrap_or_else(|| self.rest().len());
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        self.skip_whitespace_and_comments();
+        let pos = self.pos;
+        let token = |kind| Ok(Token { kind, pos });
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return token(TokenKind::Eof),
+        };
+
+        // two-character operators first
+        let two = [
+            ("!=", TokenKind::NotEqual),
+            ("=~", TokenKind::RegexMatch),
+            ("!~", TokenKind::RegexNoMatch),
+            ("==", TokenKind::Equal),
+            (">=", TokenKind::GreaterEqual),
+            ("<=", TokenKind::LessEqual),
+        ];
+        for (s, kind) in two.iter() {
+            if self.rest().starts_with(s) {
+                self.pos += 2;
+                return token(kind.clone());
+            }
+        }
+
+        let single = match c {
+            '(' => Some(TokenKind::LeftParen),
+            ')' => Some(TokenKind::RightParen),
+            '{' => Some(TokenKind::LeftBrace),
+            '}' => Some(TokenKind::RightBrace),
+            '[' => Some(TokenKind::LeftBracket),
+            ']' => Some(TokenKind::RightBracket),
+            ',' => Some(TokenKind::Comma),
+            ':' => Some(TokenKind::Colon),
+            '@' => Some(TokenKind::At),
+            '=' => Some(TokenKind::Assign),
+            '+' => Some(TokenKind::Add),
+            '-' => Some(TokenKind::Sub),
+            '*' => Some(TokenKind::Mul),
+            '/' => Some(TokenKind::Div),
+            '%' => Some(TokenKind::Mod),
+            '^' => Some(TokenKind::Pow),
+            '>' => Some(TokenKind::Greater),
+            '<' => Some(TokenKind::Less),
+            _ => None,
+        };
+        if let Some(kind) = single {
+            // ':' also starts recording rule names such as `:node_cpu:rate5m`
+            let starts_identifier = c == ':'
+                && self.rest()[1..]
+                    .chars()
+                    .next()
+                    .map_or(false, |c| c.is_ascii_alphabetic() || c == '_');
+            if !starts_identifier {
+                self.pos += 1;
+                return token(kind);
+            }
+        }
+
+        if c == '"' || c == '\'' || c == '`' {
+            return token(TokenKind::String(self.string(c)?));
+        }
+        if c.is_ascii_digit()
+            || (c == '.' && self.rest()[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            return self.number_or_duration();
+        }
+        if c.is_ascii_alphabetic() || c == '_' || c == ':' {
+            let len = self
+                .rest()
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+                .unwrap_or_else(|| self.rest().len());
+            let word = &self.rest()[..len];
+            self.pos += len;
+            // `inf` and `nan` stay identifiers, since they're also valid
+            // label names. The parser reads them as numbers in expressions
+            return token(TokenKind::Identifier(word.to_owned()));
+        }
+
+        Err(syntax_error(
+            self.input,
+            pos,
+            format!("unexpected character {:?}", c),
+        ))
+    }
+
+    fn number_or_duration(&mut self) -> Result<Token> {
+        let pos = self.pos;
+        let rest = self.rest();
+
+        let duration_len = duration_len(rest);
+        if duration_len > 0 {
+            let s = &rest[..duration_len];
+            self.pos += duration_len;
+            let d = units::parse_duration(s).map_err(|_| {
+                syntax_error(self.input, pos, format!("invalid duration \"{}\"", s))
+            })?;
+            return Ok(Token {
+                kind: TokenKind::Duration(d),
+                pos,
+            });
+        }
+
+        let len = if rest.starts_with("0x") || rest.starts_with("0X") {
+            2 + rest[2..]
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(rest.len() - 2)
+        } else {
+            let mut len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            if rest[len..].starts_with(&['e', 'E'][..]) {
+                let exponent = &rest[len + 1..];
+                let sign = if exponent.starts_with(&['+', '-'][..]) {
+                    1
+                } else {
+                    0
+                };
+                let digits = exponent[sign..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(exponent.len() - sign);
+                if digits > 0 {
+                    len += 1 + sign + digits;
+                }
+            }
+            len
+        };
+
+        let s = &rest[..len];
+        self.pos += len;
+        if self
+            .peek()
+            .map_or(false, |c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(syntax_error(
+                self.input,
+                pos,
+                format!(
+                    "bad number or duration syntax \"{}{}\"",
+                    s,
+                    self.peek().unwrap()
+                ),
+            ));
+        }
+        let n = if len > 2 && (s.starts_with("0x") || s.starts_with("0X")) {
+            i64::from_str_radix(&s[2..], 16).map(|n| n as f64).ok()
+        } else {
+            s.parse::<f64>().ok()
+        };
+        match n {
+            Some(n) => Ok(Token {
+                kind: TokenKind::Number(n),
+                pos,
+            }),
+            None => Err(syntax_error(
+                self.input,
+                pos,
+                format!("bad number syntax \"{}\"", s),
+            )),
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    return Err(syntax_error(
+                        self.input,
+                        start,
+                        "unterminated quoted string",
+                    ));
+                }
+            };
+            self.pos += c.len_utf8();
+            if c == quote {
+                return Ok(s);
+            }
+            if quote == '`' {
+                s.push(c);
+                continue;
+            }
+            if c == '\n' {
+                return Err(syntax_error(
+                    self.input,
+                    start,
+                    "unterminated quoted string",
+                ));
+            }
+            if c != '\\' {
+                s.push(c);
+                continue;
+            }
+
+            let escape_pos = self.pos - 1;
+            let e = match self.peek() {
+                Some(e) => e,
+                None => {
+                    return Err(syntax_error(
+                        self.input,
+                        start,
+                        "unterminated quoted string",
+                    ));
+                }
+            };
+            self.pos += e.len_utf8();
+            let unescaped = match e {
+                'a' => '\u{7}',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'v' => '\u{b}',
+                '\\' | '"' | '\'' => e,
+                'x' | 'u' | 'U' => {
+                    let len = match e {
+                        'x' => 2,
+                        'u' => 4,
+                        _ => 8,
+                    };
+                    let hex = self.rest().get(..len).unwrap_or("");
+                    let c = u32::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == len)
+                        .and_then(std::char::from_u32);
+                    match c {
+                        Some(c) => {
+                            self.pos += len;
+                            c
+                        }
+                        None => {
+                            return Err(syntax_error(
+                                self.input,
+                                escape_pos,
+                                "invalid escape sequence",
+                            ));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(syntax_error(
+                        self.input,
+                        escape_pos,
+                        format!("unknown escape sequence '\\{}'", e),
+                    ));
+                }
+            };
+            s.push(unescaped);
+        }
+    }
+}
+
+// Length of the duration at the start of `s`, e.g. 5 for `1h30m`, or 0
+// if `s` doesn't start with one.
+fn duration_len(s: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let rest = &s[len..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            break;
+        }
+        let unit = ["ms", "y", "w", "d", "h", "m", "s"]
+            .iter()
+            .find(|u| rest[digits..].starts_with(*u));
+        match unit {
+            Some(u) => len += digits + u.len(),
+            None => break,
+        }
+    }
+
+    let followed_by_word = s[len..]
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if followed_by_word {
+        0
+    } else {
+        len
+    }
+}
+
+/// Line and column (both 1-based, columns in characters) of byte offset `pos`.
+fn line_column(input: &str, pos: usize) -> (usize, usize) {
+    let before = &input[..pos];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+pub(crate) fn syntax_error<S: Into<String>>(input: &str, pos: usize, message: S) -> Error {
+    let (line, column) = line_column(input, pos);
+    Error::new_invalid_promql_error(message, pos, line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::promql::lexer::{lex, TokenKind};
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        lex(input).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn should_lex_numbers_and_durations() {
+        assert_eq!(
+            vec![
+                TokenKind::Number(1.5),
+                TokenKind::Number(255.0),
+                TokenKind::Number(1e3),
+                TokenKind::Duration(Duration::from_secs(5400)),
+                TokenKind::Duration(Duration::from_millis(250)),
+                TokenKind::Eof,
+            ],
+            kinds("1.5 0xff 1e3 1h30m 250ms")
+        );
+        assert!(lex("1x").is_err());
+    }
+
+    #[test]
+    fn should_lex_escaped_strings() {
+        assert_eq!(
+            vec![
+                TokenKind::String("a\"b\n".to_owned()),
+                TokenKind::String("\\d+".to_owned()),
+                TokenKind::String("é".to_owned()),
+                TokenKind::Eof,
+            ],
+            kinds(r#""a\"b\n" `\d+` 'é'"#)
+        );
+    }
+
+    #[test]
+    fn should_lex_recording_rule_names() {
+        assert_eq!(
+            vec![
+                TokenKind::Identifier("job:up:sum".to_owned()),
+                TokenKind::LeftBracket,
+                TokenKind::Duration(Duration::from_secs(300)),
+                TokenKind::Colon,
+                TokenKind::RightBracket,
+                TokenKind::Eof,
+            ],
+            kinds("job:up:sum[5m:] # comment")
+        );
+    }
+}
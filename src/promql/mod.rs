@@ -0,0 +1,33 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PromQL expressions, a parser and a [Builder] for them, and an
+//! [Evaluator] that runs them over series that have already been fetched,
+//! without calling the server again.
+
+pub use self::ast::Expr;
+pub use self::builder::{metric, Builder};
+pub use self::eval::Evaluator;
+pub use self::parser::parse;
+pub use self::query::Query;
+
+pub mod ast;
+pub mod builder;
+pub mod eval;
+mod functions;
+mod lexer;
+pub mod parser;
+pub mod pretty;
+mod query;
+pub mod rewrite;
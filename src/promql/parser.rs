@@ -0,0 +1,1035 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recursive-descent PromQL parser, with the same type checks as Prometheus.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::matcher::{LabelMatcher, MatchOp};
+use crate::promql::ast::{
+    Aggregate, AggregateOp, At, Binary, BinaryOp, Call, Expr, GroupModifier, Grouping, Matching,
+    MatrixSelector, Offset, Subquery, ValueType, VectorMatching, VectorSelector,
+};
+use crate::promql::functions;
+use crate::promql::lexer::{self, Token, TokenKind};
+use crate::{Error, Result};
+
+/// Parse a PromQL expression.
+///
+/// Errors are [InvalidPromQl](crate::error::ErrorKind::InvalidPromQl)
+/// and carry the position of the offending token.
+pub fn parse(input: &str) -> Result<Expr> {
+    let tokens = lexer::lex(input)?;
+    let mut parser = Parser {
+        input,
+        tokens,
+        next: 0,
+    };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        TokenKind::Eof => Ok(expr),
+        t => Err(parser.error_here(format!("unexpected {}", t))),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Expr> {
+        parse(s)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+const UNARY_PRECEDENCE: u8 = 6;
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.next].kind
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens[self.next].pos
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].clone();
+        if token.kind != TokenKind::Eof {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn error<S: Into<String>>(&self, pos: usize, message: S) -> Error {
+        lexer::syntax_error(self.input, pos, message)
+    }
+
+    fn error_here<S: Into<String>>(&self, message: S) -> Error {
+        self.error(self.pos(), message)
+    }
+
+    fn expect(&mut self, kind: TokenKind, context: &str) -> Result<Token> {
+        if *self.peek() == kind {
+            Ok(self.advance())
+        } else {
+            Err(self.error_here(format!(
+                "unexpected {} in {}, expected {}",
+                self.peek(),
+                context,
+                kind
+            )))
+        }
+    }
+
+    /// Whether the next token is the (case-insensitive) keyword `keyword`.
+    fn at_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            TokenKind::Identifier(s) => s.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.at_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek_binary_op(&self) -> Option<BinaryOp> {
+        let op = match self.peek() {
+            TokenKind::Add => BinaryOp::Add,
+            TokenKind::Sub => BinaryOp::Sub,
+            TokenKind::Mul => BinaryOp::Mul,
+            TokenKind::Div => BinaryOp::Div,
+            TokenKind::Mod => BinaryOp::Mod,
+            TokenKind::Pow => BinaryOp::Pow,
+            TokenKind::Equal => BinaryOp::Eq,
+            TokenKind::NotEqual => BinaryOp::Ne,
+            TokenKind::Greater => BinaryOp::Gt,
+            TokenKind::Less => BinaryOp::Lt,
+            TokenKind::GreaterEqual => BinaryOp::Ge,
+            TokenKind::LessEqual => BinaryOp::Le,
+            TokenKind::Identifier(s) => match s.to_lowercase().as_str() {
+                "and" => BinaryOp::And,
+                "or" => BinaryOp::Or,
+                "unless" => BinaryOp::Unless,
+                "atan2" => BinaryOp::Atan2,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek_binary_op() {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => return Ok(lhs),
+            };
+            let op_pos = self.advance().pos;
+
+            let return_bool = self.eat_keyword("bool");
+            if return_bool && !op.is_comparison() {
+                return Err(self.error(
+                    op_pos,
+                    "bool modifier can only be used on comparison operators",
+                ));
+            }
+            let matching = self.vector_matching(op)?;
+
+            let rhs_precedence = if op.is_right_associative() {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let rhs = self.expr(rhs_precedence)?;
+
+            let binary = Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+                return_bool,
+                matching,
+            };
+            self.check_binary(&binary, op_pos)?;
+            lhs = Expr::Binary(binary);
+        }
+    }
+
+    fn vector_matching(&mut self, op: BinaryOp) -> Result<Option<VectorMatching>> {
+        let labels = if self.eat_keyword("on") {
+            Matching::On(self.label_list()?)
+        } else if self.eat_keyword("ignoring") {
+            Matching::Ignoring(self.label_list()?)
+        } else {
+            if self.at_keyword("group_left") || self.at_keyword("group_right") {
+                return Err(self.error_here("group modifiers must follow on or ignoring"));
+            }
+            return Ok(None);
+        };
+
+        let group_pos = self.pos();
+        let group = if self.eat_keyword("group_left") {
+            Some(GroupModifier::Left(self.optional_label_list()?))
+        } else if self.eat_keyword("group_right") {
+            Some(GroupModifier::Right(self.optional_label_list()?))
+        } else {
+            None
+        };
+        if group.is_some() && op.is_set() {
+            return Err(self.error(
+                group_pos,
+                format!("no grouping allowed for \"{}\" operation", op.as_str()),
+            ));
+        }
+
+        Ok(Some(VectorMatching { labels, group }))
+    }
+
+    fn check_binary(&self, b: &Binary, pos: usize) -> Result<()> {
+        let (lhs, rhs) = (b.lhs.value_type(), b.rhs.value_type());
+        let operand_ok = |t| t == ValueType::Scalar || t == ValueType::Vector;
+        if !operand_ok(lhs) || !operand_ok(rhs) {
+            return Err(self.error(
+                pos,
+                "binary expression must contain only scalar and instant vector types",
+            ));
+        }
+
+        let both_vectors = lhs == ValueType::Vector && rhs == ValueType::Vector;
+        if b.op.is_set() && !both_vectors {
+            return Err(self.error(
+                pos,
+                format!(
+                    "set operator \"{}\" not allowed in binary scalar expression",
+                    b.op.as_str()
+                ),
+            ));
+        }
+        if b.matching.is_some() && !both_vectors {
+            return Err(self.error(pos, "vector matching only allowed between instant vectors"));
+        }
+        if b.op.is_comparison() && !b.return_bool && lhs == ValueType::Scalar && rhs == lhs {
+            return Err(self.error(pos, "comparisons between scalars must use BOOL modifier"));
+        }
+
+        if let Some(VectorMatching {
+            labels: Matching::On(on),
+            group: Some(GroupModifier::Left(include)),
+        })
+        | Some(VectorMatching {
+            labels: Matching::On(on),
+            group: Some(GroupModifier::Right(include)),
+        }) = &b.matching
+        {
+            if let Some(l) = include.iter().find(|l| on.contains(l)) {
+                return Err(self.error(
+                    pos,
+                    format!(
+                        "label \"{}\" must not occur in ON and GROUP clause at once",
+                        l
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let negate = match self.peek() {
+            TokenKind::Sub => true,
+            TokenKind::Add => false,
+            _ => return self.postfix(),
+        };
+        let pos = self.advance().pos;
+
+        // binds looser than `^`, so `-a ^ b` is `-(a ^ b)`
+        let expr = self.expr(UNARY_PRECEDENCE)?;
+        match expr.value_type() {
+            ValueType::Scalar | ValueType::Vector => {}
+            _ => {
+                return Err(self.error(
+                    pos,
+                    "unary expression only allowed on expressions of type scalar or instant vector",
+                ));
+            }
+        }
+        Ok(match expr {
+            e if !negate => e,
+            Expr::Number(n) => Expr::Number(-n),
+            e => Expr::Negate(Box::new(e)),
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            expr = match self.peek() {
+                TokenKind::LeftBracket => self.range(expr)?,
+                TokenKind::At => self.at(expr)?,
+                _ if self.at_keyword("offset") => self.offset(expr)?,
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn range(&mut self, expr: Expr) -> Result<Expr> {
+        let pos = self.advance().pos;
+        let range = self.duration("range")?;
+
+        if *self.peek() == TokenKind::Colon {
+            self.advance();
+            let step = match self.peek() {
+                TokenKind::Duration(_) => Some(self.duration("subquery step")?),
+                _ => None,
+            };
+            self.expect(TokenKind::RightBracket, "subquery")?;
+            if expr.value_type() != ValueType::Vector {
+                return Err(self.error(
+                    pos,
+                    format!(
+                        "subquery is only allowed on instant vector, got {:?}",
+                        expr.value_type()
+                    ),
+                ));
+            }
+            return Ok(Expr::Subquery(Subquery {
+                expr: Box::new(expr),
+                range,
+                step,
+                offset: None,
+                at: None,
+            }));
+        }
+
+        self.expect(TokenKind::RightBracket, "range")?;
+        match expr {
+            Expr::VectorSelector(selector) => {
+                if selector.offset.is_some() || selector.at.is_some() {
+                    return Err(self.error(pos, "no offset or @ modifiers allowed before range"));
+                }
+                Ok(Expr::MatrixSelector(MatrixSelector { selector, range }))
+            }
+            _ => Err(self.error(pos, "ranges only allowed for vector selectors")),
+        }
+    }
+
+    fn duration(&mut self, context: &str) -> Result<Duration> {
+        match self.peek() {
+            TokenKind::Duration(d) => {
+                let d = *d;
+                self.advance();
+                Ok(d)
+            }
+            t => Err(self.error_here(format!(
+                "unexpected {} in {}, expected duration",
+                t, context
+            ))),
+        }
+    }
+
+    fn offset(&mut self, mut expr: Expr) -> Result<Expr> {
+        let pos = self.advance().pos;
+        let negative = match self.peek() {
+            TokenKind::Sub => {
+                self.advance();
+                true
+            }
+            _ => false,
+        };
+        let offset = Some(Offset {
+            duration: self.duration("offset")?,
+            negative,
+        });
+
+        let target = match &mut expr {
+            Expr::VectorSelector(s) => &mut s.offset,
+            Expr::MatrixSelector(s) => &mut s.selector.offset,
+            Expr::Subquery(s) => &mut s.offset,
+            _ => {
+                return Err(self.error(
+                    pos,
+                    "offset modifier must be preceded by an instant vector selector or range vector selector or a subquery",
+                ));
+            }
+        };
+        if target.is_some() {
+            return Err(self.error(pos, "offset may not be set multiple times"));
+        }
+        *target = offset;
+        Ok(expr)
+    }
+
+    fn at(&mut self, mut expr: Expr) -> Result<Expr> {
+        let pos = self.advance().pos;
+        let at = match self.peek().clone() {
+            TokenKind::Number(n) => {
+                self.advance();
+                At::Timestamp((n * 1000.0).round() as i64)
+            }
+            TokenKind::Sub => {
+                self.advance();
+                match self.peek() {
+                    TokenKind::Number(n) => {
+                        let n = *n;
+                        self.advance();
+                        At::Timestamp((-n * 1000.0).round() as i64)
+                    }
+                    t => {
+                        return Err(
+                            self.error_here(format!("unexpected {} in @, expected timestamp", t))
+                        )
+                    }
+                }
+            }
+            TokenKind::Identifier(ref s) if s == "start" || s == "end" => {
+                self.advance();
+                self.expect(TokenKind::LeftParen, "@")?;
+                self.expect(TokenKind::RightParen, "@")?;
+                if s == "start" {
+                    At::Start
+                } else {
+                    At::End
+                }
+            }
+            t => {
+                return Err(self.error_here(format!(
+                    "unexpected {} in @, expected timestamp, start() or end()",
+                    t
+                )));
+            }
+        };
+
+        let target = match &mut expr {
+            Expr::VectorSelector(s) => &mut s.at,
+            Expr::MatrixSelector(s) => &mut s.selector.at,
+            Expr::Subquery(s) => &mut s.at,
+            _ => {
+                return Err(self.error(
+                    pos,
+                    "@ modifier must be preceded by an instant vector selector or range vector selector or a subquery",
+                ));
+            }
+        };
+        if target.is_some() {
+            return Err(self.error(pos, "@ <timestamp> may not be set multiple times"));
+        }
+        *target = Some(at);
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(n) => Ok(Expr::Number(n)),
+            TokenKind::String(s) => Ok(Expr::String(s)),
+            TokenKind::LeftParen => {
+                let expr = self.expr(0)?;
+                self.expect(TokenKind::RightParen, "parenthesized expression")?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            TokenKind::LeftBrace => self.selector(None, token.pos),
+            TokenKind::Identifier(name) => {
+                if let Some(n) = number_literal(&name) {
+                    return Ok(Expr::Number(n));
+                }
+                if let Some(op) = aggregate_op(&name) {
+                    let starts_aggregation = *self.peek() == TokenKind::LeftParen
+                        || self.at_keyword("by")
+                        || self.at_keyword("without");
+                    if starts_aggregation {
+                        return self.aggregate(op, token.pos);
+                    }
+                }
+                if *self.peek() == TokenKind::LeftParen {
+                    return self.call(name, token.pos);
+                }
+                if is_keyword(&name) {
+                    return Err(self.error(token.pos, format!("unexpected keyword \"{}\"", name)));
+                }
+                if *self.peek() == TokenKind::LeftBrace {
+                    self.advance();
+                    self.selector(Some(name), token.pos)
+                } else {
+                    Ok(Expr::VectorSelector(VectorSelector::new(name)))
+                }
+            }
+            t => Err(self.error(token.pos, format!("unexpected {}", t))),
+        }
+    }
+
+    // called after the opening brace
+    fn selector(&mut self, mut name: Option<String>, pos: usize) -> Result<Expr> {
+        let mut matchers = Vec::new();
+        loop {
+            let label_pos = self.pos();
+            let (label, quoted) = match self.advance().kind {
+                TokenKind::RightBrace => break,
+                TokenKind::Identifier(s) => (s, false),
+                TokenKind::String(s) => (s, true),
+                t => {
+                    return Err(self.error(
+                        label_pos,
+                        format!("unexpected {} in label matching, expected label", t),
+                    ));
+                }
+            };
+
+            let op = match self.peek() {
+                TokenKind::Assign => Some(MatchOp::Equal),
+                TokenKind::NotEqual => Some(MatchOp::NotEqual),
+                TokenKind::RegexMatch => Some(MatchOp::RegexMatch),
+                TokenKind::RegexNoMatch => Some(MatchOp::RegexNoMatch),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.advance();
+                    let value_pos = self.pos();
+                    let value = match self.advance().kind {
+                        TokenKind::String(s) => s,
+                        t => {
+                            return Err(self.error(
+                                value_pos,
+                                format!("unexpected {} in label matching, expected string", t),
+                            ));
+                        }
+                    };
+                    let matcher = LabelMatcher::new(label, op, value)
+                        .map_err(|e| self.error(value_pos, e.to_string()))?;
+                    matchers.push(matcher);
+                }
+                // a quoted metric name, e.g. `{"my.metric", job="x"}`
+                None if quoted && name.is_none() => name = Some(label),
+                None => {
+                    return Err(self.error_here(format!(
+                        "unexpected {} in label matching, expected label matching operator",
+                        self.peek()
+                    )));
+                }
+            }
+
+            match self.peek() {
+                TokenKind::Comma => {
+                    self.advance();
+                }
+                TokenKind::RightBrace => {}
+                t => {
+                    return Err(self.error_here(format!(
+                        "unexpected {} in label matching, expected \",\" or \"}}\"",
+                        t
+                    )));
+                }
+            }
+        }
+
+        if name.is_none() && matchers.iter().all(|m| m.matches(None)) {
+            return Err(self.error(
+                pos,
+                "vector selector must contain at least one non-empty matcher",
+            ));
+        }
+        Ok(Expr::VectorSelector(VectorSelector {
+            name,
+            matchers,
+            offset: None,
+            at: None,
+        }))
+    }
+
+    fn label_list(&mut self) -> Result<Vec<String>> {
+        self.expect(TokenKind::LeftParen, "grouping")?;
+        let mut labels = Vec::new();
+        loop {
+            let pos = self.pos();
+            match self.advance().kind {
+                TokenKind::RightParen => return Ok(labels),
+                TokenKind::Identifier(s) | TokenKind::String(s) => labels.push(s),
+                t => {
+                    return Err(self.error(
+                        pos,
+                        format!("unexpected {} in grouping opts, expected label", t),
+                    ));
+                }
+            }
+            match self.peek() {
+                TokenKind::Comma => {
+                    self.advance();
+                }
+                TokenKind::RightParen => {}
+                t => {
+                    return Err(self.error_here(format!(
+                        "unexpected {} in grouping opts, expected \",\" or \")\"",
+                        t
+                    )));
+                }
+            }
+        }
+    }
+
+    fn optional_label_list(&mut self) -> Result<Vec<String>> {
+        if *self.peek() == TokenKind::LeftParen {
+            self.label_list()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn grouping(&mut self) -> Result<Option<Grouping>> {
+        if self.eat_keyword("by") {
+            Ok(Some(Grouping::By(self.label_list()?)))
+        } else if self.eat_keyword("without") {
+            Ok(Some(Grouping::Without(self.label_list()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn aggregate(&mut self, op: AggregateOp, pos: usize) -> Result<Expr> {
+        let mut grouping = self.grouping()?;
+        self.expect(TokenKind::LeftParen, "aggregation")?;
+
+        let param = if op.has_param() {
+            let param_pos = self.pos();
+            let param = self.expr(0)?;
+            let expected = if op == AggregateOp::CountValues {
+                ValueType::String
+            } else {
+                ValueType::Scalar
+            };
+            if param.value_type() != expected {
+                return Err(self.error(
+                    param_pos,
+                    format!(
+                        "expected type {:?} in aggregation parameter, got {:?}",
+                        expected,
+                        param.value_type()
+                    ),
+                ));
+            }
+            self.expect(TokenKind::Comma, "aggregation")?;
+            Some(Box::new(param))
+        } else {
+            None
+        };
+
+        let expr_pos = self.pos();
+        let expr = self.expr(0)?;
+        if expr.value_type() != ValueType::Vector {
+            return Err(self.error(
+                expr_pos,
+                format!(
+                    "expected type Vector in aggregation expression, got {:?}",
+                    expr.value_type()
+                ),
+            ));
+        }
+        self.expect(TokenKind::RightParen, "aggregation")?;
+
+        if grouping.is_none() {
+            grouping = self.grouping()?;
+        } else if self.at_keyword("by") || self.at_keyword("without") {
+            return Err(self.error(pos, "aggregation may only have one grouping clause"));
+        }
+
+        Ok(Expr::Aggregate(Aggregate {
+            op,
+            expr: Box::new(expr),
+            param,
+            grouping,
+        }))
+    }
+
+    fn call(&mut self, func: String, pos: usize) -> Result<Expr> {
+        let signature = match functions::function(&func) {
+            Some(f) => f,
+            None => {
+                return Err(self.error(pos, format!("unknown function with name \"{}\"", func)));
+            }
+        };
+        self.advance();
+
+        let mut args = Vec::new();
+        if *self.peek() == TokenKind::RightParen {
+            self.advance();
+        } else {
+            loop {
+                let arg_pos = self.pos();
+                let arg = self.expr(0)?;
+                let i = args.len();
+                let expected = signature.args.get(i).or_else(|| {
+                    if signature.optional < 0 {
+                        signature.args.last()
+                    } else {
+                        None
+                    }
+                });
+                match expected {
+                    Some(t) if *t != arg.value_type() => {
+                        return Err(self.error(
+                            arg_pos,
+                            format!(
+                                "expected type {:?} in call to function \"{}\", got {:?}",
+                                t,
+                                func,
+                                arg.value_type()
+                            ),
+                        ));
+                    }
+                    _ => {}
+                }
+                args.push(arg);
+                match self.advance().kind {
+                    TokenKind::Comma => {}
+                    TokenKind::RightParen => break,
+                    t => {
+                        return Err(self.error(
+                            self.tokens[self.next - 1].pos,
+                            format!("unexpected {} in function call, expected \",\" or \")\"", t),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // like Prometheus, the repeated argument of a variadic function may
+        // also be left out entirely
+        let max = signature.args.len();
+        let min = if signature.optional < 0 {
+            max - 1
+        } else {
+            max - signature.optional as usize
+        };
+        if args.len() < min || (signature.optional >= 0 && args.len() > max) {
+            return Err(self.error(
+                pos,
+                format!(
+                    "expected {} argument(s) in call to \"{}\", got {}",
+                    if signature.optional < 0 {
+                        format!("at least {}", min)
+                    } else if min == max {
+                        min.to_string()
+                    } else {
+                        format!("{} to {}", min, max)
+                    },
+                    func,
+                    args.len()
+                ),
+            ));
+        }
+
+        Ok(Expr::Call(Call { func, args }))
+    }
+}
+
+fn aggregate_op(name: &str) -> Option<AggregateOp> {
+    let op = match name.to_lowercase().as_str() {
+        "sum" => AggregateOp::Sum,
+        "avg" => AggregateOp::Avg,
+        "min" => AggregateOp::Min,
+        "max" => AggregateOp::Max,
+        "count" => AggregateOp::Count,
+        "group" => AggregateOp::Group,
+        "stddev" => AggregateOp::Stddev,
+        "stdvar" => AggregateOp::Stdvar,
+        "topk" => AggregateOp::Topk,
+        "bottomk" => AggregateOp::Bottomk,
+        "quantile" => AggregateOp::Quantile,
+        "count_values" => AggregateOp::CountValues,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// `inf` and `nan`, in any case, which the lexer leaves as identifiers.
+fn number_literal(name: &str) -> Option<f64> {
+    match name.to_lowercase().as_str() {
+        "inf" => Some(std::f64::INFINITY),
+        "nan" => Some(std::f64::NAN),
+        _ => None,
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    match name.to_lowercase().as_str() {
+        "and" | "or" | "unless" | "atan2" | "by" | "without" | "on" | "ignoring" | "group_left"
+        | "group_right" | "bool" | "offset" => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::error::ErrorKind;
+    use crate::matcher::LabelMatcher;
+    use crate::promql::ast::{
+        Aggregate, AggregateOp, At, Binary, BinaryOp, Call, Expr, GroupModifier, Grouping,
+        Matching, MatrixSelector, Offset, Subquery, VectorMatching, VectorSelector,
+    };
+    use crate::promql::parse;
+
+    #[test]
+    fn should_parse_selectors() {
+        assert_eq!(
+            Expr::MatrixSelector(MatrixSelector {
+                selector: VectorSelector {
+                    name: Some("http_requests_total".to_owned()),
+                    matchers: vec![
+                        LabelMatcher::equal("job", "api"),
+                        LabelMatcher::regex_match("code", "5..").unwrap(),
+                    ],
+                    offset: Some(Offset {
+                        duration: Duration::from_secs(3600),
+                        negative: false,
+                    }),
+                    at: Some(At::Timestamp(1_609_746_000_000)),
+                },
+                range: Duration::from_secs(300),
+            }),
+            parse(r#"http_requests_total{job="api", code=~"5..",}[5m] @ 1609746000 offset 1h"#)
+                .unwrap()
+        );
+
+        assert_eq!(
+            Expr::VectorSelector(VectorSelector {
+                name: Some("my.metric".to_owned()),
+                matchers: vec![LabelMatcher::not_equal("env", "dev")],
+                offset: Some(Offset {
+                    duration: Duration::from_secs(300),
+                    negative: true,
+                }),
+                at: Some(At::End),
+            }),
+            parse(r#"{"my.metric", env!="dev"} offset -5m @ end()"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_parse_aggregations_and_calls() {
+        let expected = Expr::Aggregate(Aggregate {
+            op: AggregateOp::Sum,
+            expr: Box::new(Expr::Call(Call {
+                func: "rate".to_owned(),
+                args: vec![Expr::MatrixSelector(MatrixSelector {
+                    selector: VectorSelector::new("http_requests_total"),
+                    range: Duration::from_secs(300),
+                })],
+            })),
+            param: None,
+            grouping: Some(Grouping::By(vec!["job".to_owned(), "code".to_owned()])),
+        });
+        assert_eq!(
+            expected,
+            parse("sum by (job, code) (rate(http_requests_total[5m]))").unwrap()
+        );
+        assert_eq!(
+            expected,
+            parse("SUM(rate(http_requests_total[5m])) BY (job, code)").unwrap()
+        );
+
+        assert_eq!(
+            Expr::Aggregate(Aggregate {
+                op: AggregateOp::Topk,
+                expr: Box::new(Expr::VectorSelector(VectorSelector::new("up"))),
+                param: Some(Box::new(Expr::Number(3.0))),
+                grouping: Some(Grouping::Without(vec!["instance".to_owned()])),
+            }),
+            parse("topk without (instance) (3, up)").unwrap()
+        );
+
+        // variadic functions may omit their repeated argument
+        for query in [
+            r#"label_join(up, "dst", ",")"#,
+            r#"label_join(up, "dst", ",", "a", "b")"#,
+            "sort_by_label(up)",
+        ]
+        .iter()
+        {
+            assert!(parse(query).is_ok(), "{}", query);
+        }
+        assert!(parse(r#"label_join(up, "dst")"#).is_err());
+    }
+
+    #[test]
+    fn should_parse_inf_and_nan_as_numbers_or_label_names() {
+        assert_eq!(Expr::Number(std::f64::INFINITY), parse("Inf").unwrap());
+        match parse("nAn").unwrap() {
+            Expr::Number(n) => assert!(n.is_nan()),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(
+            Expr::Aggregate(Aggregate {
+                op: AggregateOp::Topk,
+                expr: Box::new(Expr::VectorSelector(VectorSelector::new("up"))),
+                param: Some(Box::new(Expr::Number(std::f64::INFINITY))),
+                grouping: Some(Grouping::By(vec!["inf".to_owned()])),
+            }),
+            parse("topk by (inf) (inf, up)").unwrap()
+        );
+
+        assert_eq!(
+            Expr::VectorSelector(VectorSelector {
+                name: Some("up".to_owned()),
+                matchers: vec![LabelMatcher::equal("nan", "x")],
+                offset: None,
+                at: None,
+            }),
+            parse(r#"up{nan="x"}"#).unwrap()
+        );
+        match parse("a + on (NaN) b").unwrap() {
+            Expr::Binary(b) => assert_eq!(
+                Some(Matching::On(vec!["NaN".to_owned()])),
+                b.matching.map(|m| m.labels)
+            ),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_parse_binary_operators_with_precedence() {
+        let selector = |n: &str| Box::new(Expr::VectorSelector(VectorSelector::new(n)));
+        assert_eq!(
+            Expr::Binary(Binary {
+                op: BinaryOp::Add,
+                lhs: selector("a"),
+                rhs: Box::new(Expr::Binary(Binary {
+                    op: BinaryOp::Div,
+                    lhs: selector("b"),
+                    rhs: selector("c"),
+                    return_bool: false,
+                    matching: Some(VectorMatching {
+                        labels: Matching::On(vec!["job".to_owned()]),
+                        group: Some(GroupModifier::Left(vec!["team".to_owned()])),
+                    }),
+                })),
+                return_bool: false,
+                matching: None,
+            }),
+            parse("a + b / on (job) group_left (team) c").unwrap()
+        );
+
+        // `^` is right-associative and binds tighter than unary minus
+        assert_eq!(
+            Expr::Negate(Box::new(Expr::Binary(Binary {
+                op: BinaryOp::Pow,
+                lhs: Box::new(Expr::Number(2.0)),
+                rhs: Box::new(Expr::Binary(Binary {
+                    op: BinaryOp::Pow,
+                    lhs: Box::new(Expr::Number(3.0)),
+                    rhs: Box::new(Expr::Number(2.0)),
+                    return_bool: false,
+                    matching: None,
+                })),
+                return_bool: false,
+                matching: None,
+            }))),
+            parse("-2 ^ 3 ^ 2").unwrap()
+        );
+
+        assert_eq!(
+            Expr::Binary(Binary {
+                op: BinaryOp::Gt,
+                lhs: Box::new(Expr::Number(1.0)),
+                rhs: Box::new(Expr::Number(2.0)),
+                return_bool: true,
+                matching: None,
+            }),
+            parse("1 > bool 2").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_parse_subqueries() {
+        assert_eq!(
+            Expr::Call(Call {
+                func: "max_over_time".to_owned(),
+                args: vec![Expr::Subquery(Subquery {
+                    expr: Box::new(Expr::Call(Call {
+                        func: "rate".to_owned(),
+                        args: vec![Expr::MatrixSelector(MatrixSelector {
+                            selector: VectorSelector::new("x"),
+                            range: Duration::from_secs(60),
+                        })],
+                    })),
+                    range: Duration::from_secs(3600),
+                    step: None,
+                    offset: None,
+                    at: Some(At::Start),
+                })],
+            }),
+            parse("max_over_time(rate(x[1m])[1h:] @ start())").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_report_error_positions() {
+        let cases = [
+            ("sum(rate(x[5m])", 1, 16, "unexpected end of input"),
+            ("up{job=\"a\"\n  , env=5}", 2, 9, "expected string"),
+            ("rate(x)", 1, 6, "expected type Matrix"),
+            ("foo(x)", 1, 1, "unknown function"),
+            ("1 > 2", 1, 3, "BOOL modifier"),
+            ("x[5m] offset 1m offset 2m", 1, 17, "multiple times"),
+            ("{job=~\".*\"}", 1, 1, "non-empty matcher"),
+            ("a and on (x) group_left b", 1, 14, "no grouping"),
+        ];
+        for (input, line, column, message) in cases.iter() {
+            match parse(input).unwrap_err().kind() {
+                ErrorKind::InvalidPromQl {
+                    message: m,
+                    line: l,
+                    column: c,
+                    ..
+                } => {
+                    assert!(m.contains(message), "{}: {}", input, m);
+                    assert_eq!((*line, *column), (*l, *c), "{}: {}", input, m);
+                }
+                k => panic!("unexpected error {:?}", k),
+            }
+        }
+    }
+
+    #[test]
+    fn should_display_parseable_promql() {
+        let cases = [
+            r#"sum by (job) (rate(http_requests_total{code=~"5.."}[5m] offset 1h))"#,
+            "a + b / on (job) group_left (team) c",
+            "(a + b) / c",
+            "-2 ^ 3",
+            "max_over_time((a - b)[1h:5m] @ 1609746000.000)",
+            "histogram_quantile(0.99, sum without (pod) (rate(latency_bucket[5m])))",
+            r#"count_values("version", build_info)"#,
+            r#"{"my.metric", env!="dev"}"#,
+            "1 > bool 2",
+        ];
+        for input in cases.iter() {
+            let expr = parse(input).unwrap();
+            assert_eq!(*input, expr.to_string());
+            assert_eq!(expr, parse(&expr.to_string()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canonical PromQL formatting, following the layout of the server's
+//! `/api/v1/format_query` endpoint without needing a server.
+//!
+//! Expressions that fit on a line are printed on one. Longer ones are split,
+//! with the arguments of calls and aggregations and the operands of binary
+//! operators indented by two spaces. Label matchers are sorted by name.
+
+use crate::promql::ast::{needs_parens, needs_parens_for_postfix, write_modifiers, BinaryOp, Expr};
+use crate::promql::parser;
+use crate::{units, Result};
+
+/// Longest line, excluding indentation, kept on a single line.
+pub const MAX_LINE_WIDTH: usize = 100;
+
+const INDENT: &str = "  ";
+
+/// Parse `query` and print it in canonical form.
+pub fn format_query(query: &str) -> Result<String> {
+    Ok(prettify(&parser::parse(query)?))
+}
+
+/// Print `expr` in canonical form.
+pub fn prettify(expr: &Expr) -> String {
+    let mut expr = expr.clone();
+    sort_matchers(&mut expr);
+    let mut out = String::new();
+    write_pretty(&mut out, &expr, 0);
+    out
+}
+
+fn sort_matchers(expr: &mut Expr) {
+    expr.walk_mut(&mut |e| {
+        let selector = match e {
+            Expr::VectorSelector(s) => s,
+            Expr::MatrixSelector(s) => &mut s.selector,
+            _ => return,
+        };
+        selector.matchers.sort_by(|a, b| {
+            (&a.name, a.op.as_str(), &a.value).cmp(&(&b.name, b.op.as_str(), &b.value))
+        });
+    });
+}
+
+fn indent(out: &mut String, level: usize) {
+    for _ in 0..level {
+        out.push_str(INDENT);
+    }
+}
+
+// Writes `expr` at nesting `level`. The caller has already written the
+// indentation of the first line.
+fn write_pretty(out: &mut String, expr: &Expr, level: usize) {
+    let line = expr.to_string();
+    if line.len() <= MAX_LINE_WIDTH {
+        out.push_str(&line);
+        return;
+    }
+
+    match expr {
+        Expr::Number(_) | Expr::String(_) | Expr::VectorSelector(_) | Expr::MatrixSelector(_) => {
+            out.push_str(&line)
+        }
+        Expr::Paren(e) => {
+            out.push('(');
+            write_line(out, e, level + 1);
+            out.push('\n');
+            indent(out, level);
+            out.push(')');
+        }
+        Expr::Call(c) => {
+            out.push_str(&c.func);
+            out.push('(');
+            for (i, arg) in c.args.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_line(out, arg, level + 1);
+            }
+            out.push('\n');
+            indent(out, level);
+            out.push(')');
+        }
+        Expr::Aggregate(a) => {
+            out.push_str(a.op.as_str());
+            if let Some(g) = &a.grouping {
+                out.push_str(&format!(" {} ", g));
+            }
+            out.push('(');
+            if let Some(p) = &a.param {
+                write_line(out, p, level + 1);
+                out.push(',');
+            }
+            write_line(out, &a.expr, level + 1);
+            out.push('\n');
+            indent(out, level);
+            out.push(')');
+        }
+        Expr::Binary(b) => {
+            // operands are indented one level deeper than their operator
+            out.push_str(INDENT);
+            write_operand(out, &b.lhs, needs_parens(&b.lhs, b.op, false), level + 1);
+            out.push('\n');
+            indent(out, level);
+            out.push_str(b.op.as_str());
+            if b.return_bool {
+                out.push_str(" bool");
+            }
+            if let Some(m) = &b.matching {
+                out.push_str(&format!(" {}", m));
+            }
+            out.push('\n');
+            indent(out, level + 1);
+            write_operand(out, &b.rhs, needs_parens(&b.rhs, b.op, true), level + 1);
+        }
+        Expr::Negate(e) => {
+            out.push('-');
+            let parens = match **e {
+                Expr::Binary(ref b) => b.op != BinaryOp::Pow,
+                Expr::Negate(_) => true,
+                Expr::Number(n) => n < 0.0,
+                _ => false,
+            };
+            write_operand(out, e, parens, level);
+        }
+        Expr::Subquery(s) => {
+            write_operand(out, &s.expr, needs_parens_for_postfix(&s.expr), level);
+            out.push('[');
+            out.push_str(&units::format_duration(s.range));
+            out.push(':');
+            if let Some(step) = s.step {
+                out.push_str(&units::format_duration(step));
+            }
+            out.push(']');
+            // infallible when writing to a String
+            let _ = write_modifiers(out, s.offset, s.at);
+        }
+    }
+}
+
+// Writes `expr` on a new line at `level`.
+fn write_line(out: &mut String, expr: &Expr, level: usize) {
+    out.push('\n');
+    indent(out, level);
+    write_pretty(out, expr, level);
+}
+
+fn write_operand(out: &mut String, e: &Expr, parens: bool, level: usize) {
+    if parens {
+        write_pretty(out, &Expr::Paren(Box::new(e.clone())), level);
+    } else {
+        write_pretty(out, e, level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::promql::pretty::format_query;
+
+    #[test]
+    fn should_keep_short_expressions_on_one_line() {
+        assert_eq!(
+            r#"sum by (job) (rate(up{env="prod", job="api"}[5m]))"#,
+            format_query(r#"sum   by(job)(rate(up{job='api',env="prod"}[5m]))"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_split_long_expressions() {
+        let cases = [
+            (
+                r#"sum by (job, code) (rate(http_requests_total{job="api-server", env="production", code=~"5.."}[5m])) / on (job) group_left sum by (job) (rate(http_requests_total{job="api-server", env="production"}[5m])) > bool 0.05"#,
+                r#"    sum by (job, code) (rate(http_requests_total{code=~"5..", env="production", job="api-server"}[5m]))
+  / on (job) group_left
+    sum by (job) (rate(http_requests_total{env="production", job="api-server"}[5m]))
+> bool
+  0.05"#,
+            ),
+            (
+                r#"topk(5, sum without (instance, pod) (container_memory_working_set_bytes{namespace="kube-system", container!=""}))"#,
+                r#"topk(
+  5,
+  sum without (instance, pod) (
+    container_memory_working_set_bytes{container!="", namespace="kube-system"}
+  )
+)"#,
+            ),
+            (
+                r#"-(max_over_time(rate(http_requests_total{job="api-server", env="production", instance="host-1:9090"}[5m])[1h:1m]) - 1)"#,
+                r#"-(
+    max_over_time(
+      rate(http_requests_total{env="production", instance="host-1:9090", job="api-server"}[5m])[1h:1m]
+    )
+  -
+    1
+)"#,
+            ),
+        ];
+        for (query, expected) in cases.iter() {
+            let formatted = format_query(query).unwrap();
+            assert_eq!(*expected, formatted);
+            assert_eq!(formatted, format_query(&formatted).unwrap());
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::promql::ast::Expr;
+use crate::promql::builder::Builder;
+use crate::Result;
+
+/// Query text accepted by the query methods of [PromClient](crate::PromClient).
+///
+/// Raw strings are sent as-is. Expressions and [Builder]s are rendered as
+/// PromQL, and a builder that failed reports its error when the query is sent.
+#[derive(Debug)]
+pub struct Query {
+    text: Result<String>,
+}
+
+impl Query {
+    /// The PromQL text, or the error that occurred while building it.
+    pub fn into_string(self) -> Result<String> {
+        self.text
+    }
+}
+
+impl From<String> for Query {
+    fn from(text: String) -> Query {
+        Query { text: Ok(text) }
+    }
+}
+
+impl<'a> From<&'a str> for Query {
+    fn from(text: &'a str) -> Query {
+        Query {
+            text: Ok(text.to_owned()),
+        }
+    }
+}
+
+impl From<Expr> for Query {
+    fn from(expr: Expr) -> Query {
+        Query {
+            text: Ok(expr.to_string()),
+        }
+    }
+}
+
+impl<'a> From<&'a Expr> for Query {
+    fn from(expr: &'a Expr) -> Query {
+        Query {
+            text: Ok(expr.to_string()),
+        }
+    }
+}
+
+impl From<Builder> for Query {
+    fn from(builder: Builder) -> Query {
+        Query {
+            text: builder.build().map(|e| e.to_string()),
+        }
+    }
+}
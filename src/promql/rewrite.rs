@@ -0,0 +1,217 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query rewriting that enforces label matchers on every selector, like
+//! [prom-label-proxy](https://github.com/prometheus-community/prom-label-proxy).
+//!
+//! Injected matchers are combined with the user's, so a query can only ever
+//! see a subset of the series the injected matchers allow.
+
+use crate::matcher::{LabelMatcher, MatchOp};
+use crate::promql::ast::{Expr, VectorSelector};
+use crate::promql::parser;
+use crate::{Error, Result};
+
+/// What to do when a selector already has a matcher on an injected label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    /// Fail if the existing matcher would exclude the injected value.
+    /// Existing matchers the injected value satisfies are kept.
+    Reject,
+    /// Drop existing matchers on the label in favor of the injected one.
+    Replace,
+}
+
+/// Injects label matchers into every vector selector of a query.
+#[derive(Clone, Debug)]
+pub struct LabelInjector {
+    matchers: Vec<LabelMatcher>,
+    conflict: Conflict,
+}
+
+/// Rewrite `query` so that it only selects series with `name="value"`,
+/// rejecting queries that select a different value.
+pub fn inject_label(query: &str, name: &str, value: &str) -> Result<String> {
+    LabelInjector::new(LabelMatcher::equal(name, value)).rewrite_query(query)
+}
+
+impl LabelInjector {
+    /// Inject `matcher`, rejecting queries that contradict it.
+    pub fn new(matcher: LabelMatcher) -> LabelInjector {
+        LabelInjector {
+            matchers: vec![matcher],
+            conflict: Conflict::Reject,
+        }
+    }
+
+    /// Inject `matcher` as well.
+    pub fn matcher(mut self, matcher: LabelMatcher) -> LabelInjector {
+        self.matchers.push(matcher);
+        self
+    }
+
+    /// How to handle existing matchers on an injected label, [Conflict::Reject] by default.
+    pub fn on_conflict(mut self, conflict: Conflict) -> LabelInjector {
+        self.conflict = conflict;
+        self
+    }
+
+    /// Parse `query`, inject the matchers and render it back as PromQL.
+    pub fn rewrite_query(&self, query: &str) -> Result<String> {
+        let mut expr = parser::parse(query)?;
+        self.rewrite(&mut expr)?;
+        Ok(expr.to_string())
+    }
+
+    /// Inject the matchers into every selector in `expr`, including those
+    /// in range selectors and subqueries.
+    ///
+    /// `expr` is left partially rewritten if this fails.
+    pub fn rewrite(&self, expr: &mut Expr) -> Result<()> {
+        let mut result = Ok(());
+        expr.walk_mut(&mut |e| {
+            let selector = match e {
+                Expr::VectorSelector(s) => s,
+                Expr::MatrixSelector(s) => &mut s.selector,
+                _ => return,
+            };
+            if result.is_ok() {
+                result = self.inject(selector);
+            }
+        });
+        result
+    }
+
+    fn inject(&self, selector: &mut VectorSelector) -> Result<()> {
+        match self.conflict {
+            // drop the user's matchers up front so that several injected
+            // matchers on one label don't remove each other
+            Conflict::Replace => {
+                let injected = &self.matchers;
+                selector
+                    .matchers
+                    .retain(|m| injected.iter().all(|i| i.name != m.name));
+            }
+            Conflict::Reject => {
+                for injected in &self.matchers {
+                    let conflict = selector
+                        .matchers
+                        .iter()
+                        .find(|m| m.name == injected.name && contradicts(m, injected));
+                    if let Some(m) = conflict {
+                        return Err(Error::new_invalid_query_error(format!(
+                            "label matcher {} conflicts with injected matcher {}",
+                            m, injected
+                        )));
+                    }
+                }
+            }
+        }
+        for injected in &self.matchers {
+            if !selector.matchers.contains(injected) {
+                selector.matchers.push(injected.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Only an injected equality pins the label to a single value that existing
+// matchers can be checked against; anything else is combined as-is, which
+// can only narrow the result.
+fn contradicts(existing: &LabelMatcher, injected: &LabelMatcher) -> bool {
+    match injected.op {
+        MatchOp::Equal => !existing.matches(Some(&injected.value)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+    use crate::matcher::LabelMatcher;
+    use crate::promql::rewrite::{inject_label, Conflict, LabelInjector};
+
+    #[test]
+    fn should_inject_label_into_every_selector() {
+        assert_eq!(
+            r#"sum by (code) (rate(http_requests_total{code=~"5..", namespace="team-a"}[5m])) / on (code) group_left max_over_time(up{namespace="team-a"}[1h:])"#,
+            inject_label(
+                r#"sum by (code) (rate(http_requests_total{code=~"5.."}[5m])) / on (code) group_left max_over_time(up[1h:])"#,
+                "namespace",
+                "team-a"
+            )
+            .unwrap()
+        );
+
+        // matchers the tenant satisfies are kept, identical ones aren't duplicated
+        assert_eq!(
+            r#"up{namespace=~"team-.*", namespace="team-a"} or down{namespace="team-a"}"#,
+            inject_label(
+                r#"up{namespace=~"team-.*"} or down{namespace="team-a"}"#,
+                "namespace",
+                "team-a"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_contradicting_matchers() {
+        for query in [
+            r#"up{namespace="team-b"}"#,
+            r#"rate(up{namespace!="team-a"}[5m])"#,
+            r#"up or absent(down{namespace=~"team-[bc]"})"#,
+        ]
+        .iter()
+        {
+            match inject_label(query, "namespace", "team-a")
+                .unwrap_err()
+                .kind()
+            {
+                ErrorKind::InvalidQuery { message } => {
+                    assert!(message.contains("conflicts"), "{}", message)
+                }
+                k => panic!("unexpected error {:?}", k),
+            }
+        }
+    }
+
+    #[test]
+    fn should_replace_existing_matchers() {
+        let injector = LabelInjector::new(LabelMatcher::equal("namespace", "team-a"))
+            .matcher(LabelMatcher::regex_match("cluster", "eu-.*").unwrap())
+            .on_conflict(Conflict::Replace);
+        assert_eq!(
+            r#"up{job="api", namespace="team-a", cluster=~"eu-.*"}"#,
+            injector
+                .rewrite_query(r#"up{namespace="team-b", job="api", cluster="us-1"}"#)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_keep_every_injected_matcher_on_the_same_label() {
+        let injector =
+            LabelInjector::new(LabelMatcher::regex_match("namespace", "team-a-.*").unwrap())
+                .matcher(LabelMatcher::not_equal("namespace", "team-a-secret"))
+                .on_conflict(Conflict::Replace);
+        assert_eq!(
+            r#"up{job="api", namespace=~"team-a-.*", namespace!="team-a-secret"}"#,
+            injector
+                .rewrite_query(r#"up{namespace="team-b", job="api"}"#)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::str::FromStr;
+
+use base64;
+use http::header::CONTENT_TYPE;
+use http::{Method, Uri};
+use hyper::{Body, Client, Request};
+use url::Url;
+
+use crate::pushgateway::exposition::{encode_text, MetricFamily};
+use crate::pushgateway::messages::MetricsResult;
+use crate::transport::{self, HyperHttpsConnector};
+use crate::{Error, Result};
+
+const TEXT_FORMAT_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Identifies a group of pushed metrics.
+/// Rendered as `/metrics/job/<job>{/<label>/<value>}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupingKey {
+    pub job: String,
+    pub labels: Vec<(String, String)>,
+}
+
+impl GroupingKey {
+    pub fn new<S: Into<String>>(job: S) -> GroupingKey {
+        GroupingKey {
+            job: job.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn with_label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> GroupingKey {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    fn append_to(&self, u: &mut Url) {
+        // Explicitly unwrapping here because the host was validated
+        // to be a base URL when the client was built
+        let mut segments = u
+            .path_segments_mut()
+            .expect("Pushgateway URL cannot be a base");
+        segments.pop_if_empty().push("metrics");
+        for (name, value) in Some(("job", self.job.as_str()))
+            .into_iter()
+            .chain(self.labels.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+        {
+            // Values that are empty or contain '/' cannot be represented
+            // as path segments, so the Pushgateway accepts them base64-encoded
+            if value.is_empty() || value.contains('/') {
+                let encoded = if value.is_empty() {
+                    "=".to_owned()
+                } else {
+                    base64::encode_config(value, base64::URL_SAFE)
+                };
+                segments.push(&format!("{}@base64", name)).push(&encoded);
+            } else {
+                segments.push(name).push(value);
+            }
+        }
+    }
+}
+
+pub struct PushgatewayClient<T: hyper::client::connect::Connect + 'static> {
+    client: Client<T, Body>,
+    host: Url,
+}
+
+impl PushgatewayClient<HyperHttpsConnector> {
+    pub fn new_https(host: &str) -> Result<PushgatewayClient<HyperHttpsConnector>> {
+        let base = transport::parse_host(host)?;
+        // Paths are appended as segments, so the host must be able to have
+        // one. e.g. "localhost:9091" parses with the scheme "localhost"
+        if base.cannot_be_a_base() {
+            return Err(Error::new_invalid_host_error(
+                host,
+                url::ParseError::RelativeUrlWithCannotBeABaseBase,
+            ));
+        }
+        Ok(PushgatewayClient {
+            client: transport::https_client(),
+            host: base,
+        })
+    }
+}
+
+impl<T: hyper::client::connect::Connect + 'static> PushgatewayClient<T> {
+    /// Replace _all_ metrics under `key` with `families` (HTTP `PUT`).
+    pub async fn push(&mut self, key: &GroupingKey, families: &[MetricFamily]) -> Result<()> {
+        await!(self.push_with_method(Method::PUT, key, families))
+    }
+
+    /// Replace only the metrics under `key` that share a name with
+    /// one of `families` (HTTP `POST`).
+    pub async fn push_add(&mut self, key: &GroupingKey, families: &[MetricFamily]) -> Result<()> {
+        await!(self.push_with_method(Method::POST, key, families))
+    }
+
+    /// Delete all metrics under `key`.
+    pub async fn delete(&mut self, key: &GroupingKey) -> Result<()> {
+        let mut u = self.host.clone();
+        key.append_to(&mut u);
+        let u = Uri::from_str(u.as_str())?;
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let delete = Request::delete(u)
+            .body(Body::empty())
+            .expect("Failed to construct 'delete' DELETE with empty body");
+
+        await!(transport::send(&self.client, delete))?;
+        Ok(())
+    }
+
+    /// List all metric groups currently held by the Pushgateway.
+    pub async fn metrics(&mut self) -> Result<MetricsResult> {
+        let u = api_url(&self.host, &["api", "v1", "metrics"]);
+        let u = Uri::from_str(u.as_str())?;
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let get = Request::get(u)
+            .body(Body::empty())
+            .expect("Failed to construct 'metrics' GET with empty body");
+
+        await!(transport::send_json(&self.client, get))
+    }
+
+    async fn push_with_method(
+        &mut self,
+        method: Method,
+        key: &GroupingKey,
+        families: &[MetricFamily],
+    ) -> Result<()> {
+        let mut u = self.host.clone();
+        key.append_to(&mut u);
+        let u = Uri::from_str(u.as_str())?;
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let req = Request::builder()
+            .method(method)
+            .uri(u)
+            .header(CONTENT_TYPE, TEXT_FORMAT_CONTENT_TYPE)
+            .body(Body::from(encode_text(families)))
+            .expect("Failed to construct push request with text body");
+
+        await!(transport::send(&self.client, req))?;
+        Ok(())
+    }
+}
+
+/// Append `segments` to the path of `host`, keeping any route prefix
+/// the Pushgateway is served under.
+fn api_url(host: &Url, segments: &[&str]) -> Url {
+    let mut u = host.clone();
+    // Explicitly unwrapping here because the host was validated
+    // to be a base URL when the client was built
+    u.path_segments_mut()
+        .expect("Pushgateway URL cannot be a base")
+        .pop_if_empty()
+        .extend(segments);
+    u
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{api_url, GroupingKey, PushgatewayClient};
+
+    #[test]
+    fn should_render_grouping_key_path() {
+        let mut u = Url::parse("http://pushgateway:9091").unwrap();
+        GroupingKey::new("backup")
+            .with_label("instance", "db-1")
+            .with_label("path", "/var/lib")
+            .with_label("empty", "")
+            .append_to(&mut u);
+
+        assert_eq!(
+            "http://pushgateway:9091/metrics/job/backup/instance/db-1/path@base64/L3Zhci9saWI=/empty@base64/=",
+            u.as_str()
+        );
+    }
+
+    #[test]
+    fn should_keep_route_prefix_in_api_url() {
+        let u = Url::parse("http://pushgateway:9091/pushgateway").unwrap();
+        assert_eq!(
+            "http://pushgateway:9091/pushgateway/api/v1/metrics",
+            api_url(&u, &["api", "v1", "metrics"]).as_str()
+        );
+
+        let u = Url::parse("http://pushgateway:9091/").unwrap();
+        assert_eq!(
+            "http://pushgateway:9091/api/v1/metrics",
+            api_url(&u, &["api", "v1", "metrics"]).as_str()
+        );
+    }
+
+    #[test]
+    fn should_reject_host_that_cannot_be_a_base() {
+        assert!(PushgatewayClient::new_https("localhost:9091").is_err());
+    }
+}
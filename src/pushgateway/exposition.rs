@@ -0,0 +1,166 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal writer for the Prometheus [text exposition format](https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format).
+
+use std::fmt::Write;
+
+use crate::messages::format_plus_inf_value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+    Summary,
+    Untyped,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+            MetricType::Summary => "summary",
+            MetricType::Untyped => "untyped",
+        }
+    }
+}
+
+/// A named group of samples sharing `# HELP` and `# TYPE` lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: Option<String>,
+    pub metric_type: MetricType,
+    pub samples: Vec<ExpositionSample>,
+}
+
+/// A single exposition line.
+///
+/// `suffix` is appended to the family name, and is used for the
+/// `_bucket`, `_sum` and `_count` series of histograms and summaries.
+/// Timestamps are deliberately unsupported: the Pushgateway rejects them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpositionSample {
+    pub suffix: Option<String>,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl MetricFamily {
+    pub fn new<S: Into<String>>(name: S, metric_type: MetricType) -> MetricFamily {
+        MetricFamily {
+            name: name.into(),
+            help: None,
+            metric_type,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> MetricFamily {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_sample(mut self, labels: &[(&str, &str)], value: f64) -> MetricFamily {
+        self.samples.push(ExpositionSample {
+            suffix: None,
+            labels: labels
+                .iter()
+                .map(|(n, v)| ((*n).to_owned(), (*v).to_owned()))
+                .collect(),
+            value,
+        });
+        self
+    }
+}
+
+/// Render `families` in the text exposition format.
+pub fn encode_text(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        if let Some(ref help) = family.help {
+            out.push_str(&format!(
+                "# HELP {} {}\n",
+                family.name,
+                escape_help(help)
+            ));
+        }
+        out.push_str(&format!(
+            "# TYPE {} {}\n",
+            family.name,
+            family.metric_type.as_str()
+        ));
+        for sample in &family.samples {
+            out.push_str(&family.name);
+            if let Some(ref suffix) = sample.suffix {
+                out.push_str(suffix);
+            }
+            if !sample.labels.is_empty() {
+                out.push('{');
+                for (i, (name, value)) in sample.labels.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    // writing to a String cannot fail
+                    let _ = write!(out, "{}=\"{}\"", name, escape_label_value(value));
+                }
+                out.push('}');
+            }
+            out.push(' ');
+            out.push_str(&format_plus_inf_value(sample.value));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn escape_help(s: &str) -> String {
+    s.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+fn escape_label_value(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_text, MetricFamily, MetricType};
+
+    #[test]
+    fn should_encode_families_in_text_format() {
+        let families = vec![
+            MetricFamily::new("batch_last_success_seconds", MetricType::Gauge)
+                .with_help("Last time the batch job succeeded.\nIn seconds.")
+                .with_sample(&[("stage", "load")], 1554076800.5),
+            MetricFamily::new("batch_records", MetricType::Untyped)
+                .with_sample(&[], 42.0)
+                .with_sample(&[("path", r#"C:\tmp "x""#)], std::f64::INFINITY),
+        ];
+
+        assert_eq!(
+            "# HELP batch_last_success_seconds Last time the batch job succeeded.\\nIn seconds.\n\
+             # TYPE batch_last_success_seconds gauge\n\
+             batch_last_success_seconds{stage=\"load\"} 1554076800.5\n\
+             # TYPE batch_records untyped\n\
+             batch_records 42\n\
+             batch_records{path=\"C:\\\\tmp \\\"x\\\"\"} +Inf\n",
+            encode_text(&families)
+        );
+    }
+}
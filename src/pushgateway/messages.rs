@@ -0,0 +1,136 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Response types for the Pushgateway `/api/v1` API.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::messages::ApiErr;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "status")]
+pub enum MetricsResult {
+    #[serde(rename = "success")]
+    MetricsOk(MetricsOk),
+    #[serde(rename = "error")]
+    ApiErr(ApiErr),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MetricsOk {
+    #[serde(default)]
+    pub data: Vec<MetricGroup>,
+}
+
+/// All metrics pushed under a single grouping key.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MetricGroup {
+    /// Grouping key labels, including `job`.
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub last_push_successful: Option<bool>,
+    /// Metric families keyed by name. This includes the
+    /// `push_time_seconds` and `push_failure_time_seconds`
+    /// families maintained by the Pushgateway itself.
+    #[serde(flatten)]
+    pub families: HashMap<String, PushedFamily>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PushedFamily {
+    pub time_stamp: String,
+    #[serde(rename = "type")]
+    pub metric_type: String,
+    #[serde(default)]
+    pub help: String,
+    pub metrics: Vec<PushedMetric>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PushedMetric {
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Set for counters, gauges and untyped metrics.
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Remaining fields, e.g. `buckets`, `quantiles`, `count` and `sum`
+    /// for histograms and summaries.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::result::Result as StdResult;
+
+    use crate::pushgateway::messages::{MetricsResult, PushedMetric};
+
+    #[test]
+    fn should_deserialize_json_pushgateway_metrics() -> StdResult<(), std::io::Error> {
+        let j = r#"
+        {
+            "status": "success",
+            "data": [
+                {
+                    "labels": { "job": "batch", "instance": "a" },
+                    "last_push_successful": true,
+                    "batch_records": {
+                        "time_stamp": "2019-04-01T10:00:00.000000000Z",
+                        "type": "UNTYPED",
+                        "help": "",
+                        "metrics": [
+                            { "labels": { "job": "batch", "instance": "a" }, "value": "42" }
+                        ]
+                    },
+                    "push_time_seconds": {
+                        "time_stamp": "2019-04-01T10:00:00.000000000Z",
+                        "type": "GAUGE",
+                        "help": "Last Unix time when changing this group in the Pushgateway succeeded.",
+                        "metrics": [
+                            { "labels": { "job": "batch", "instance": "a" }, "value": "1.5541128e+09" }
+                        ]
+                    }
+                }
+            ]
+        }
+        "#;
+
+        let res = serde_json::from_str::<MetricsResult>(j)?;
+        let group = match res {
+            MetricsResult::MetricsOk(ok) => ok.data.into_iter().next().unwrap(),
+            MetricsResult::ApiErr(e) => panic!("unexpected error {}", e),
+        };
+
+        let mut labels: HashMap<String, String> = HashMap::new();
+        labels.insert("job".to_owned(), "batch".to_owned());
+        labels.insert("instance".to_owned(), "a".to_owned());
+
+        assert_eq!(labels, group.labels);
+        assert_eq!(Some(true), group.last_push_successful);
+        assert_eq!(2, group.families.len());
+        assert_eq!(
+            vec![PushedMetric {
+                labels,
+                value: Some("42".to_owned()),
+                other: HashMap::new(),
+            }],
+            group.families["batch_records"].metrics
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Async interface to the [Pushgateway](https://github.com/prometheus/pushgateway).
+//! Metrics are pushed in the text exposition format under a [GroupingKey].
+
+pub use self::client::{GroupingKey, PushgatewayClient};
+pub use self::exposition::{encode_text, ExpositionSample, MetricFamily, MetricType};
+
+mod client;
+mod exposition;
+pub mod messages;
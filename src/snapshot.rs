@@ -0,0 +1,230 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TSDB snapshot helpers.
+//!
+//! Prometheus writes snapshots to `<data dir>/snapshots/<name>` on the
+//! server's own filesystem. [SnapshotInfo] resolves that directory, and
+//! with the `archive` feature [archive] packs it into a `.tar.gz`, for
+//! tools that run on the same host as Prometheus (or share its volume).
+//! Archiving blocks, so async callers should use
+//! [PromClient::snapshot_to_archive], which runs it on its own thread.
+
+#[cfg(feature = "archive")]
+use std::fs::File;
+#[cfg(feature = "archive")]
+use std::io;
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "archive")]
+use std::thread;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+#[cfg(feature = "archive")]
+use flate2::{write::GzEncoder, Compression};
+#[cfg(feature = "archive")]
+use futures::channel::oneshot;
+
+use crate::messages::{ApiResult, Data, Snapshot};
+use crate::{Error, PromClient, Result};
+
+/// Directory under the data directory where Prometheus writes snapshots.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// A snapshot resolved against the Prometheus data directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    /// Snapshot name, e.g. `20171210T211224Z-2be650b6d019eb54`.
+    pub name: String,
+    /// Directory holding the snapshot.
+    pub path: PathBuf,
+}
+
+impl SnapshotInfo {
+    /// Fails if the server-sent name isn't a single plain path component,
+    /// so a name like `../../etc` can't resolve outside the snapshots dir.
+    pub fn new<P: AsRef<Path>>(snapshot: &Snapshot, data_dir: P) -> Result<SnapshotInfo> {
+        let mut components = Path::new(&snapshot.name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(SnapshotInfo {
+                name: snapshot.name.clone(),
+                path: data_dir.as_ref().join(SNAPSHOTS_DIR).join(&snapshot.name),
+            }),
+            _ => Err(Error::new_unexpected_response_error(format!(
+                "invalid snapshot name: {:?}",
+                snapshot.name
+            ))),
+        }
+    }
+
+    /// Time the snapshot was taken, parsed from its name.
+    /// `None` if the name doesn't follow the Prometheus naming scheme.
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        let timestamp = self.name.split('-').next()?;
+        NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%SZ")
+            .ok()
+            .map(|t| Utc.from_utc_datetime(&t))
+    }
+}
+
+impl Snapshot {
+    /// Resolve this snapshot against the Prometheus data directory.
+    pub fn info<P: AsRef<Path>>(&self, data_dir: P) -> Result<SnapshotInfo> {
+        SnapshotInfo::new(self, data_dir)
+    }
+}
+
+/// Write the snapshot directory to `archive_path` as a gzipped tarball.
+///
+/// Entries are stored under the snapshot name, so the archive unpacks
+/// to a single `<name>/` directory. This does blocking file I/O.
+#[cfg(feature = "archive")]
+pub fn archive<P: AsRef<Path>>(info: &SnapshotInfo, archive_path: P) -> Result<()> {
+    let file = File::create(archive_path)?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    tar.append_dir_all(&info.name, &info.path)?;
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
+    /// Take a snapshot and resolve it against `data_dir`, the Prometheus
+    /// data directory (`--storage.tsdb.path`) as seen from this process.
+    ///
+    /// See [PromClient::snapshot_to_archive] to also archive it.
+    pub async fn snapshot_info<P: AsRef<Path>>(
+        &mut self,
+        skip_head: bool,
+        data_dir: P,
+    ) -> Result<SnapshotInfo> {
+        match await!(self.snapshot(skip_head))? {
+            ApiResult::ApiOk(ok) => match ok.data {
+                Some(Data::Snapshot(snapshot)) => snapshot.info(data_dir),
+                _ => Err(Error::new_unexpected_response_error(
+                    "snapshot response has no snapshot name",
+                )),
+            },
+            ApiResult::ApiErr(err) => Err(Error::new_api_error(err)),
+        }
+    }
+
+    /// Take a snapshot and [archive] it to `archive_path`.
+    ///
+    /// The archiving runs on a dedicated thread so its blocking file I/O
+    /// doesn't stall the executor.
+    #[cfg(feature = "archive")]
+    pub async fn snapshot_to_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        skip_head: bool,
+        data_dir: P,
+        archive_path: Q,
+    ) -> Result<SnapshotInfo> {
+        let info = await!(self.snapshot_info(skip_head, data_dir))?;
+
+        let (tx, rx) = oneshot::channel();
+        let archive_info = info.clone();
+        let archive_path = archive_path.as_ref().to_path_buf();
+        thread::spawn(move || {
+            // the receiver only goes away if the caller dropped the future
+            let _ = tx.send(archive(&archive_info, archive_path));
+        });
+
+        match await!(rx) {
+            Ok(result) => result.map(|_| info),
+            Err(_) => Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                "snapshot archiving thread panicked",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::{DateTime, Utc};
+
+    use crate::messages::Snapshot;
+
+    #[test]
+    fn should_resolve_snapshot_path_and_time() {
+        let snapshot = Snapshot {
+            name: "20171210T211224Z-2be650b6d019eb54".to_owned(),
+        };
+
+        let info = snapshot.info("/prometheus").unwrap();
+        assert_eq!(
+            PathBuf::from("/prometheus/snapshots/20171210T211224Z-2be650b6d019eb54"),
+            info.path
+        );
+        assert_eq!(
+            Some("2017-12-10T21:12:24Z".parse::<DateTime<Utc>>().unwrap()),
+            info.created_at()
+        );
+    }
+
+    #[test]
+    fn should_reject_snapshot_names_outside_the_snapshots_dir() {
+        for name in &["../../etc", "a/b", "/etc", "..", ""] {
+            let snapshot = Snapshot {
+                name: (*name).to_owned(),
+            };
+            assert!(snapshot.info("/prometheus").is_err(), "{:?}", name);
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn should_archive_snapshot_directory() {
+        use std::fs::{self, File};
+        use std::io::Read;
+
+        use flate2::read::GzDecoder;
+
+        use crate::snapshot::archive;
+
+        let dir = std::env::temp_dir().join(format!("prometheus-query-{}", std::process::id()));
+        let snapshot = Snapshot {
+            name: "20171210T211224Z-2be650b6d019eb54".to_owned(),
+        };
+        let info = snapshot.info(&dir).unwrap();
+        fs::create_dir_all(info.path.join("01BKGV7JBM69T2G1BGBGM6KB12")).unwrap();
+        fs::write(
+            info.path.join("01BKGV7JBM69T2G1BGBGM6KB12").join("meta.json"),
+            "{}",
+        )
+        .unwrap();
+
+        let tarball = dir.join("snapshot.tar.gz");
+        archive(&info, &tarball).unwrap();
+
+        let mut tar = tar::Archive::new(GzDecoder::new(File::open(&tarball).unwrap()));
+        let mut found = false;
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap().ends_with("meta.json") {
+                assert!(entry
+                    .path()
+                    .unwrap()
+                    .starts_with("20171210T211224Z-2be650b6d019eb54"));
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                assert_eq!("{}", contents);
+                found = true;
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(found);
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Range queries split into shorter, concurrent queries.
+//!
+//! Long ranges can time out on the server, and Prometheus rejects range
+//! queries that would return more than [MAX_POINTS_PER_SERIES] points per
+//! series. [range_query_split](PromClient::range_query_split) runs the
+//! query over step-aligned chunks that each stay within the limit and
+//! stitches the series back together.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::client::Step;
+use crate::messages::{self, ApiOk, ApiResult, Data, Expression, Range};
+use crate::promql::Query;
+use crate::units::PromDuration;
+use crate::{Error, PromClient, Result};
+
+/// Most points Prometheus returns per series in one range query.
+pub const MAX_POINTS_PER_SERIES: u64 = 11_000;
+
+/// Default length of each chunk.
+pub const DEFAULT_CHUNK: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default number of chunks queried at once.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How [range_query_split](PromClient::range_query_split) divides a query.
+#[derive(Clone, Debug)]
+pub struct SplitOptions {
+    chunk: Duration,
+    concurrency: usize,
+    max_points: Option<u64>,
+}
+
+impl Default for SplitOptions {
+    fn default() -> SplitOptions {
+        SplitOptions::new()
+    }
+}
+
+impl SplitOptions {
+    pub fn new() -> SplitOptions {
+        SplitOptions {
+            chunk: DEFAULT_CHUNK,
+            concurrency: DEFAULT_CONCURRENCY,
+            max_points: None,
+        }
+    }
+
+    /// Length of each chunk, rounded down to a whole number of steps.
+    pub fn chunk(mut self, chunk: Duration) -> SplitOptions {
+        self.chunk = chunk;
+        self
+    }
+
+    /// Number of chunks queried at once.
+    pub fn concurrency(mut self, concurrency: usize) -> SplitOptions {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Most points per series over the whole range, unlimited by default.
+    /// The step is coarsened to stay within this. Chunks never exceed
+    /// [MAX_POINTS_PER_SERIES] either way.
+    pub fn max_points(mut self, max_points: u64) -> SplitOptions {
+        self.max_points = Some(max_points.max(1));
+        self
+    }
+}
+
+/// Step, in milliseconds, no finer than `step` that evaluates `[start, end]`
+/// at no more than `max_points` points. A step that has to be coarsened is
+/// rounded up to whole seconds.
+pub fn guard_step(start: i64, end: i64, step: i64, max_points: u64) -> i64 {
+    let range = end - start;
+    let min_step = if range <= 0 || max_points <= 1 {
+        range
+    } else {
+        (range + max_points as i64 - 2) / (max_points as i64 - 1)
+    };
+    if step >= min_step {
+        step
+    } else {
+        (min_step + 999) / 1000 * 1000
+    }
+}
+
+/// `(start, end)` of each chunk, in milliseconds. Chunks evaluate exactly
+/// the timestamps `start + n * step` the unsplit query would.
+fn chunks(start: i64, end: i64, step: i64, chunk: i64) -> Vec<(i64, i64)> {
+    let points_per_chunk = (chunk / step).max(1).min(MAX_POINTS_PER_SERIES as i64);
+    let mut chunks = Vec::new();
+    let mut chunk_start = start;
+    while chunk_start <= end {
+        let chunk_end = chunk_start
+            .saturating_add((points_per_chunk - 1).saturating_mul(step))
+            .min(end);
+        chunks.push((chunk_start, chunk_end));
+        chunk_start = match chunk_end.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    chunks
+}
+
+/// Join the series of consecutive chunks by their labels, keeping the order
+/// in which series first appear.
+fn stitch(chunks: Vec<Vec<Range>>) -> Vec<Range> {
+    let mut series: Vec<Range> = Vec::new();
+    let mut index: HashMap<Vec<(String, String)>, usize> = HashMap::new();
+    for chunk in chunks {
+        for range in chunk {
+            let mut key: Vec<(String, String)> = range
+                .metric
+                .labels
+                .iter()
+                .map(|(n, v)| (n.clone(), v.clone()))
+                .collect();
+            key.sort();
+            match index.get(&key) {
+                Some(&i) => {
+                    let stitched = &mut series[i];
+                    let last = stitched.samples.last().map(|s| s.timestamp_millis());
+                    stitched.samples.extend(
+                        range
+                            .samples
+                            .into_iter()
+                            .filter(|s| last.map_or(true, |l| s.timestamp_millis() > l)),
+                    );
+                }
+                None => {
+                    index.insert(key, series.len());
+                    series.push(range);
+                }
+            }
+        }
+    }
+    series
+}
+
+fn step_millis(step: Step) -> i64 {
+    match step {
+        Step::Seconds(secs) => (secs * 1000.0).round() as i64,
+        Step::Duration(d) => millis_i64(PromDuration::from(d)),
+    }
+}
+
+/// Milliseconds in `d`, saturating at `i64::MAX`.
+fn millis_i64(d: PromDuration) -> i64 {
+    d.as_millis().min(std::i64::MAX as u64) as i64
+}
+
+impl<T: hyper::client::connect::Connect + Clone + 'static> PromClient<T> {
+    /// Range query run as step-aligned chunks of at most `options.chunk`,
+    /// `options.concurrency` at a time, with the resulting series stitched
+    /// back together by their labels.
+    ///
+    /// If `options.max_points` is set and the range would have more points
+    /// per series at `step`, a coarser step is used and reported as a
+    /// warning. Warnings from all chunks are combined. If any chunk fails,
+    /// the first error response is returned.
+    pub async fn range_query_split<Q: Into<Query>, S: Into<Step>>(
+        &self,
+        query: Q,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: S,
+        options: SplitOptions,
+    ) -> Result<ApiResult> {
+        let query = query.into().into_string()?;
+        let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+        let step = step_millis(step.into());
+        if step <= 0 {
+            return Err(Error::new_invalid_query_error(
+                "zero or negative query resolution step",
+            ));
+        }
+        if end < start {
+            return Err(Error::new_invalid_query_error(
+                "end timestamp must not be before start time",
+            ));
+        }
+
+        let mut warnings: Vec<String> = Vec::new();
+        let step = match options.max_points {
+            Some(max_points) => {
+                let guarded = guard_step(start, end, step, max_points);
+                if guarded != step {
+                    warnings.push(format!(
+                        "step coarsened from {} to {} to stay within {} points per series",
+                        PromDuration::from_millis(step as u64),
+                        PromDuration::from_millis(guarded as u64),
+                        max_points
+                    ));
+                }
+                guarded
+            }
+            None => step,
+        };
+        let chunk = millis_i64(PromDuration::from(options.chunk));
+        let requests = chunks(start, end, step, chunk)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (chunk_start, chunk_end))| {
+                let mut client = self.clone();
+                let query = query.clone();
+                async move {
+                    let result = await!(client.range_query(
+                        query,
+                        messages::timestamp_from_millis(chunk_start),
+                        messages::timestamp_from_millis(chunk_end),
+                        Duration::from_millis(step as u64),
+                    ));
+                    result.map(|r| (i, r))
+                }
+            });
+        let mut results: Vec<(usize, ApiResult)> = await!(stream::iter(requests)
+            .buffer_unordered(options.concurrency)
+            .try_collect())?;
+        results.sort_by_key(|(i, _)| *i);
+
+        let mut ranges = Vec::with_capacity(results.len());
+        for (_, result) in results {
+            let ok = match result {
+                ApiResult::ApiOk(ok) => ok,
+                err => return Ok(err),
+            };
+            for w in ok.warnings {
+                if !warnings.contains(&w) {
+                    warnings.push(w);
+                }
+            }
+            match ok.data {
+                Some(Data::Expression(Expression::Range(r))) => ranges.push(r),
+                _ => {
+                    return Err(Error::new_unexpected_response_error(
+                        "range query response has no matrix",
+                    ));
+                }
+            }
+        }
+
+        Ok(ApiResult::ApiOk(ApiOk {
+            data: Some(Data::Expression(Expression::Range(stitch(ranges)))),
+            warnings,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::range;
+    use std::time::Duration;
+
+    use crate::client::Step;
+    use crate::split::{chunks, guard_step, step_millis, stitch, MAX_POINTS_PER_SERIES};
+
+    #[test]
+    fn should_split_into_step_aligned_chunks() {
+        // 0, 15, 30 ... 120 in chunks of at most 4 points
+        assert_eq!(
+            vec![(0, 45_000), (60_000, 105_000), (120_000, 120_000)],
+            chunks(0, 120_000, 15_000, 60_000)
+        );
+        // chunks shorter than a step still hold one point
+        assert_eq!(vec![(0, 0), (60_000, 60_000)], chunks(0, 60_000, 60_000, 1_000));
+        // and never more than the server limit
+        let c = chunks(0, 30 * 86_400_000, 1_000, 30 * 86_400_000);
+        assert_eq!(
+            (MAX_POINTS_PER_SERIES as i64 - 1) * 1_000,
+            c[0].1 - c[0].0
+        );
+    }
+
+    #[test]
+    fn should_not_overflow_on_huge_steps() {
+        let step = step_millis(Step::Duration(Duration::from_secs(std::u64::MAX)));
+        assert_eq!(std::i64::MAX, step);
+        assert_eq!(vec![(0, 0)], chunks(0, 120_000, step, step));
+    }
+
+    #[test]
+    fn should_guard_step() {
+        // 30 days at 15s is 172,801 points
+        let month = 30 * 86_400_000;
+        let step = guard_step(0, month, 15_000, MAX_POINTS_PER_SERIES);
+        assert_eq!(236_000, step);
+        let points = month / step + 1;
+        assert!(points <= MAX_POINTS_PER_SERIES as i64, "{}", points);
+        // steps that are already coarse enough are kept
+        assert_eq!(3_600_000, guard_step(0, month, 3_600_000, MAX_POINTS_PER_SERIES));
+    }
+
+    #[test]
+    fn should_stitch_series_by_labels() {
+        let stitched = stitch(vec![
+            vec![
+                range(&[("instance", "a")], &[(0.0, 1.0), (15.0, 1.0)]),
+                range(&[("instance", "b")], &[(15.0, 1.0)]),
+            ],
+            vec![
+                range(&[("instance", "c")], &[(30.0, 1.0)]),
+                range(
+                    &[("instance", "a")],
+                    &[(15.0, 1.0), (30.0, 1.0), (45.0, 1.0)],
+                ),
+            ],
+        ]);
+        let epochs: Vec<(String, Vec<f64>)> = stitched
+            .iter()
+            .map(|r| {
+                (
+                    r.metric.labels["instance"].clone(),
+                    r.samples.iter().map(|s| s.epoch).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("a".to_owned(), vec![0.0, 15.0, 30.0, 45.0]),
+                ("b".to_owned(), vec![15.0]),
+                ("c".to_owned(), vec![30.0]),
+            ],
+            epochs
+        );
+    }
+}
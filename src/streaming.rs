@@ -0,0 +1,371 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Incremental decoding of response bodies.
+//!
+//! Response bodies are fed to a [ChunkDecoder] chunk-by-chunk as they
+//! arrive, so only the bytes of the item currently being decoded are
+//! held in memory.
+
+use std::pin::Pin;
+
+use futures::compat::Stream01CompatExt;
+use futures::Stream;
+use futures_stable::{stream as stream01, Stream as Stream01};
+#[cfg(feature = "victoriametrics")]
+use hyper::Chunk;
+use hyper::Body;
+use serde::de::{self, Unexpected};
+use serde_json;
+
+use crate::messages::{ApiErr, Range};
+use crate::{Error, Result};
+
+/// Stream of series decoded from a response body as it arrives.
+pub type RangeStream = Pin<Box<dyn Stream<Item = Result<Range>> + Send>>;
+
+/// Stream of raw response body chunks.
+#[cfg(feature = "victoriametrics")]
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<Chunk>> + Send>>;
+
+/// Stateful decoder that turns body chunks into zero or more items.
+pub(crate) trait ChunkDecoder {
+    type Item;
+
+    /// Decode all items completed by `chunk`, buffering any trailing partial item.
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<Self::Item>>;
+
+    /// Called once the body ends. Decodes any buffered partial item,
+    /// or fails if the body ended in the middle of one.
+    fn finish(&mut self) -> Result<Vec<Self::Item>>;
+}
+
+/// Drive `decoder` over `body`, yielding items as soon as they are complete.
+pub(crate) fn decode_body<D>(
+    body: Body,
+    mut decoder: D,
+) -> Pin<Box<dyn Stream<Item = Result<D::Item>> + Send>>
+where
+    D: ChunkDecoder + Send + 'static,
+    D::Item: Send + 'static,
+{
+    let items = body
+        .map(Some)
+        .chain(stream01::once(Ok(None)))
+        .map_err(Error::from)
+        .and_then(move |chunk| match chunk {
+            Some(chunk) => decoder.decode(&chunk),
+            None => decoder.finish(),
+        })
+        .map(stream01::iter_ok)
+        .flatten();
+
+    Box::pin(items.compat())
+}
+
+/// Pass body chunks through untouched.
+#[cfg(feature = "victoriametrics")]
+pub(crate) fn chunks(body: Body) -> ChunkStream {
+    Box::pin(body.map_err(Error::from).compat())
+}
+
+/// Values of the API response envelope that [MatrixDecoder] extracts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+    Series,
+    Status,
+    ResultType,
+    ErrorType,
+    ErrorMessage,
+}
+
+#[derive(Debug)]
+struct Container {
+    is_object: bool,
+    expecting_key: bool,
+    // most recent key seen in this object
+    key: Vec<u8>,
+}
+
+/// Decodes the `data.result` array of a `matrix` API response one series at a time.
+///
+/// This is a minimal JSON scanner: it only tracks enough structure to find
+/// the envelope fields it needs and the byte extent of each series. Each
+/// series is then handed to `serde_json` once all of its bytes have arrived.
+#[derive(Debug, Default)]
+pub(crate) struct MatrixDecoder {
+    buf: Vec<u8>,
+    pos: usize,
+    stack: Vec<Container>,
+    in_string: bool,
+    in_key: bool,
+    escaped: bool,
+    in_scalar: bool,
+    capture: Option<(Target, usize, usize)>, // (target, start offset, stack depth)
+    status: Option<String>,
+    result_type: Option<String>,
+    error_type: Option<String>,
+    error_message: Option<String>,
+}
+
+impl MatrixDecoder {
+    fn target_at_value_start(&self) -> Option<Target> {
+        let key = |i: usize| -> &[u8] { &self.stack[i].key };
+        match self.stack.len() {
+            1 if self.stack[0].is_object => match key(0) {
+                b"status" => Some(Target::Status),
+                b"errorType" => Some(Target::ErrorType),
+                b"error" => Some(Target::ErrorMessage),
+                _ => None,
+            },
+            2 if key(0) == b"data" && self.stack[1].is_object && key(1) == b"resultType" => {
+                Some(Target::ResultType)
+            }
+            3 if key(0) == b"data"
+                && self.stack[1].is_object
+                && key(1) == b"result"
+                && !self.stack[2].is_object =>
+            {
+                Some(Target::Series)
+            }
+            _ => None,
+        }
+    }
+
+    fn value_start(&mut self, start: usize) {
+        if self.capture.is_none() {
+            if let Some(target) = self.target_at_value_start() {
+                self.capture = Some((target, start, self.stack.len()));
+            }
+        }
+    }
+
+    fn value_end(&mut self, end: usize, series: &mut Vec<Range>) -> Result<()> {
+        let (target, start) = match self.capture {
+            Some((target, start, depth)) if depth == self.stack.len() => (target, start),
+            _ => return Ok(()),
+        };
+        self.capture = None;
+
+        let bytes = &self.buf[start..end];
+        match target {
+            Target::Series => {
+                if let Some(ref result_type) = self.result_type {
+                    if result_type != "matrix" {
+                        return Err(From::from(<serde_json::Error as de::Error>::invalid_value(
+                            Unexpected::Str(result_type),
+                            &"matrix",
+                        )));
+                    }
+                }
+                series.push(serde_json::from_slice::<Range>(bytes)?);
+            }
+            Target::Status => self.status = Some(serde_json::from_slice(bytes)?),
+            Target::ResultType => self.result_type = Some(serde_json::from_slice(bytes)?),
+            Target::ErrorType => self.error_type = Some(serde_json::from_slice(bytes)?),
+            Target::ErrorMessage => self.error_message = Some(serde_json::from_slice(bytes)?),
+        }
+
+        Ok(())
+    }
+}
+
+impl ChunkDecoder for MatrixDecoder {
+    type Item = Range;
+
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<Range>> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut series = Vec::new();
+        while self.pos < self.buf.len() {
+            let b = self.buf[self.pos];
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if b == b'\\' {
+                    self.escaped = true;
+                } else if b == b'"' {
+                    self.in_string = false;
+                    if !self.in_key {
+                        self.value_end(self.pos + 1, &mut series)?;
+                    }
+                    self.pos += 1;
+                    continue;
+                }
+                if self.in_key {
+                    if let Some(top) = self.stack.last_mut() {
+                        top.key.push(b);
+                    }
+                }
+                self.pos += 1;
+                continue;
+            }
+
+            if self.in_scalar {
+                match b {
+                    b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n' => {
+                        // the delimiter isn't part of the scalar, so re-scan it
+                        self.in_scalar = false;
+                        self.value_end(self.pos, &mut series)?;
+                    }
+                    _ => self.pos += 1,
+                }
+                continue;
+            }
+
+            match b {
+                b' ' | b'\t' | b'\r' | b'\n' => {}
+                b'"' => {
+                    self.in_string = true;
+                    self.in_key = match self.stack.last_mut() {
+                        Some(top) if top.is_object && top.expecting_key => {
+                            top.key.clear();
+                            true
+                        }
+                        _ => false,
+                    };
+                    if !self.in_key {
+                        self.value_start(self.pos);
+                    }
+                }
+                b':' => {
+                    if let Some(top) = self.stack.last_mut() {
+                        top.expecting_key = false;
+                    }
+                }
+                b',' => {
+                    if let Some(top) = self.stack.last_mut() {
+                        top.expecting_key = top.is_object;
+                    }
+                }
+                b'{' | b'[' => {
+                    self.value_start(self.pos);
+                    self.stack.push(Container {
+                        is_object: b == b'{',
+                        expecting_key: b == b'{',
+                        key: Vec::new(),
+                    });
+                }
+                b'}' | b']' => {
+                    self.stack.pop();
+                    self.value_end(self.pos + 1, &mut series)?;
+                }
+                _ => {
+                    self.value_start(self.pos);
+                    self.in_scalar = true;
+                }
+            }
+            self.pos += 1;
+        }
+
+        // discard everything that isn't part of a partially-received capture
+        let keep_from = self.capture.map_or(self.pos, |(_, start, _)| start);
+        self.buf.drain(..keep_from);
+        self.pos -= keep_from;
+        if let Some((_, ref mut start, _)) = self.capture {
+            *start = 0;
+        }
+
+        Ok(series)
+    }
+
+    fn finish(&mut self) -> Result<Vec<Range>> {
+        if !self.stack.is_empty() || self.in_string || self.capture.is_some() {
+            return Err(From::from(<serde_json::Error as de::Error>::custom(
+                "response body ended before the end of the JSON document",
+            )));
+        }
+
+        match self.status.as_ref().map(String::as_str) {
+            Some("success") => Ok(Vec::new()),
+            Some("error") => Err(Error::new_api_error(ApiErr {
+                error_type: self.error_type.take().unwrap_or_default(),
+                error_message: self.error_message.take().unwrap_or_default(),
+                data: None,
+                warnings: Vec::new(),
+            })),
+            _ => Err(From::from(<serde_json::Error as de::Error>::missing_field(
+                "status",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+    use crate::streaming::{ChunkDecoder, MatrixDecoder};
+
+    const MATRIX: &str = r#"
+    {
+        "status" : "success",
+        "data" : {
+            "resultType" : "matrix",
+            "result" : [
+                {
+                    "metric" : { "__name__" : "up", "job" : "prometheus", "instance" : "localhost:9090" },
+                    "values" : [ [ 1435781430.781, "1" ], [ 1435781445.781, "1" ] ]
+                },
+                {
+                    "metric" : { "__name__" : "up", "job" : "n\"o]d{e", "instance" : "localhost:9091" },
+                    "values" : [ [ 1435781430.781, "0" ] ]
+                }
+            ]
+        }
+    }
+    "#;
+
+    #[test]
+    fn should_decode_matrix_one_byte_at_a_time() {
+        let mut decoder = MatrixDecoder::default();
+        let mut series = Vec::new();
+        for b in MATRIX.as_bytes() {
+            series.extend(decoder.decode(&[*b]).unwrap());
+            // only the series currently being received may be buffered
+            assert!(decoder.buf.len() < MATRIX.len() / 2);
+        }
+        series.extend(decoder.finish().unwrap());
+
+        assert_eq!(2, series.len());
+        assert_eq!(2, series[0].samples.len());
+        assert_eq!("n\"o]d{e", series[1].metric.labels["job"]);
+        assert_eq!(0.0, series[1].samples[0].value);
+    }
+
+    #[test]
+    fn should_reject_non_matrix_result() {
+        let j = r#"{"status":"success","data":{"resultType":"vector","result":[{"metric":{},"value":[1,"1"]}]}}"#;
+        let mut decoder = MatrixDecoder::default();
+        assert!(decoder.decode(j.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn should_return_api_error() {
+        let j = r#"{"status":"error","errorType":"bad_data","error":"invalid parameter 'query'"}"#;
+        let mut decoder = MatrixDecoder::default();
+        assert!(decoder.decode(j.as_bytes()).unwrap().is_empty());
+        match decoder.finish().unwrap_err().kind() {
+            ErrorKind::Api { err } => assert_eq!("bad_data", err.error_type),
+            k => panic!("unexpected error {:?}", k),
+        }
+    }
+
+    #[test]
+    fn should_fail_on_truncated_body() {
+        let mut decoder = MatrixDecoder::default();
+        assert!(decoder.decode(&MATRIX.as_bytes()[..200]).is_ok());
+        assert!(decoder.finish().is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP plumbing shared by the Prometheus, Alertmanager and other clients.
+
+use std::str::FromStr;
+
+use futures::compat::Future01CompatExt;
+use futures_stable::Stream;
+use hyper::client::HttpConnector;
+use hyper::{Body, Chunk, Client, Request, Response};
+use hyper_tls::HttpsConnector;
+use serde::de::DeserializeOwned;
+use serde_json;
+use url::Url;
+
+use crate::{Error, Result};
+
+pub type HyperHttpsConnector = HttpsConnector<HttpConnector>;
+
+/// Build a keep-alive HTTPS-capable connection pool.
+pub(crate) fn https_client() -> Client<HyperHttpsConnector, Body> {
+    // Explicitly unwrapping here because this library is unusable if you can't build an HTTPS connection pool
+    let https = HttpsConnector::new(4).expect("Cannot build HTTPS connection pool");
+    Client::builder().keep_alive(true).build(https)
+}
+
+/// Parse a user-supplied host string into a `Url`.
+pub(crate) fn parse_host(host: &str) -> Result<Url> {
+    Url::from_str(host).map_err(|e| Error::new_invalid_host_error(host, e))
+}
+
+/// Send `req` and return the full response body if the server responded
+/// with a 2xx status. Any other status is returned as an
+/// [ErrorKind::UnexpectedStatus](crate::error::ErrorKind::UnexpectedStatus).
+pub(crate) async fn send<T: hyper::client::connect::Connect + 'static>(
+    client: &Client<T, Body>,
+    req: Request<Body>,
+) -> Result<Chunk> {
+    let resp = await!(send_streaming(client, req))?;
+    let body = await!(resp.into_body().concat2().compat())?;
+    Ok(body)
+}
+
+/// Send `req` and return the response with its body unread if the server
+/// responded with a 2xx status. Any other status is returned as an
+/// [ErrorKind::UnexpectedStatus](crate::error::ErrorKind::UnexpectedStatus).
+pub(crate) async fn send_streaming<T: hyper::client::connect::Connect + 'static>(
+    client: &Client<T, Body>,
+    req: Request<Body>,
+) -> Result<Response<Body>> {
+    let resp = await!(client.request(req).compat())?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = await!(resp.into_body().concat2().compat())?;
+    Err(Error::new_unexpected_status_error(
+        status,
+        String::from_utf8_lossy(&body),
+    ))
+}
+
+/// Send `req` and deserialize a successful JSON response body into `R`.
+pub(crate) async fn send_json<T, R>(client: &Client<T, Body>, req: Request<Body>) -> Result<R>
+where
+    T: hyper::client::connect::Connect + 'static,
+    R: DeserializeOwned,
+{
+    let body = await!(send(client, req))?;
+    serde_json::from_slice::<R>(&body).map_err(From::from)
+}
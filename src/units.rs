@@ -0,0 +1,395 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsers for the duration and byte-size strings Prometheus uses in
+//! flags, configuration and PromQL, e.g. `1h30m` and `512MB`.
+
+use std::fmt::{self, Display, Formatter};
+use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Error, Result};
+
+/// Duration units in the order they must appear, with their length in milliseconds.
+const DURATION_UNITS: &[(&str, u64)] = &[
+    ("y", 365 * 24 * 60 * 60 * 1000),
+    ("w", 7 * 24 * 60 * 60 * 1000),
+    ("d", 24 * 60 * 60 * 1000),
+    ("h", 60 * 60 * 1000),
+    ("m", 60 * 1000),
+    ("s", 1000),
+    ("ms", 1),
+];
+
+/// Byte-size units. As in Prometheus, `KB` and `KiB` are both 1024 bytes.
+const BYTE_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("KB", 1 << 10),
+    ("KiB", 1 << 10),
+    ("MB", 1 << 20),
+    ("MiB", 1 << 20),
+    ("GB", 1 << 30),
+    ("GiB", 1 << 30),
+    ("TB", 1 << 40),
+    ("TiB", 1 << 40),
+    ("PB", 1 << 50),
+    ("PiB", 1 << 50),
+    ("EB", 1 << 60),
+    ("EiB", 1 << 60),
+];
+
+/// Split `s` into `(number, unit)` pairs, e.g. `"1h30m"` into `[("1", "h"), ("30", "m")]`.
+fn split_units(s: &str) -> Option<Vec<(&str, &str)>> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let unit_len = rest[number_len..]
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len() - number_len);
+        if number_len == 0 || unit_len == 0 {
+            return None;
+        }
+        parts.push((
+            &rest[..number_len],
+            &rest[number_len..number_len + unit_len],
+        ));
+        rest = &rest[number_len + unit_len..];
+    }
+    Some(parts)
+}
+
+/// Parse a Prometheus duration such as `1h30m`, `5m` or `250ms`.
+///
+/// Units are `y`, `w`, `d`, `h`, `m`, `s` and `ms`, each used at most
+/// once and in that order. A year is always 365 days. `0` is accepted
+/// without a unit.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    if s == "0" {
+        return Ok(Duration::from_millis(0));
+    }
+
+    let invalid = || Error::new_invalid_duration_error(s);
+    let parts = split_units(s)
+        .filter(|p| !p.is_empty())
+        .ok_or_else(invalid)?;
+
+    let mut millis: u64 = 0;
+    let mut next_unit = 0;
+    for (number, unit) in parts {
+        let i = DURATION_UNITS[next_unit..]
+            .iter()
+            .position(|(u, _)| *u == unit)
+            .ok_or_else(invalid)?
+            + next_unit;
+        next_unit = i + 1;
+
+        let n: u64 = number.parse().map_err(|_| invalid())?;
+        millis = n
+            .checked_mul(DURATION_UNITS[i].1)
+            .and_then(|ms| millis.checked_add(ms))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(Duration::from_millis(millis))
+}
+
+/// Whole milliseconds in `d`, saturating at `u64::MAX`.
+fn saturating_millis(d: Duration) -> u64 {
+    d.as_secs()
+        .saturating_mul(1000)
+        .saturating_add(u64::from(d.subsec_millis()))
+}
+
+/// Format `d` as a Prometheus duration, e.g. `1h30m`, truncated to milliseconds.
+///
+/// Like Prometheus, years and weeks are only used when they divide the
+/// duration exactly, since `90d` is easier to read than `12w6d`.
+pub fn format_duration(d: Duration) -> String {
+    let mut millis = saturating_millis(d);
+    if millis == 0 {
+        return "0s".to_owned();
+    }
+
+    let mut s = String::new();
+    for (unit, unit_millis) in DURATION_UNITS {
+        let exact_only = *unit == "y" || *unit == "w";
+        if exact_only && millis % unit_millis != 0 {
+            continue;
+        }
+        let n = millis / unit_millis;
+        if n > 0 {
+            s.push_str(&n.to_string());
+            s.push_str(unit);
+            millis -= n * unit_millis;
+        }
+    }
+    s
+}
+
+/// Parse a Prometheus byte size such as `512MB`, `1.5GiB` or `1GB512MB`.
+pub fn parse_byte_size(s: &str) -> Result<u64> {
+    let invalid = || Error::new_invalid_byte_size_error(s);
+    let parts = split_units(s)
+        .filter(|p| !p.is_empty())
+        .ok_or_else(invalid)?;
+
+    let mut bytes: f64 = 0.0;
+    for (number, unit) in parts {
+        let (_, multiplier) = BYTE_UNITS
+            .iter()
+            .find(|(u, _)| *u == unit)
+            .ok_or_else(invalid)?;
+        let n: f64 = number.parse().map_err(|_| invalid())?;
+        bytes += n * *multiplier as f64;
+    }
+
+    if bytes > std::u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// A duration as Prometheus writes it, e.g. `1h30m` or `250ms`, kept to
+/// millisecond precision.
+///
+/// Parses either a Prometheus duration or, as the HTTP API also accepts,
+/// a number of seconds such as `15` or `0.5`. Displays and serializes as a
+/// Prometheus duration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PromDuration(Duration);
+
+impl PromDuration {
+    pub fn from_millis(millis: u64) -> PromDuration {
+        PromDuration(Duration::from_millis(millis))
+    }
+
+    pub fn as_duration(self) -> Duration {
+        self.0
+    }
+
+    pub fn as_millis(self) -> u64 {
+        saturating_millis(self.0)
+    }
+
+    /// Fractional seconds, e.g. `1.5` for `1s500ms`.
+    pub fn as_secs_f64(self) -> f64 {
+        self.as_millis() as f64 / 1000.0
+    }
+}
+
+impl From<Duration> for PromDuration {
+    /// Truncates `d` to milliseconds, saturating at `u64::MAX` of them.
+    fn from(d: Duration) -> PromDuration {
+        PromDuration(Duration::from_millis(saturating_millis(d)))
+    }
+}
+
+impl From<PromDuration> for Duration {
+    fn from(d: PromDuration) -> Duration {
+        d.0
+    }
+}
+
+impl FromStr for PromDuration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PromDuration> {
+        match s.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => {
+                let millis = (secs * 1000.0).round();
+                if millis > std::u64::MAX as f64 {
+                    return Err(Error::new_invalid_duration_error(s));
+                }
+                Ok(PromDuration::from_millis(millis as u64))
+            }
+            Ok(_) => Err(Error::new_invalid_duration_error(s)),
+            Err(_) => parse_duration(s).map(PromDuration),
+        }
+    }
+}
+
+impl Display for PromDuration {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&format_duration(self.0))
+    }
+}
+
+impl Serialize for PromDuration {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PromDuration {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VisitorImpl;
+
+        impl<'de> Visitor<'de> for VisitorImpl {
+            type Value = PromDuration;
+
+            fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+                formatter.write_str("Prometheus duration or number of seconds")
+            }
+
+            fn visit_str<E>(self, v: &str) -> StdResult<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                v.parse()
+                    .map_err(|_| de::Error::invalid_value(Unexpected::Str(v), &self))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> StdResult<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                v.checked_mul(1000)
+                    .map(PromDuration::from_millis)
+                    .ok_or_else(|| de::Error::invalid_value(Unexpected::Unsigned(v), &self))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> StdResult<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                if v < 0 {
+                    return Err(de::Error::invalid_value(Unexpected::Signed(v), &self));
+                }
+                self.visit_u64(v as u64)
+            }
+
+            fn visit_f64<E>(self, v: f64) -> StdResult<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                v.to_string()
+                    .parse()
+                    .map_err(|_| de::Error::invalid_value(Unexpected::Float(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(VisitorImpl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::error::ErrorKind;
+    use crate::units::{format_duration, parse_byte_size, parse_duration, PromDuration};
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(Duration::from_secs(5400), parse_duration("1h30m").unwrap());
+        assert_eq!(Duration::from_millis(250), parse_duration("250ms").unwrap());
+        assert_eq!(Duration::from_secs(0), parse_duration("0").unwrap());
+        assert_eq!(
+            Duration::from_millis(
+                365 * 86_400_000
+                    + 2 * 7 * 86_400_000
+                    + 3 * 86_400_000
+                    + 4 * 3_600_000
+                    + 5 * 60_000
+                    + 6_000
+                    + 7
+            ),
+            parse_duration("1y2w3d4h5m6s7ms").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_format_durations() {
+        assert_eq!("0s", format_duration(Duration::from_secs(0)));
+        assert_eq!("1h30m", format_duration(Duration::from_secs(5400)));
+        assert_eq!("1s500ms", format_duration(Duration::from_millis(1500)));
+        assert_eq!("2w", format_duration(Duration::from_secs(14 * 86400)));
+        assert_eq!("90d", format_duration(Duration::from_secs(90 * 86400)));
+        assert_eq!("1y", format_duration(parse_duration("1y").unwrap()));
+    }
+
+    #[test]
+    fn should_saturate_durations_too_long_for_milliseconds() {
+        let huge = Duration::from_secs(std::u64::MAX);
+        assert_eq!(std::u64::MAX, PromDuration::from(huge).as_millis());
+        assert_eq!(
+            format_duration(Duration::from_millis(std::u64::MAX)),
+            format_duration(huge)
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_durations() {
+        for s in &["", "5", "1.5h", "30m1h", "1m1m", "1x", "m", "-1s"] {
+            match parse_duration(s).unwrap_err().kind() {
+                ErrorKind::InvalidDuration { duration } => assert_eq!(s, duration),
+                k => panic!("unexpected error {:?}", k),
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_byte_sizes() {
+        assert_eq!(512 * 1024 * 1024, parse_byte_size("512MB").unwrap());
+        assert_eq!(512 * 1024 * 1024, parse_byte_size("512MiB").unwrap());
+        assert_eq!(0, parse_byte_size("0B").unwrap());
+        assert_eq!(3 * (1 << 29), parse_byte_size("1.5GB").unwrap());
+        assert_eq!((1 << 30) + (1 << 29), parse_byte_size("1GB512MB").unwrap());
+        assert!(parse_byte_size("512").is_err());
+        assert!(parse_byte_size("512mb").is_err());
+    }
+
+    #[test]
+    fn should_convert_prom_durations() {
+        // like Prometheus, years and weeks are only displayed when exact
+        let d: PromDuration = "1y2w3d4h5m6s7ms".parse().unwrap();
+        assert_eq!("382d4h5m6s7ms", d.to_string());
+        assert_eq!(d, d.to_string().parse().unwrap());
+
+        let d: PromDuration = "1.5".parse().unwrap();
+        assert_eq!(Duration::from_millis(1500), Duration::from(d));
+        assert_eq!(1.5, d.as_secs_f64());
+        assert_eq!("1s500ms", d.to_string());
+        assert!("-1".parse::<PromDuration>().is_err());
+        assert!("5 minutes".parse::<PromDuration>().is_err());
+
+        // sub-millisecond precision is truncated
+        let d = PromDuration::from(Duration::new(90, 250_999_999));
+        assert_eq!("1m30s250ms", d.to_string());
+
+        assert_eq!("\"1m30s250ms\"", serde_json::to_string(&d).unwrap());
+        assert_eq!(d, serde_json::from_str("\"1m30s250ms\"").unwrap());
+        assert_eq!(
+            PromDuration::from_millis(15_000),
+            serde_json::from_str("15").unwrap()
+        );
+        assert_eq!(
+            PromDuration::from_millis(500),
+            serde_json::from_str("0.5").unwrap()
+        );
+    }
+}
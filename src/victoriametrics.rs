@@ -0,0 +1,353 @@
+// Copyright 2019 Allen A. George
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [VictoriaMetrics](https://docs.victoriametrics.com/) export and import APIs.
+//!
+//! VictoriaMetrics serves the Prometheus query API, so a `PromClient` can
+//! talk to it directly. This module adds the VictoriaMetrics-only
+//! `/api/v1/export`, `/api/v1/export/native` and `/api/v1/import` endpoints.
+//! Enabled with the `victoriametrics` feature.
+
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use http::header::CONTENT_TYPE;
+use http::Uri;
+use hyper::{Body, Request};
+use serde::{
+    de,
+    de::{Unexpected, Visitor},
+    {Deserialize, Deserializer, Serialize, Serializer},
+};
+use serde_json;
+
+use crate::messages::{
+    millis_to_epoch, non_finite_value, Metric, Range, Sample, PROM_INFINITY, PROM_NAN,
+    PROM_NEGATIVE_INFINITY, PROM_PLUS_INFINITY,
+};
+use crate::streaming::{self, ChunkDecoder, ChunkStream, RangeStream};
+use crate::transport;
+use crate::{PromClient, Result};
+
+impl<T: hyper::client::connect::Connect + 'static> PromClient<T> {
+    /// Export raw samples for all series matching `selectors` from `/api/v1/export`.
+    ///
+    /// The response is JSON lines with one series per line. Series are
+    /// yielded as each line arrives, so the full export is never buffered.
+    pub async fn export(
+        &mut self,
+        selectors: Vec<String>,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<RangeStream> {
+        let get = self.export_request("/api/v1/export", selectors, start, end)?;
+        let resp = await!(transport::send_streaming(self.http_client(), get))?;
+        Ok(streaming::decode_body(
+            resp.into_body(),
+            JsonLinesDecoder::default(),
+        ))
+    }
+
+    /// Export series in the VictoriaMetrics native binary format from `/api/v1/export/native`.
+    /// The body is passed through untouched, e.g. to be written to a file
+    /// and later imported into another VictoriaMetrics instance.
+    pub async fn export_native(
+        &mut self,
+        selectors: Vec<String>,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<ChunkStream> {
+        let get = self.export_request("/api/v1/export/native", selectors, start, end)?;
+        let resp = await!(transport::send_streaming(self.http_client(), get))?;
+        Ok(streaming::chunks(resp.into_body()))
+    }
+
+    /// Import series via `/api/v1/import`, in the same JSON lines format
+    /// produced by [export](PromClient::export).
+    pub async fn import(&mut self, series: Vec<Range>) -> Result<()> {
+        let u = self.api_call_base_url("/api/v1/import");
+        let u = Uri::from_str(u.as_str())?;
+
+        let mut body = Vec::new();
+        for range in &series {
+            serde_json::to_writer(&mut body, &ExportedSeriesRef::from(range))?;
+            body.push(b'\n');
+        }
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        let post = Request::post(u)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("Failed to construct 'import' POST with JSON lines body");
+
+        await!(transport::send(self.http_client(), post))?;
+        Ok(())
+    }
+
+    fn export_request(
+        &self,
+        api_path: &str,
+        selectors: Vec<String>,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Result<Request<Body>> {
+        let mut u = self.api_call_base_url(api_path);
+        for s in selectors {
+            u.query_pairs_mut().append_pair("match[]", &s);
+        }
+        if let Some(start) = start {
+            u.query_pairs_mut()
+                .append_pair("start", &start.to_rfc3339());
+        }
+        if let Some(end) = end {
+            u.query_pairs_mut().append_pair("end", &end.to_rfc3339());
+        }
+        let u = Uri::from_str(u.as_str())?;
+
+        // Explicitly unwrapping here because this shouldn't fail,
+        // and there's nothing a user can do if it does. this failure
+        // is because of a library bug, not because of their input
+        Ok(Request::get(u)
+            .body(Body::empty())
+            .expect("Failed to construct 'export' GET with empty body"))
+    }
+}
+
+/// Splits a body into lines and decodes each one as an exported series.
+#[derive(Debug, Default)]
+struct JsonLinesDecoder {
+    buf: Vec<u8>,
+    // bytes of `buf` already known not to contain a newline
+    scanned: usize,
+}
+
+impl ChunkDecoder for JsonLinesDecoder {
+    type Item = Range;
+
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<Range>> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut series = Vec::new();
+        let mut line_start = 0;
+        for i in self.scanned..self.buf.len() {
+            if self.buf[i] == b'\n' {
+                if let Some(range) = decode_line(&self.buf[line_start..i])? {
+                    series.push(range);
+                }
+                line_start = i + 1;
+            }
+        }
+        self.buf.drain(..line_start);
+        self.scanned = self.buf.len();
+
+        Ok(series)
+    }
+
+    fn finish(&mut self) -> Result<Vec<Range>> {
+        let line = std::mem::replace(&mut self.buf, Vec::new());
+        self.scanned = 0;
+        Ok(decode_line(&line)?.into_iter().collect())
+    }
+}
+
+fn decode_line(line: &[u8]) -> Result<Option<Range>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    let series = serde_json::from_slice::<ExportedSeries>(line)?;
+    if series.values.len() != series.timestamps.len() {
+        return Err(From::from(<serde_json::Error as de::Error>::invalid_length(
+            series.values.len(),
+            &"as many values as timestamps",
+        )));
+    }
+    Ok(Some(series.into()))
+}
+
+/// A single line of `/api/v1/export` output.
+#[derive(Deserialize)]
+struct ExportedSeries {
+    metric: Metric,
+    values: Vec<ExportedValue>,
+    timestamps: Vec<i64>,
+}
+
+impl From<ExportedSeries> for Range {
+    fn from(series: ExportedSeries) -> Self {
+        let samples = series
+            .timestamps
+            .into_iter()
+            .zip(series.values)
+            .map(|(ts, ExportedValue(value))| Sample {
+                epoch: millis_to_epoch(ts),
+                value,
+            })
+            .collect();
+        Range {
+            metric: series.metric,
+            samples,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportedSeriesRef<'a> {
+    metric: &'a Metric,
+    values: Vec<ExportedValue>,
+    timestamps: Vec<i64>,
+}
+
+impl<'a> From<&'a Range> for ExportedSeriesRef<'a> {
+    fn from(range: &'a Range) -> Self {
+        ExportedSeriesRef {
+            metric: &range.metric,
+            values: range
+                .samples
+                .iter()
+                .map(|s| ExportedValue(s.value))
+                .collect(),
+            timestamps: range.samples.iter().map(Sample::timestamp_millis).collect(),
+        }
+    }
+}
+
+/// Exported values are JSON numbers. Non-finite values have no JSON
+/// number representation, so they are written and accepted as the
+/// Prometheus string constants, and `null` is read as `NaN`.
+struct ExportedValue(f64);
+
+impl<'de> Deserialize<'de> for ExportedValue {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VisitorImpl;
+
+        impl<'de> Visitor<'de> for VisitorImpl {
+            type Value = ExportedValue;
+
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("VictoriaMetrics sample value")
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> StdResult<Self::Value, E> {
+                Ok(ExportedValue(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> StdResult<Self::Value, E> {
+                Ok(ExportedValue(v as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> StdResult<Self::Value, E> {
+                Ok(ExportedValue(v as f64))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> StdResult<Self::Value, E> {
+                Ok(ExportedValue(std::f64::NAN))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> StdResult<Self::Value, E> {
+                match v {
+                    PROM_INFINITY | PROM_PLUS_INFINITY => Ok(ExportedValue(std::f64::INFINITY)),
+                    PROM_NEGATIVE_INFINITY => Ok(ExportedValue(std::f64::NEG_INFINITY)),
+                    PROM_NAN => Ok(ExportedValue(std::f64::NAN)),
+                    _ => Err(de::Error::invalid_value(Unexpected::Str(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(VisitorImpl)
+    }
+}
+
+impl Serialize for ExportedValue {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match non_finite_value(self.0) {
+            Some(s) => serializer.serialize_str(s),
+            None => serializer.serialize_f64(self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::range;
+    use crate::streaming::ChunkDecoder;
+    use crate::victoriametrics::{ExportedSeriesRef, JsonLinesDecoder};
+
+    #[test]
+    fn should_decode_export_lines_split_across_chunks() {
+        let body = concat!(
+            r#"{"metric":{"__name__":"up","instance":"a"},"values":[1,0.5],"timestamps":[1549891472010,1549891487724]}"#,
+            "\n",
+            r#"{"metric":{"__name__":"up","instance":"b"},"values":[null],"timestamps":[1549891472010]}"#,
+        );
+        let (first, second) = body.as_bytes().split_at(60);
+
+        let mut decoder = JsonLinesDecoder::default();
+        assert!(decoder.decode(first).unwrap().is_empty());
+        assert_eq!(
+            vec![range(
+                &[("__name__", "up"), ("instance", "a")],
+                &[(1549891472.010, 1.0), (1549891487.724, 0.5)],
+            )],
+            decoder.decode(second).unwrap()
+        );
+
+        let last = decoder.finish().unwrap();
+        assert_eq!(1, last.len());
+        assert_eq!(1549891472.010, last[0].samples[0].epoch);
+        assert!(last[0].samples[0].value.is_nan());
+    }
+
+    #[test]
+    fn should_fail_on_truncated_export() {
+        let mut decoder = JsonLinesDecoder::default();
+        assert!(decoder.decode(br#"{"metric":{},"values":[1"#).is_ok());
+        assert!(decoder.finish().is_err());
+    }
+
+    #[test]
+    fn should_serialize_import_lines() {
+        let r = range(
+            &[("__name__", "up"), ("instance", "a")],
+            &[
+                (1549891472.01, 1.0),
+                (1549891487.724, std::f64::INFINITY),
+                (1549891502.0, std::f64::NEG_INFINITY),
+            ],
+        );
+        let j = serde_json::to_string(&ExportedSeriesRef::from(&r)).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "metric": { "__name__": "up", "instance": "a" },
+                "values": [1.0, "Inf", "-Inf"],
+                "timestamps": [1549891472010i64, 1549891487724i64, 1549891502000i64]
+            }),
+            serde_json::from_str::<serde_json::Value>(&j).unwrap()
+        );
+
+        // and decode back unchanged
+        let mut decoder = JsonLinesDecoder::default();
+        assert_eq!(vec![r], decoder.decode(format!("{}\n", j).as_bytes()).unwrap());
+    }
+}